@@ -0,0 +1,14 @@
+use quick_perf_event::QuickPerfEvent;
+use std::hint::black_box;
+
+fn sum(perf: &mut QuickPerfEvent<str>) {
+    perf.run(|| (0..1_000_000u64).map(black_box).sum::<u64>())
+        .record(1_000_000, "sum");
+}
+
+fn max(perf: &mut QuickPerfEvent<str>) {
+    perf.run(|| (0..1_000_000u64).map(black_box).max())
+        .record(1_000_000, "max");
+}
+
+quick_perf_event::main!(sum, max);
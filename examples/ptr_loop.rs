@@ -10,7 +10,7 @@ use std::mem;
 
 struct_labels! {
     struct Labels{
-        size:String,
+        size:TabledFloat,
     }
 }
 
@@ -33,7 +33,7 @@ fn walk_ptr_loop(steps: usize, size: usize, qpe: &mut QuickPerfEvent<Labels>) {
     .record(
         steps,
         Labels {
-            size: TabledFloat(size as f64).to_string(),
+            size: TabledFloat(size as f64),
         },
     )
 }
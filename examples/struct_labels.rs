@@ -3,6 +3,8 @@ use std::env;
 
 struct_labels! {
     struct Labels {
+        #[width = 20]
+        #[overflow = TruncateStart]
         dataset: String,
         operation: &'static str,
     }
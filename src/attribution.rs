@@ -0,0 +1,184 @@
+use crate::{
+    config::Config,
+    labels::LabelMeta,
+    perf_sys::*,
+    symbols::{Location, Symbolizer},
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Attributes events such as branch or cache misses to source locations.
+///
+/// While a measurement is running, the instruction pointer is sampled every `period` events.
+/// Samples are grouped by the labels of the recorded [`Reading`](crate::Reading).
+/// When the [`QuickPerfEvent`](crate::QuickPerfEvent) is dropped, a markdown report listing the locations responsible for the most samples of each label is written to a file.
+///
+/// Locations are resolved to functions using the symbol table of the executable.
+/// With the `symbolize` feature, they are resolved to source lines using debug info, so you will usually want to build with `debug = true` in your profile.
+/// Due to skid, samples may be attributed to an instruction shortly after the one causing the event.
+/// Only the thread starting the measurement is sampled.
+/// Samples are buffered until the measurement is stopped, if the buffer fills up, further samples are lost and the report includes a warning.
+pub struct Attribution {
+    event: String,
+    sampler: Sampler,
+    path: PathBuf,
+    top: usize,
+    /// Addresses sampled since the last record.
+    pending: Vec<u64>,
+    lost: u64,
+    /// Sampled addresses by label.
+    labels: Vec<(String, HashMap<u64, usize>)>,
+}
+
+impl Attribution {
+    /// Creates an attribution report from the environment variable `QPE_ATTRIBUTION`.
+    ///
+    /// The variable contains the event to sample, see [`new`](Self::new).
+    /// The report is written to `QPE_ATTRIBUTION_FILE`, which defaults to `qpe-attribution.md`.
+    /// Returns `None` if `QPE_ATTRIBUTION` is not set or the event cannot be sampled.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let event = config.get("ATTRIBUTION")?;
+        let path = config.get("ATTRIBUTION_FILE");
+        Self::new(&event, path.as_deref().unwrap_or("qpe-attribution.md"))
+    }
+
+    /// Creates an attribution report for an event, written to `path`.
+    ///
+    /// Supported events are `br-miss`, `llc-miss`, `l1-miss`, `cycle`, and `t-clock`, as defined for [`PerfBackend`](crate::counters::PerfBackend).
+    /// Returns `None` with a warning message to stderr if the event cannot be sampled.
+    pub fn new(event: &str, path: impl AsRef<Path>) -> Option<Self> {
+        let Some((kind, config, period)) = sampled_event(event) else {
+            eprintln!("invalid attribution event: {event:?}");
+            return None;
+        };
+        let attr = PerfEventAttr {
+            kind,
+            config,
+            sample_period: period,
+            flags: FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+        let sampler = Sampler::new(attr, 8)
+            .map_err(|e| eprintln!("failed to sample {event:?}: {e}"))
+            .ok()?;
+        Some(Attribution {
+            event: event.to_string(),
+            sampler,
+            path: path.as_ref().to_path_buf(),
+            top: 10,
+            pending: Vec::new(),
+            lost: 0,
+            labels: Vec::new(),
+        })
+    }
+
+    /// Set the number of locations listed per label, the default is 10.
+    pub fn with_top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Set the number of events per sample.
+    ///
+    /// The default is 10000 for cache and branch misses, 1000000 for cycles, and 1ms for `t-clock`.
+    pub fn with_period(mut self, period: u64) -> Self {
+        self.sampler.set_period(period);
+        self
+    }
+
+    pub(crate) fn enable(&mut self) {
+        ioctl(self.sampler.file(), PERF_EVENT_IOC_ENABLE);
+    }
+
+    pub(crate) fn disable(&mut self) {
+        ioctl(self.sampler.file(), PERF_EVENT_IOC_DISABLE);
+        self.lost += self.sampler.drain(&mut self.pending);
+    }
+
+    /// Attributes the samples taken since the last record to a label.
+    pub(crate) fn record(&mut self, labels: String) {
+        let counts = match self.labels.iter().position(|x| x.0 == labels) {
+            Some(i) => &mut self.labels[i].1,
+            None => {
+                self.labels.push((labels, HashMap::new()));
+                &mut self.labels.last_mut().unwrap().1
+            }
+        };
+        for address in self.pending.drain(..) {
+            *counts.entry(address).or_default() += 1;
+        }
+    }
+
+    /// Writes the report and discards all samples.
+    pub(crate) fn dump_and_reset(&mut self, label_meta: &'static [LabelMeta]) {
+        if self.labels.is_empty() {
+            return;
+        }
+        let mut symbolizer = Symbolizer::new()
+            .map_err(|e| eprintln!("failed to load symbols: {e}"))
+            .ok();
+        let mut report = format!("# {} attribution\n", self.event);
+        if self.lost > 0 {
+            writeln!(report, "\n⚠️ {} samples were lost.", self.lost).unwrap();
+        }
+        for (labels, counts) in self.labels.drain(..) {
+            let total: usize = counts.values().sum();
+            let mut locations = HashMap::<String, usize>::new();
+            for (address, count) in counts {
+                let location = match symbolizer.as_mut().and_then(|x| x.locate(address)) {
+                    Some(location) => format_location(location),
+                    None => format!("`{address:#x}`"),
+                };
+                *locations.entry(location).or_default() += count;
+            }
+            let mut locations: Vec<_> = locations.into_iter().collect();
+            locations.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            let heading = if label_meta.is_empty() {
+                "all samples"
+            } else {
+                &labels
+            };
+            writeln!(report, "\n## {heading}\n\n{total} samples\n").unwrap();
+            report.push_str("| samples | share | location |\n|---|---|---|\n");
+            for (location, count) in locations.iter().take(self.top) {
+                let share = *count as f64 / total as f64 * 100.0;
+                writeln!(report, "| {count} | {share:.1}% | {location} |").unwrap();
+            }
+        }
+        self.lost = 0;
+        match fs::write(&self.path, report) {
+            Ok(()) => eprintln!("attribution report written to {}", self.path.display()),
+            Err(e) => eprintln!("failed to write {}: {e}", self.path.display()),
+        }
+    }
+}
+
+/// Returns the type, config, and default period of an event supported for sampling.
+pub(crate) fn sampled_event(event: &str) -> Option<(u32, u64, u64)> {
+    match event {
+        "br-miss" => Some((PERF_TYPE_HARDWARE, 5, 10_000)),
+        "llc-miss" => Some((PERF_TYPE_HARDWARE, 3, 10_000)),
+        "l1-miss" => Some((PERF_TYPE_HW_CACHE, 1 << 16, 10_000)),
+        "cycle" => Some((PERF_TYPE_HARDWARE, 0, 1_000_000)),
+        // nanoseconds
+        "t-clock" => Some((PERF_TYPE_SOFTWARE, 1, 1_000_000)),
+        _ => None,
+    }
+}
+
+fn format_location(location: &Location) -> String {
+    let function = location.function.as_deref().unwrap_or("??");
+    match (&location.file, location.line) {
+        (Some(file), Some(line)) => format!("`{function}` {file}:{line}"),
+        (Some(file), None) => format!("`{function}` {file}"),
+        _ => format!("`{function}`"),
+    }
+}
@@ -0,0 +1,237 @@
+use std::{collections::HashMap, fs, io, path::Path, str::FromStr};
+
+/// A source of configuration settings for [`from_config`](crate::QuickPerfEvent::from_config).
+///
+/// Settings are named like the environment variables documented in the crate docs, without the `QPE_` prefix, e.g. `EVENTS`.
+/// Names are case insensitive.
+/// Settings given explicitly using [`with_value`](Self::with_value) or loaded from a file take precedence.
+/// Other settings are read from environment variables, if a prefix is configured.
+///
+/// This allows multiple independent [`QuickPerfEvent`](crate::QuickPerfEvent) instances in one process, e.g. embedded by different libraries, each with their own counters and format.
+/// Harnesses may also use it to load, adjust, and log the effective configuration, see [`settings`](Self::settings).
+#[derive(Clone, Debug)]
+pub struct Config {
+    prefix: Option<String>,
+    values: HashMap<String, String>,
+}
+
+impl Default for Config {
+    /// Reads settings from environment variables with the prefix `QPE_`.
+    fn default() -> Self {
+        Self::env_with_prefix("QPE_")
+    }
+}
+
+impl Config {
+    /// Reads settings from environment variables with the prefix `QPE_`.
+    ///
+    /// This is the configuration used by [`from_env`](crate::from_env).
+    pub fn from_env() -> Self {
+        Self::default()
+    }
+
+    /// Loads settings from the lines before the first section of a configuration file, ignoring environment variables.
+    ///
+    /// Keys are the setting names, e.g. `events = "cycle,instr"` or `repeat = 10`.
+    /// The file format is the `key = "value"` subset of TOML described for [`with_file`](Self::with_file).
+    /// Sections are skipped without being parsed, so the settings may be embedded at the top of a larger TOML file.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut config = Self::empty();
+        for (name, value) in parse_section(&text, None)? {
+            config.set(&name, value);
+        }
+        Ok(config)
+    }
+
+    /// A configuration without any settings.
+    ///
+    /// Environment variables are ignored.
+    pub fn empty() -> Self {
+        Config {
+            prefix: None,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Reads settings from environment variables named by `prefix` followed by the setting name.
+    pub fn env_with_prefix(prefix: impl Into<String>) -> Self {
+        Config {
+            prefix: Some(prefix.into()),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Set a setting, e.g. `with_value("EVENTS", "cycle,instr")`.
+    pub fn with_value(mut self, name: &str, value: &str) -> Self {
+        self.set(name, value.to_string());
+        self
+    }
+
+    fn set(&mut self, name: &str, value: String) {
+        self.values.insert(name.to_uppercase(), value);
+    }
+
+    /// Load the settings of a section from a configuration file.
+    ///
+    /// The file consists of sections started by a `[name]` line, containing `NAME = value` lines.
+    /// This is a subset of TOML: values may be enclosed in double or single quotes, or left unquoted.
+    /// Escape sequences, multi-line strings, arrays, inline tables, and dotted or quoted keys are not supported and return an error.
+    /// A `#` outside of quotes starts a comment.
+    pub fn with_file(mut self, path: impl AsRef<Path>, section: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        for (name, value) in parse_section(&text, Some(section))? {
+            self.set(&name, value);
+        }
+        Ok(self)
+    }
+
+    /// Returns the full name of a setting for use in messages, e.g. `QPE_EVENTS` for `EVENTS`.
+    pub(crate) fn name(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) if !self.values.contains_key(&name.to_uppercase()) => {
+                format!("{prefix}{name}")
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// Returns the value of a setting, e.g. `get("EVENTS")`.
+    pub fn get(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.values.get(&name.to_uppercase()) {
+            return Some(value.clone());
+        }
+        std::env::var(format!("{}{name}", self.prefix.as_ref()?)).ok()
+    }
+
+    /// Returns true if the setting is set to anything other than an empty string, `0`, or `false`.
+    pub(crate) fn flag(&self, name: &str) -> bool {
+        self.get(name)
+            .is_some_and(|x| !matches!(x.as_str(), "" | "0" | "false"))
+    }
+
+    /// Returns all settings in effect, sorted by name.
+    ///
+    /// This includes environment variables with the configured prefix, even if they are not recognized by this crate.
+    pub fn settings(&self) -> Vec<(String, String)> {
+        let mut settings = self.values.clone();
+        if let Some(prefix) = &self.prefix {
+            for (name, value) in std::env::vars() {
+                if let Some(name) = name.strip_prefix(prefix.as_str()) {
+                    settings.entry(name.to_string()).or_insert(value);
+                }
+            }
+        }
+        let mut settings: Vec<_> = settings.into_iter().collect();
+        settings.sort();
+        settings
+    }
+
+    /// Parses a setting, printing a warning if it is set but cannot be parsed.
+    pub(crate) fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        let x = self.get(name)?;
+        x.parse()
+            .map_err(|_| eprintln!("failed to parse {}: {x:?}", self.name(name)))
+            .ok()
+    }
+}
+
+/// Parses the `NAME = value` lines of a section, or of the lines before the first section if `section` is `None`.
+///
+/// Lines of other sections are not validated.
+fn parse_section(text: &str, section: Option<&str>) -> io::Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    let mut current = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            current = Some(name.trim());
+            continue;
+        }
+        // other sections may use TOML syntax not supported here, e.g. multi-line arrays
+        if current != section {
+            continue;
+        }
+        let error = |msg: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {msg}", i + 1))
+        };
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(error("expected NAME = value"));
+        };
+        let name = name.trim();
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(error("only bare keys are supported"));
+        }
+        let value = value.trim_start();
+        if value.starts_with("\"\"\"") || value.starts_with("'''") {
+            return Err(error("multi-line strings are not supported"));
+        }
+        let value = match value.chars().next() {
+            Some('[' | '{') => return Err(error("arrays and inline tables are not supported")),
+            Some(quote @ ('"' | '\'')) => {
+                let (value, rest) = value[1..]
+                    .split_once(quote)
+                    .ok_or_else(|| error("unterminated string"))?;
+                if quote == '"' && value.contains('\\') {
+                    return Err(error("escape sequences are not supported"));
+                }
+                let rest = rest.trim_start();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(error("unexpected characters after string"));
+                }
+                value
+            }
+            _ => value.split_once('#').map_or(value, |x| x.0).trim_end(),
+        };
+        values.insert(name.to_string(), value.to_string());
+    }
+    Ok(values)
+}
+
+#[test]
+fn test_parse_section() {
+    let text = "# comment\n[a]\nEVENTS = cycle\n\n[b]\nEVENTS = \"instr,cycle\" # quoted\nFORMAT=md # md\nEXPR = 'a#b'\n";
+    let b = parse_section(text, Some("b")).unwrap();
+    assert_eq!(b.len(), 3);
+    assert_eq!(b["EVENTS"], "instr,cycle");
+    assert_eq!(b["FORMAT"], "md");
+    assert_eq!(b["EXPR"], "a#b");
+    assert!(parse_section("A = \"x", None).is_err());
+    assert!(parse_section("A = \"x\" y", None).is_err());
+    assert!(parse_section(text, Some("c")).unwrap().is_empty());
+    assert!(parse_section("[a]\nEVENTS", Some("a")).is_err());
+    let top = parse_section("repeat = 10\n[other]\nx = 1\n", None).unwrap();
+    assert_eq!(top.len(), 1);
+    assert_eq!(top["repeat"], "10");
+    let text = "repeat = 10\n[other]\nlist = [\n  \"a\",\n]\ndoc = \"\"\"\ntext\n\"\"\"\n";
+    assert_eq!(parse_section(text, None).unwrap().len(), 1);
+    assert_eq!(
+        parse_section("path = 'C:\\x'", None).unwrap()["path"],
+        "C:\\x"
+    );
+    for line in [
+        "events = [\"cycle\"]",
+        "env = { A = \"1\" }",
+        "doc = \"\"\"x\"\"\"",
+        "doc = '''",
+        "path = \"C:\\\\x\"",
+        "quote = \"a\\\"b\"",
+        "a.b = 1",
+        "\"a\" = 1",
+    ] {
+        assert!(parse_section(line, None).is_err(), "{line}");
+    }
+}
+
+#[test]
+fn test_case_insensitive() {
+    let config = Config::empty().with_value("events", "cycle");
+    assert_eq!(config.get("EVENTS").as_deref(), Some("cycle"));
+    assert_eq!(config.get("Events").as_deref(), Some("cycle"));
+}
@@ -1,19 +1,76 @@
-mod manual_backend;
+mod budget;
+#[cfg(target_os = "linux")]
+mod cgroup;
+#[cfg(all(target_os = "linux", feature = "perf"))]
+mod cgroup_backend;
+#[cfg(target_os = "linux")]
+mod cpu_pinning;
+#[cfg(feature = "criterion")]
+mod criterion_measurement;
+mod degradation;
+mod derived;
 #[cfg(target_os = "linux")]
+mod io_backend;
+mod lazy;
+#[cfg(target_os = "macos")]
+mod macos_backend;
+mod manual_backend;
+#[cfg(all(target_os = "linux", feature = "nvml"))]
+mod nvml_backend;
+#[cfg(all(target_os = "linux", feature = "perf"))]
 mod perf_backend;
+mod replay;
+#[cfg(unix)]
+mod rusage_backend;
+mod state;
 mod time_backend;
+#[cfg(target_os = "linux")]
+mod uprobe_backend;
 
-pub use manual_backend::ManualBackend;
+pub use budget::Budget;
+#[cfg(target_os = "linux")]
+pub use cgroup::CgroupContext;
+#[cfg(all(target_os = "linux", feature = "perf"))]
+pub use cgroup_backend::CgroupBackend;
 #[cfg(target_os = "linux")]
+pub use cpu_pinning::CpuPinning;
+#[cfg(feature = "criterion")]
+pub use criterion_measurement::CriterionMeasurement;
+pub use degradation::Degradation;
+pub use derived::Derived;
+#[cfg(target_os = "linux")]
+pub use io_backend::IoBackend;
+pub use lazy::Lazy;
+#[cfg(target_os = "macos")]
+pub use macos_backend::MacosBackend;
+pub use manual_backend::ManualBackend;
+#[cfg(all(target_os = "linux", feature = "nvml"))]
+pub use nvml_backend::NvmlBackend;
+#[cfg(all(target_os = "linux", feature = "perf"))]
 pub use perf_backend::PerfBackend;
+#[cfg(unix)]
+pub use rusage_backend::RusageBackend;
 pub use time_backend::TimeBackend;
+#[cfg(target_os = "linux")]
+pub use uprobe_backend::UprobeBackend;
+
+pub(crate) use replay::Replay;
+pub(crate) use state::CounterState;
 
-/// A `CounterBackend` is used by a [`QuickPerfEvent`] to record performance counters.
-/// Each `CounterBackend` contains a set of named performance counters.
+use crate::config::Config;
+use std::cmp::Ordering;
+
+/// A `Counters` implementation is used by a [`QuickPerfEvent`](crate::QuickPerfEvent) to record performance counters.
+/// Each `Counters` implementation contains a set of named performance counters.
 /// It supports starting, stopping, resetting, and reading counter values and names.
 ///
+/// Counters start out disabled.
+/// Calls to [`enable`](Self::enable) and [`disable`](Self::disable) must alternate, and [`reset`](Self::reset) may only be called while counters are disabled.
+/// Implementations in this crate report violations with a message naming the backend, panicking in debug builds and printing a warning once in release builds.
+/// The tuple and `Box` implementations have no state of their own, so violations are reported by the counters they contain.
+///
 /// This crate comes with various implementations.
-/// The counter backend used by a default [`QuickPerfEvent`] is [`default_counter_backend`].
+/// The counter backend used by a default [`QuickPerfEvent`](crate::QuickPerfEvent) is [`counters_from_env`].
 pub trait Counters {
     /// Enable counters.
     fn enable(&mut self);
@@ -22,11 +79,54 @@ pub trait Counters {
     /// Reset counters.
     fn reset(&mut self);
     /// Read all counters and append the readings to `dst`.
+    ///
+    /// This may be called while counters are enabled.
     fn read(&mut self, dst: &mut Vec<CounterReading>);
     /// Appends the counter names to `dst`.
     ///
-    /// Names must be appended in the same order as the values appended by [`read`].
+    /// Names must be appended in the same order as the values appended by [`read`](Self::read).
     fn names(&self, dst: &mut dyn FnMut(&str));
+    /// Read all counters and append the readings along with their counter names to `dst`.
+    ///
+    /// Unlike [`read`](Self::read), this lets callers key readings by name instead of relying on the order of [`names`](Self::names).
+    /// The default implementation pairs the results of the two and panics if their numbers differ.
+    fn read_named(&mut self, dst: &mut Vec<NamedReading>) {
+        let mut readings = Vec::new();
+        self.read(&mut readings);
+        let mut names = Vec::new();
+        self.names(&mut |x| names.push(x.to_string()));
+        assert_eq!(
+            names.len(),
+            readings.len(),
+            "counters reported {} names but {} readings",
+            names.len(),
+            readings.len()
+        );
+        dst.extend(
+            (names.into_iter().zip(readings)).map(|(name, reading)| NamedReading { name, reading }),
+        );
+    }
+    /// Appends metadata for each counter to `dst`.
+    ///
+    /// Metadata must be appended in the same order as the names appended by [`names`](Self::names).
+    /// The default implementation reports [`CounterMeta::new`] for every counter.
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.names(&mut |_| dst(CounterMeta::new()));
+    }
+    /// Recomputes the readings of columns derived from other counters, e.g. ratios.
+    ///
+    /// `readings` contains a reading of each counter in the order of [`read`](Self::read), whose base counters may have been modified, e.g. by subtracting an overhead measurement.
+    /// The default implementation does nothing, as implementations without derived columns have nothing to recompute.
+    fn recompute_derived(&self, readings: &mut [CounterReading]) {
+        let _ = readings;
+    }
+    /// The unit of the scale that counter values are normalized by, e.g. `byte`.
+    ///
+    /// Formats include it in the header of the scale column.
+    /// The default implementation returns `None`.
+    fn scale_unit(&self) -> Option<&str> {
+        None
+    }
 }
 
 impl Counters for Box<dyn Counters> {
@@ -49,6 +149,22 @@ impl Counters for Box<dyn Counters> {
     fn names(&self, dst: &mut dyn FnMut(&str)) {
         (**self).names(dst);
     }
+
+    fn read_named(&mut self, dst: &mut Vec<NamedReading>) {
+        (**self).read_named(dst);
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        (**self).meta(dst);
+    }
+
+    fn recompute_derived(&self, readings: &mut [CounterReading]) {
+        (**self).recompute_derived(readings);
+    }
+
+    fn scale_unit(&self) -> Option<&str> {
+        (**self).scale_unit()
+    }
 }
 
 impl<A: Counters, B: Counters> Counters for (A, B) {
@@ -78,35 +194,267 @@ impl<A: Counters, B: Counters> Counters for (A, B) {
         self.0.names(dst);
         self.1.names(dst);
     }
+
+    fn read_named(&mut self, dst: &mut Vec<NamedReading>) {
+        self.0.read_named(dst);
+        self.1.read_named(dst);
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.0.meta(dst);
+        self.1.meta(dst);
+    }
+
+    fn recompute_derived(&self, readings: &mut [CounterReading]) {
+        let (a, b) = readings.split_at_mut(count_counters(&self.0));
+        self.0.recompute_derived(a);
+        self.1.recompute_derived(b);
+    }
+
+    fn scale_unit(&self) -> Option<&str> {
+        self.0.scale_unit().or(self.1.scale_unit())
+    }
 }
 
-/// Construct a default [`CounterBackend`] from environment variables.
+/// Construct a default [`Counters`] implementation from environment variables.
 ///
 /// The exact set of counters it includes is subject to change.
-/// Currently, it consists of a [`TimeBackEnd`] and a default [`PerfBackEnd`].
+/// Currently, it consists of a [`TimeBackend`] and a default [`PerfBackend`], wrapped in [`Derived::from_env`].
+/// On macOS, a `MacosBackend` is used instead of the [`PerfBackend`].
+/// Where perf counters are not supported, a notice describing the [`Degradation`] is printed once.
+/// If `QPE_CGROUP` is set, a [`CgroupBackend`] is used instead of the [`PerfBackend`].
+/// If `QPE_UPROBES` is set, an [`UprobeBackend`] is included.
+/// If `QPE_BUDGET` is set, a [`Budget`] watchdog is included.
+/// If `QPE_CONTEXT` is set, a [`CgroupContext`] is included.
+/// If `QPE_CPU` is set, a [`CpuPinning`] is included.
+/// If `QPE_IO` is set, an [`IoBackend`] is included.
+/// If `QPE_RUSAGE` is set, a [`RusageBackend`] is included.
+/// On Linux, a warning is printed if the process is limited to a CPU quota.
 pub fn counters_from_env() -> Box<dyn Counters> {
-    if let Some(manual) = ManualBackend::from_env() {
+    counters_from_config(&Config::default())
+}
+
+/// Like [`counters_from_env`], but reads settings from a [`Config`].
+pub fn counters_from_config(config: &Config) -> Box<dyn Counters> {
+    let counters = base_counters_from_config(config);
+    #[cfg(target_os = "linux")]
+    let counters: Box<dyn Counters> = match UprobeBackend::from_config(config) {
+        Some(uprobes) => Box::new((counters, uprobes)),
+        None => counters,
+    };
+    #[cfg(target_os = "linux")]
+    let counters: Box<dyn Counters> = {
+        cgroup::warn_quota();
+        match CgroupContext::from_config(config) {
+            Some(context) => Box::new((counters, context)),
+            None => counters,
+        }
+    };
+    #[cfg(target_os = "linux")]
+    let counters: Box<dyn Counters> = match CpuPinning::from_config(config) {
+        Some(pinning) => Box::new((counters, pinning)),
+        None => counters,
+    };
+    #[cfg(target_os = "linux")]
+    let counters: Box<dyn Counters> = match IoBackend::from_config(config) {
+        Some(io) => Box::new((counters, io)),
+        None => counters,
+    };
+    #[cfg(unix)]
+    let counters: Box<dyn Counters> = match RusageBackend::from_config(config) {
+        Some(rusage) => Box::new((counters, rusage)),
+        None => counters,
+    };
+    #[cfg(all(target_os = "linux", feature = "nvml"))]
+    let counters: Box<dyn Counters> = match NvmlBackend::from_config(config) {
+        Some(nvml) => Box::new((counters, nvml)),
+        None => counters,
+    };
+    let counters: Box<dyn Counters> = match Budget::from_config(config) {
+        Some(budget) => Box::new((counters, budget)),
+        None => counters,
+    };
+    Box::new(Derived::from_config(counters, config))
+}
+
+fn base_counters_from_config(config: &Config) -> Box<dyn Counters> {
+    if let Some(manual) = ManualBackend::from_config(config) {
         return Box::new((manual, TimeBackend::new()));
     }
-    #[cfg(target_os = "linux")]
-    return Box::new((TimeBackend::new(), PerfBackend::new()));
-    #[cfg(not(target_os = "linux"))]
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    if let Some(cgroup) = CgroupBackend::from_config(config) {
+        return Box::new((TimeBackend::new(), cgroup));
+    }
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    return Box::new((TimeBackend::new(), PerfBackend::from_config(config)));
+    #[cfg(not(all(target_os = "linux", feature = "perf")))]
+    degradation::warn_degradation(config);
+    #[cfg(target_os = "macos")]
+    return Box::new((TimeBackend::new(), MacosBackend::from_config(config)));
+    #[cfg(not(any(all(target_os = "linux", feature = "perf"), target_os = "macos")))]
     return Box::new(TimeBackend::new());
 }
 
+const DEFAULT_EVENTS: &[&str] = &[
+    "cycle", "kcycle", "instr", "l1-miss", "llc-miss", "br-miss", "t-clock",
+];
+
+/// Applies a `QPE_EVENTS` specification to the default event list.
+pub(crate) fn resolve_event_names(spec: Option<&str>) -> Vec<&str> {
+    let Some(spec) = spec else {
+        return DEFAULT_EVENTS.to_vec();
+    };
+    let names: Vec<&str> = spec.split(",").collect();
+    if !names.iter().any(|x| x.starts_with(['+', '-'])) {
+        return names;
+    }
+    let mut events = DEFAULT_EVENTS.to_vec();
+    for name in names {
+        if let Some(name) = name.strip_prefix("-") {
+            events.retain(|x| column_name(x) != column_name(name));
+        } else {
+            let name = name.strip_prefix("+").unwrap_or(name);
+            // adding a pinned event replaces the unpinned one, e.g. `+cycle:p`
+            match (events.iter()).position(|x| column_name(x) == column_name(name)) {
+                Some(i) => events[i] = name,
+                None => events.push(name),
+            }
+        }
+    }
+    events
+}
+
+/// The modifiers following an event name, e.g. `uk` in `instr:uk`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Modifiers {
+    /// `p`, keep the counter on the PMU instead of multiplexing it.
+    pub pinned: bool,
+    /// `u`, count events in user space.
+    pub user: bool,
+    /// `k`, count events in the kernel.
+    pub kernel: bool,
+}
+
+impl Modifiers {
+    /// Returns whether the modifiers select the privilege levels counted, overriding the default of the event.
+    #[cfg_attr(not(all(target_os = "linux", feature = "perf")), allow(dead_code))]
+    pub fn privilege(&self) -> bool {
+        self.user || self.kernel
+    }
+}
+
+/// Splits an event name into the event and its modifiers, e.g. `cycle:up`.
+///
+/// Unknown modifiers are returned as an error.
+pub(crate) fn parse_modifiers(name: &str) -> (&str, Result<Modifiers, char>) {
+    let Some((event, modifiers)) = name.split_once(':') else {
+        return (name, Ok(Modifiers::default()));
+    };
+    let mut result = Modifiers::default();
+    for modifier in modifiers.chars() {
+        match modifier {
+            'p' => result.pinned = true,
+            'u' => result.user = true,
+            'k' => result.kernel = true,
+            _ => return (event, Err(modifier)),
+        }
+    }
+    (event, Ok(result))
+}
+
+/// Returns the column name of an event, omitting the `p` modifier since it does not change what is counted.
+pub(crate) fn column_name(name: &str) -> String {
+    let Some((event, modifiers)) = name.split_once(':') else {
+        return name.to_string();
+    };
+    let modifiers = modifiers.replace('p', "");
+    if modifiers.is_empty() {
+        event.to_string()
+    } else {
+        format!("{event}:{modifiers}")
+    }
+}
+
+/// The metadata of an event supported by `QPE_EVENTS`.
+#[cfg_attr(
+    not(any(all(target_os = "linux", feature = "perf"), target_os = "macos")),
+    allow(dead_code)
+)]
+pub(crate) fn event_meta(name: &str) -> CounterMeta {
+    match parse_modifiers(name).0 {
+        "t-clock" => CounterMeta::new().with_unit("s"),
+        _ => CounterMeta::new(),
+    }
+}
+
 /// A reading of a performance counter.
+///
+/// Fields may be added in the future, so readings are constructed using [`new`](Self::new) and then adjusted.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
 pub struct CounterReading {
     /// The value to report to the user
     pub value: f64,
     /// if `true`, the reading was multiplexed and may therefore be less reliable.
     /// Setting this causes some output formats to include a warning.
-    /// This is currently only used by the [`PerfBackEnd`]
+    /// This is currently only used by the [`PerfBackend`]
     pub multiplexed: bool,
+    /// The fraction of the time enabled that the counter was running, i.e. `time_running / time_enabled`.
+    /// This is `1.0` unless the reading was multiplexed.
+    pub running: f64,
     /// if `true`, the reading should be divided by the `scale` parameter of the benchmark.
     pub enable_scale: bool,
+    /// if `false`, the counter could not be read and `value` is NaN.
+    pub valid: bool,
+}
+
+/// A [`CounterReading`] along with the name of its counter, see [`Counters::read_named`].
+#[derive(Clone, Debug)]
+pub struct NamedReading {
+    pub name: String,
+    pub reading: CounterReading,
+}
+
+/// Reads `counters` and appends the readings of the counters in `names` to `dst`, in the order of `names`.
+///
+/// Counters that were not read are appended as invalid `NaN` readings, readings of other counters are dropped.
+pub(crate) fn read_by_name(
+    counters: &mut dyn Counters,
+    names: &[String],
+    dst: &mut Vec<CounterReading>,
+) {
+    let mut named = Vec::new();
+    counters.read_named(&mut named);
+    let mut used = vec![false; named.len()];
+    dst.extend(names.iter().map(|name| {
+        match (0..named.len()).find(|&i| !used[i] && named[i].name == *name) {
+            Some(i) => {
+                used[i] = true;
+                named[i].reading.clone()
+            }
+            None => CounterReading {
+                value: f64::NAN,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: false,
+            },
+        }
+    }));
 }
 
 impl CounterReading {
+    /// A valid reading of `value` that was not multiplexed and is divided by the `scale` of the benchmark.
+    pub const fn new(value: f64) -> Self {
+        CounterReading {
+            value,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: true,
+        }
+    }
+
     pub(crate) fn scaled_value(&self, scale: usize) -> f64 {
         if self.enable_scale {
             self.value / scale as f64
@@ -116,6 +464,149 @@ impl CounterReading {
     }
 }
 
+/// Metadata about a counter.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CounterMeta {
+    direction: Direction,
+    detail: bool,
+    note: Option<&'static str>,
+    width: Option<usize>,
+    percent: bool,
+    unit: Option<&'static str>,
+    gauge: bool,
+}
+
+impl CounterMeta {
+    pub const fn new() -> Self {
+        CounterMeta {
+            direction: Direction::LowerIsBetter,
+            detail: false,
+            note: None,
+            width: None,
+            percent: false,
+            unit: None,
+            gauge: false,
+        }
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub const fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// If `true`, the counter is only included in machine-readable formats like CSV.
+    ///
+    /// Formats intended for humans omit detail columns to save space.
+    pub fn detail(&self) -> bool {
+        self.detail
+    }
+
+    pub const fn with_detail(mut self, detail: bool) -> Self {
+        self.detail = detail;
+        self
+    }
+
+    /// A note explaining nonzero values of the counter.
+    ///
+    /// Formats intended for humans mark nonzero values and print the note below the table.
+    pub fn note(&self) -> Option<&'static str> {
+        self.note
+    }
+
+    pub const fn with_note(mut self, note: &'static str) -> Self {
+        self.note = Some(note);
+        self
+    }
+
+    /// The requested width of the counter column in the live table.
+    ///
+    /// Values take 7 characters, which is also the default width.
+    /// A wider column avoids wrapping long counter names in the header.
+    pub fn width(&self) -> Option<usize> {
+        self.width
+    }
+
+    pub const fn with_width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// If `true`, the counter is a fraction that formats intended for humans show as a percentage, e.g. `0.013` as `1.3 %`.
+    ///
+    /// Machine-readable formats like CSV keep the fraction.
+    pub fn percent(&self) -> bool {
+        self.percent
+    }
+
+    pub const fn with_percent(mut self, percent: bool) -> Self {
+        self.percent = percent;
+        self
+    }
+
+    /// The unit of the counter values, e.g. `s`, `J`, or `B`.
+    ///
+    /// Formats intended for humans include it in the column header, e.g. `time [s]`.
+    /// Machine-readable formats like CSV keep the plain name.
+    pub fn unit(&self) -> Option<&'static str> {
+        self.unit
+    }
+
+    pub const fn with_unit(mut self, unit: &'static str) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    /// If `true`, the counter reports a current state, e.g. the CPU a thread runs on, rather than an amount accumulated during the measurement.
+    ///
+    /// Differences and statistics of such values are meaningless, so gauge readings are passed through unchanged by
+    /// [checkpoints](crate::Running::checkpoint), overhead correction, and repeated runs.
+    pub fn gauge(&self) -> bool {
+        self.gauge
+    }
+
+    pub const fn with_gauge(mut self, gauge: bool) -> Self {
+        self.gauge = gauge;
+        self
+    }
+}
+
+/// Indicates which values of a counter are preferable.
+///
+/// This is used by formats comparing readings, so that an increase in e.g. instructions per cycle is not reported like an increase in cache misses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    /// Lower values are better.
+    /// This is the case for most counters, e.g. cycles, time, or cache misses.
+    #[default]
+    LowerIsBetter,
+    /// Higher values are better, e.g. for instructions per cycle or throughput.
+    HigherIsBetter,
+    /// Neither direction is preferable.
+    Neutral,
+}
+
+impl Direction {
+    /// Returns whether `value` is an improvement over `baseline`.
+    ///
+    /// Returns `None` if the values are equal or incomparable, or if the direction is [`Neutral`](Self::Neutral).
+    pub fn is_improvement(self, baseline: f64, value: f64) -> Option<bool> {
+        let higher = match value.partial_cmp(&baseline)? {
+            Ordering::Less => false,
+            Ordering::Equal => return None,
+            Ordering::Greater => true,
+        };
+        match self {
+            Direction::LowerIsBetter => Some(!higher),
+            Direction::HigherIsBetter => Some(higher),
+            Direction::Neutral => None,
+        }
+    }
+}
+
 pub(crate) fn count_counters(counters: &dyn Counters) -> usize {
     let mut num_counters = 0;
     counters.names(&mut |_| {
@@ -123,3 +614,76 @@ pub(crate) fn count_counters(counters: &dyn Counters) -> usize {
     });
     num_counters
 }
+
+#[test]
+fn test_resolve_event_names() {
+    assert_eq!(resolve_event_names(None), DEFAULT_EVENTS);
+    assert_eq!(resolve_event_names(Some("cycle,instr")), ["cycle", "instr"]);
+    assert_eq!(
+        resolve_event_names(Some("-kcycle,-t-clock,+instr,+cycle")),
+        ["cycle", "instr", "l1-miss", "llc-miss", "br-miss"]
+    );
+    assert_eq!(
+        resolve_event_names(Some("-kcycle,-cycle:p,+instr:p")),
+        ["instr:p", "l1-miss", "llc-miss", "br-miss", "t-clock"]
+    );
+    assert_eq!(
+        resolve_event_names(Some("-kcycle,t-clock,+foo")),
+        [
+            "cycle", "instr", "l1-miss", "llc-miss", "br-miss", "t-clock", "foo"
+        ]
+    );
+}
+
+#[test]
+fn test_parse_modifiers() {
+    assert_eq!(
+        parse_modifiers("cycle"),
+        ("cycle", Ok(Modifiers::default()))
+    );
+    let (event, modifiers) = parse_modifiers("instr:ukp");
+    assert_eq!(event, "instr");
+    assert_eq!(
+        modifiers,
+        Ok(Modifiers {
+            pinned: true,
+            user: true,
+            kernel: true
+        })
+    );
+    assert_eq!(parse_modifiers("cycle:x").1, Err('x'));
+    assert_eq!(column_name("cycle:p"), "cycle");
+    assert_eq!(column_name("cycle:pk"), "cycle:k");
+    assert_eq!(
+        resolve_event_names(Some("-kcycle,+cycle:k,+instr:p")),
+        [
+            "cycle", "instr:p", "l1-miss", "llc-miss", "br-miss", "t-clock", "cycle:k"
+        ]
+    );
+}
+
+#[test]
+fn test_read_by_name() {
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let mut counters = Replay::new(
+        vec!["a".into(), "b".into()],
+        vec![CounterMeta::new(); 2],
+        vec![reading(1.0), reading(2.0)],
+    );
+    let mut dst = Vec::new();
+    read_by_name(
+        &mut counters,
+        &["b".into(), "c".into(), "a".into()],
+        &mut dst,
+    );
+    let values: Vec<f64> = dst.iter().map(|x| x.value).collect();
+    assert_eq!(values[0], 2.0);
+    assert!(values[1].is_nan() && !dst[1].valid);
+    assert_eq!(values[2], 1.0);
+}
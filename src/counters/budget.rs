@@ -0,0 +1,151 @@
+#[cfg(all(target_os = "linux", feature = "perf"))]
+use super::PerfBackend;
+use super::{CounterReading, Counters};
+use crate::config::Config;
+use std::{
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A watchdog that aborts the process if a measurement exceeds a budget.
+///
+/// This is intended for catching accidental complexity blowups, e.g. in generated parameter sweeps, before they use up the time budget of a CI job.
+/// While counters are enabled, a background thread periodically checks each budgeted counter against its limit.
+/// If a limit is exceeded, an error message is printed and the process is aborted.
+///
+/// Budgets may be placed on the `time` counter and, on Linux, on any counter name supported by [`PerfBackend::with_counter_names`].
+/// Budgeted perf counters are opened separately from the counters being reported.
+/// Limits apply to the unnormalized values, e.g. `t-clock` and `time` are limited in seconds.
+/// This counter does not record any values.
+pub struct Budget {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    start: Option<Instant>,
+    time_limit: Option<f64>,
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    perf_limits: Vec<(String, f64, PerfBackend)>,
+}
+
+impl Budget {
+    /// Creates a watchdog from the environment variable `QPE_BUDGET`.
+    ///
+    /// The variable contains a comma separated list of limits of the form `name<value`, e.g. `instr<1e12,time<60`.
+    /// Returns `None` if the variable is not set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let var = config.get("BUDGET")?;
+        let limits: Vec<(&str, f64)> = var
+            .split(",")
+            .filter_map(|limit| {
+                let parsed = limit
+                    .split_once("<")
+                    .and_then(|(name, max)| Some((name.trim(), max.trim().parse().ok()?)));
+                if parsed.is_none() {
+                    eprintln!("invalid budget {limit:?}: expected name<value");
+                }
+                parsed
+            })
+            .collect();
+        Some(Self::new(limits))
+    }
+
+    /// Creates a watchdog from a list of counter names and limits.
+    ///
+    /// Invalid names and counters that cannot be opened are skipped with a warning message to stderr.
+    pub fn new<'a>(limits: impl IntoIterator<Item = (&'a str, f64)>) -> Self {
+        let mut state = State {
+            start: None,
+            time_limit: None,
+            #[cfg(all(target_os = "linux", feature = "perf"))]
+            perf_limits: Vec::new(),
+        };
+        for (name, max) in limits {
+            if name == "time" {
+                state.time_limit = Some(max);
+                continue;
+            }
+            #[cfg(all(target_os = "linux", feature = "perf"))]
+            {
+                let perf = PerfBackend::with_counter_names([name]);
+                if super::count_counters(&perf) == 1 {
+                    state.perf_limits.push((name.to_string(), max, perf));
+                }
+            }
+            #[cfg(not(all(target_os = "linux", feature = "perf")))]
+            eprintln!(
+                "cannot enforce budget for {name:?}: only time is supported without perf counters"
+            );
+        }
+        let state = Arc::new(Mutex::new(state));
+        let weak = Arc::downgrade(&state);
+        thread::spawn(move || watch(weak));
+        Budget { state }
+    }
+}
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+fn watch(state: Weak<Mutex<State>>) {
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    let mut buffer = Vec::new();
+    loop {
+        thread::sleep(CHECK_INTERVAL);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        #[cfg_attr(not(all(target_os = "linux", feature = "perf")), allow(unused_mut))]
+        let mut state = state.lock().unwrap();
+        let Some(start) = state.start else {
+            continue;
+        };
+        if let Some(max) = state.time_limit {
+            check("time", start.elapsed().as_secs_f64(), max);
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        for (name, max, perf) in &mut state.perf_limits {
+            buffer.clear();
+            perf.read(&mut buffer);
+            check(name, buffer[0].value, *max);
+        }
+    }
+}
+
+fn check(name: &str, value: f64, max: f64) {
+    if value > max {
+        eprintln!("budget exceeded: {name} reached {value}, limit is {max}. aborting.");
+        std::process::abort();
+    }
+}
+
+impl Counters for Budget {
+    fn enable(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        for (_, _, perf) in &mut state.perf_limits {
+            perf.reset();
+            perf.enable();
+        }
+        state.start = Some(Instant::now());
+    }
+
+    fn disable(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.start = None;
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        for (_, _, perf) in &mut state.perf_limits {
+            perf.disable();
+        }
+    }
+
+    fn reset(&mut self) {}
+
+    fn read(&mut self, _dst: &mut Vec<CounterReading>) {}
+
+    fn names(&self, _dst: &mut dyn FnMut(&str)) {}
+}
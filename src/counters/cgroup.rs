@@ -0,0 +1,199 @@
+use super::{CounterMeta, CounterReading, Counters, Direction};
+use crate::config::Config;
+use std::{fs, path::Path, sync::Once};
+
+/// Reports the CPU resource limits of the process as columns.
+///
+/// Containers and CI runners often restrict processes to a CPU quota or a subset of CPUs using cgroups.
+/// A quota throttles the process once it has used up its share of a period, inflating wall-clock time without affecting CPU counters.
+/// This reports the columns `cpu-quota` (in CPUs), `cpus` (the number of CPUs the process may run on), and `container` (1 if running in a container).
+/// If there is no quota or the CPUs are not restricted, the respective reading is NaN and not [valid](CounterReading::valid).
+/// They are [detail](CounterMeta::detail) columns, so they are only included by machine-readable formats like CSV, and [gauges](CounterMeta::gauge).
+/// Linux only, detects both cgroup v1 and v2.
+pub struct CgroupContext {
+    limits: Limits,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Limits {
+    quota: Option<f64>,
+    cpus: Option<usize>,
+    container: bool,
+}
+
+impl CgroupContext {
+    /// Detects the limits of the current process.
+    pub fn new() -> Self {
+        CgroupContext {
+            limits: Limits::detect(),
+        }
+    }
+
+    /// Creates a context from the environment variable `QPE_CONTEXT`.
+    ///
+    /// Returns `None` if the variable is not set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        config.flag("CONTEXT").then(Self::new)
+    }
+}
+
+impl Default for CgroupContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Prints a warning if the process is limited to a CPU quota, at most once per process.
+pub(crate) fn warn_quota() {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        let limits = Limits::detect();
+        if let Some(quota) = limits.quota
+            && limits.cpus.is_none_or(|cpus| quota < cpus as f64)
+        {
+            eprintln!(
+                "warning: the process is limited to a CPU quota of {quota} CPUs. Throttling can inflate time measurements of parallel code."
+            );
+        }
+    });
+}
+
+impl Limits {
+    fn detect() -> Self {
+        let cgroups = fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+        let mut limits = Limits {
+            container: Path::new("/.dockerenv").exists()
+                || Path::new("/run/.containerenv").exists()
+                || std::env::var_os("container").is_some(),
+            ..Default::default()
+        };
+        for line in cgroups.lines() {
+            let mut parts = line.splitn(3, ':');
+            let (Some(_), Some(controllers), Some(path)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let path = path.trim_start_matches('/');
+            if controllers.is_empty() {
+                let dir = Path::new("/sys/fs/cgroup").join(path);
+                limits.merge_quota(ancestors(&dir, "cpu.max", parse_cpu_max));
+                limits.merge_cpus(read(&dir.join("cpuset.cpus.effective")).and_then(count_cpus));
+            }
+            if controllers.split(',').any(|x| x == "cpu") {
+                let dir = Path::new("/sys/fs/cgroup/cpu").join(path);
+                let quota = read(&dir.join("cpu.cfs_quota_us"));
+                let period = read(&dir.join("cpu.cfs_period_us"));
+                if let (Some(quota), Some(period)) = (quota, period) {
+                    limits.merge_quota(parse_cpu_max(&format!("{quota} {period}")));
+                }
+            }
+            if controllers.split(',').any(|x| x == "cpuset") {
+                let dir = Path::new("/sys/fs/cgroup/cpuset").join(path);
+                limits.merge_cpus(read(&dir.join("cpuset.effective_cpus")).and_then(count_cpus));
+            }
+        }
+        limits
+    }
+
+    fn merge_quota(&mut self, quota: Option<f64>) {
+        if let Some(quota) = quota {
+            self.quota = Some(self.quota.map_or(quota, |x| x.min(quota)));
+        }
+    }
+
+    fn merge_cpus(&mut self, cpus: Option<usize>) {
+        if let Some(cpus) = cpus {
+            self.cpus = Some(self.cpus.map_or(cpus, |x| x.min(cpus)));
+        }
+    }
+}
+
+fn read(path: &Path) -> Option<String> {
+    Some(fs::read_to_string(path).ok()?.trim().to_string())
+}
+
+/// Applies `parse` to the file `name` in `dir` and each parent up to the cgroup root, returning the smallest value.
+fn ancestors(dir: &Path, name: &str, parse: fn(&str) -> Option<f64>) -> Option<f64> {
+    dir.ancestors()
+        .take_while(|x| x.starts_with("/sys/fs/cgroup"))
+        .filter_map(|x| parse(&read(&x.join(name))?))
+        .reduce(f64::min)
+}
+
+/// Parses a quota in the format of `cpu.max`, e.g. `50000 100000` for half a CPU.
+fn parse_cpu_max(text: &str) -> Option<f64> {
+    let (quota, period) = text.split_once(' ')?;
+    let quota: f64 = quota.parse().ok()?;
+    let period: f64 = period.trim().parse().ok()?;
+    (quota > 0.0 && period > 0.0).then_some(quota / period)
+}
+
+/// Counts the CPUs in a list like `0-3,8`.
+fn count_cpus(list: String) -> Option<usize> {
+    Some(parse_cpu_list(&list)?.len())
+}
+
+/// Parses a list of CPUs like `0-3,8`.
+pub(super) fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|x| !x.is_empty()) {
+        match range.split_once('-') {
+            Some((a, b)) => cpus.extend(a.parse::<usize>().ok()?..=b.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    (!cpus.is_empty()).then_some(cpus)
+}
+
+impl Counters for CgroupContext {
+    fn enable(&mut self) {}
+
+    fn disable(&mut self) {}
+
+    fn reset(&mut self) {}
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        let values = [
+            self.limits.quota.unwrap_or(f64::NAN),
+            self.limits.cpus.map_or(f64::NAN, |x| x as f64),
+            self.limits.container as u8 as f64,
+        ];
+        dst.extend(values.map(|value| CounterReading {
+            value,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: false,
+            valid: !value.is_nan(),
+        }));
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for name in ["cpu-quota", "cpus", "container"] {
+            dst(name);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        for _ in 0..3 {
+            dst(CounterMeta::new()
+                .with_direction(Direction::Neutral)
+                .with_detail(true)
+                .with_gauge(true));
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse_cpu_max("50000 100000"), Some(0.5));
+    assert_eq!(parse_cpu_max("max 100000"), None);
+    assert_eq!(parse_cpu_max("-1 100000"), None);
+    assert_eq!(count_cpus("0-3,8,10-11".into()), Some(7));
+    assert_eq!(count_cpus("".into()), None);
+    assert_eq!(parse_cpu_list("0-2,5\n"), Some(vec![0, 1, 2, 5]));
+}
@@ -0,0 +1,211 @@
+use super::{
+    CounterMeta, CounterReading, CounterState, Counters, cgroup::parse_cpu_list, column_name,
+    event_meta, parse_modifiers, resolve_event_names,
+};
+use crate::{config::Config, perf_sys::*};
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+/// A [`Counters`] implementation counting events of all processes in a cgroup.
+///
+/// This allows measuring benchmarks that spawn work into containers or systemd scopes as a unit, by counting the cgroup the work runs in, e.g. `/sys/fs/cgroup/system.slice/my-service.scope`.
+/// Unlike a [`PerfBackend`](super::PerfBackend), this includes processes that were not spawned by the calling thread, and excludes the calling thread unless it belongs to the cgroup.
+/// The kernel requires cgroup events to be opened per CPU, so each counter is opened on every online CPU and their sum is reported.
+///
+/// Supported names are `cycle`, `kcycle`, `instr`, `l1-miss`, `llc-miss`, `branch`, `br-miss`, `t-clock`, `pg-fault`, `ctx-sw`, and `cpu-mig`, see [`PerfBackend::with_counter_names`](super::PerfBackend::with_counter_names).
+/// Opening cgroup events usually requires `CAP_PERFMON` or `perf_event_paranoid` set to `0` or less.
+pub struct CgroupBackend {
+    state: CounterState,
+    counters: Vec<(String, Vec<File>, f64)>,
+}
+
+impl CgroupBackend {
+    /// Creates a backend for the cgroup in the environment variable `QPE_CGROUP`.
+    ///
+    /// The variable contains the path of the cgroup directory, relative paths are resolved against `/sys/fs/cgroup`.
+    /// The counters are configured using `QPE_EVENTS` like those of a default [`PerfBackend`](super::PerfBackend).
+    /// Returns `None` if the variable is not set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let cgroup = config.get("CGROUP")?;
+        let events = config.get("EVENTS");
+        Some(Self::new(
+            Path::new("/sys/fs/cgroup").join(cgroup),
+            resolve_event_names(events.as_deref()),
+        ))
+    }
+
+    /// Creates a backend counting the events `names` of the cgroup with the directory `path`.
+    ///
+    /// Invalid names and counters that cannot be opened are skipped with a warning message to stderr.
+    pub fn new<'a>(path: impl AsRef<Path>, names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut this = CgroupBackend {
+            state: CounterState::default(),
+            counters: Vec::new(),
+        };
+        let path = path.as_ref();
+        let cgroup = match File::open(path) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("failed to open cgroup {}: {e}", path.display());
+                return this;
+            }
+        };
+        let cpus = fs::read_to_string("/sys/devices/system/cpu/online")
+            .ok()
+            .and_then(|x| parse_cpu_list(&x));
+        let Some(cpus) = cpus else {
+            eprintln!("failed to read the list of online CPUs");
+            return this;
+        };
+        for name in names {
+            let Some((attr, scale)) = attr(name) else {
+                continue;
+            };
+            let files: io::Result<Vec<File>> = cpus
+                .iter()
+                .map(|&cpu| perf_event_open_cgroup(attr.clone(), &cgroup, cpu))
+                .collect();
+            match files {
+                Ok(files) => (this.counters).push((column_name(name), files, scale)),
+                Err(e) => eprintln!("failed to create cgroup counter {name:?}: {e}"),
+            }
+        }
+        this
+    }
+
+    fn ioctl(&mut self, request: libc::c_ulong) {
+        for file in self.counters.iter().flat_map(|x| &x.1) {
+            ioctl(file, request);
+        }
+    }
+}
+
+/// Returns the event of the counter `name`, along with the scale applied to its values.
+fn attr(name: &str) -> Option<(PerfEventAttr, f64)> {
+    let mut scale = 1.0;
+    let mut flags = FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV;
+    let (event, modifiers) = parse_modifiers(name);
+    let modifiers = match modifiers {
+        Ok(modifiers) => modifiers,
+        Err(modifier) => {
+            eprintln!("invalid modifier {modifier:?} in counter name {name:?}");
+            return None;
+        }
+    };
+    let (kind, config) = match event {
+        "cycle" => (PERF_TYPE_HARDWARE, 0),
+        "kcycle" => {
+            flags = FLAG_DISABLED | FLAG_EXCLUDE_USER | FLAG_EXCLUDE_HV;
+            (PERF_TYPE_HARDWARE, 0)
+        }
+        "instr" => (PERF_TYPE_HARDWARE, 1),
+        "l1-miss" => (PERF_TYPE_HW_CACHE, 1 << 16),
+        "llc-miss" => (PERF_TYPE_HARDWARE, 3),
+        "branch" => (PERF_TYPE_HARDWARE, 4),
+        "br-miss" => (PERF_TYPE_HARDWARE, 5),
+        "t-clock" => {
+            // The task clock is tied to a task, the CPU clock counts the time processes of the cgroup are running.
+            scale = 1.0e-9;
+            (PERF_TYPE_SOFTWARE, 0)
+        }
+        "pg-fault" => (PERF_TYPE_SOFTWARE, 2),
+        "ctx-sw" => (PERF_TYPE_SOFTWARE, 3),
+        "cpu-mig" => (PERF_TYPE_SOFTWARE, 4),
+        _ => {
+            eprintln!("invalid counter name for cgroup counting: {name:?}");
+            return None;
+        }
+    };
+    if modifiers.privilege() {
+        flags &= !(FLAG_EXCLUDE_USER | FLAG_EXCLUDE_KERNEL);
+        if !modifiers.user {
+            flags |= FLAG_EXCLUDE_USER;
+        }
+        if !modifiers.kernel {
+            flags |= FLAG_EXCLUDE_KERNEL;
+        }
+    }
+    if modifiers.pinned {
+        flags |= FLAG_PINNED;
+    }
+    let attr = PerfEventAttr {
+        kind,
+        config,
+        flags,
+        read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+        ..Default::default()
+    };
+    Some((attr, scale))
+}
+
+/// Reads the count, time enabled, and time running of an event.
+fn read_snapshot(mut file: &File) -> io::Result<[u64; 3]> {
+    let mut buffer = [0u8; 24];
+    file.read_exact(&mut buffer)?;
+    Ok(std::array::from_fn(|i| {
+        u64::from_ne_bytes(buffer[i * 8..][..8].try_into().unwrap())
+    }))
+}
+
+impl Counters for CgroupBackend {
+    fn enable(&mut self) {
+        self.state.enable("CgroupBackend");
+        self.ioctl(PERF_EVENT_IOC_ENABLE);
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("CgroupBackend");
+        self.ioctl(PERF_EVENT_IOC_DISABLE);
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("CgroupBackend");
+        self.ioctl(PERF_EVENT_IOC_RESET);
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        for (name, files, scale) in &self.counters {
+            let mut reading = CounterReading {
+                value: 0.0,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: true,
+                valid: true,
+            };
+            for file in files {
+                match read_snapshot(file) {
+                    // Counters on CPUs where the cgroup did not run are not scheduled.
+                    Ok([_, _, 0]) => {}
+                    Ok([count, enabled, running]) => {
+                        reading.value += count as f64 * *scale * enabled as f64 / running as f64;
+                        reading.multiplexed |= enabled != running;
+                        reading.running = reading.running.min(running as f64 / enabled as f64);
+                    }
+                    Err(e) => {
+                        eprintln!("CgroupBackend: failed to read counter {name:?}: {e}");
+                        reading.value = f64::NAN;
+                        reading.valid = false;
+                    }
+                }
+            }
+            dst.push(reading);
+        }
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for (name, _, _) in &self.counters {
+            dst(name);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.names(&mut |name| dst(event_meta(name)));
+    }
+}
@@ -0,0 +1,203 @@
+use super::{CounterMeta, CounterReading, Counters, Direction, cgroup::parse_cpu_list};
+use crate::config::Config;
+use std::{
+    fs, io,
+    mem::MaybeUninit,
+    thread::{self, ThreadId},
+};
+
+/// Pins measurements to a CPU and reports which kind of CPU they ran on.
+///
+/// Benchmark machines are often configured to keep other tasks and timer interrupts away from some cores using the `isolcpus` and `nohz_full` kernel parameters.
+/// When created, the calling thread is pinned to the selected CPU, and so is any other thread when it enables the counters.
+/// This reports the columns `cpu`, the CPU the last measurement ended on, and `cpu-class`, which is 2 for CPUs listed in `isolcpus`, 1 for other CPUs listed in `nohz_full`, and 0 otherwise.
+/// They are [detail](CounterMeta::detail) columns, so they are only included by machine-readable formats like CSV, and [gauges](CounterMeta::gauge).
+/// Linux only.
+pub struct CpuPinning {
+    cpu: Option<usize>,
+    isolation: Isolation,
+    /// The thread that was last pinned.
+    thread: Option<ThreadId>,
+    last: Option<usize>,
+}
+
+/// The CPUs isolated using kernel parameters.
+#[derive(Clone, Debug, Default)]
+struct Isolation {
+    isolated: Vec<usize>,
+    nohz_full: Vec<usize>,
+}
+
+impl CpuPinning {
+    /// Pins measurements to `cpu`.
+    ///
+    /// If `cpu` is `None`, measurements are not pinned, but the CPU they ran on is still reported.
+    pub fn new(cpu: Option<usize>) -> Self {
+        let mut this = CpuPinning {
+            cpu,
+            isolation: Isolation::detect(),
+            thread: None,
+            last: None,
+        };
+        if let Some(cpu) = cpu {
+            this.pin(cpu);
+        }
+        this
+    }
+
+    /// Pins measurements to an isolated CPU, preferring CPUs listed in `isolcpus` over those listed in `nohz_full`.
+    ///
+    /// If there are no isolated CPUs or pinning to them fails, a warning is printed to stderr and measurements are not pinned.
+    pub fn auto_isolated() -> Self {
+        let isolation = Isolation::detect();
+        let cpu = isolation
+            .candidates()
+            .into_iter()
+            .find(|&cpu| set_affinity(cpu).is_ok());
+        if cpu.is_none() {
+            eprintln!("no isolated CPU available, measurements are not pinned");
+        }
+        CpuPinning {
+            cpu,
+            isolation,
+            thread: cpu.map(|_| thread::current().id()),
+            last: None,
+        }
+    }
+
+    /// Creates a pinning from the environment variable `QPE_CPU`.
+    ///
+    /// The variable contains either the number of a CPU or `auto-isolated`, see [`auto_isolated`](Self::auto_isolated).
+    /// Returns `None` if the variable is not set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let var = config.get("CPU")?;
+        if var == "auto-isolated" {
+            return Some(Self::auto_isolated());
+        }
+        let cpu = var
+            .parse()
+            .map_err(|_| eprintln!("invalid CPU {var:?}: expected a number or auto-isolated"))
+            .ok();
+        Some(Self::new(cpu))
+    }
+
+    fn pin(&mut self, cpu: usize) {
+        self.thread = Some(thread::current().id());
+        if let Err(e) = set_affinity(cpu) {
+            eprintln!("failed to pin thread to CPU {cpu}: {e}");
+        }
+    }
+}
+
+impl Isolation {
+    fn detect() -> Self {
+        let read = |name: &str| {
+            fs::read_to_string(format!("/sys/devices/system/cpu/{name}"))
+                .ok()
+                .and_then(|x| parse_cpu_list(&x))
+                .unwrap_or_default()
+        };
+        Isolation {
+            isolated: read("isolated"),
+            nohz_full: read("nohz_full"),
+        }
+    }
+
+    /// Returns the isolated CPUs in order of preference.
+    fn candidates(&self) -> Vec<usize> {
+        let mut cpus = self.isolated.clone();
+        cpus.extend(self.nohz_full.iter().filter(|x| !self.isolated.contains(x)));
+        cpus
+    }
+
+    fn class(&self, cpu: usize) -> usize {
+        if self.isolated.contains(&cpu) {
+            2
+        } else if self.nohz_full.contains(&cpu) {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+fn set_affinity(cpu: usize) -> io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    let result = unsafe {
+        let mut set = MaybeUninit::<libc::cpu_set_t>::zeroed().assume_init();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn current_cpu() -> Option<usize> {
+    usize::try_from(unsafe { libc::sched_getcpu() }).ok()
+}
+
+impl Counters for CpuPinning {
+    fn enable(&mut self) {
+        if let Some(cpu) = self.cpu
+            && self.thread != Some(thread::current().id())
+        {
+            self.pin(cpu);
+        }
+    }
+
+    fn disable(&mut self) {
+        self.last = current_cpu();
+    }
+
+    fn reset(&mut self) {}
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        let values = [
+            self.last.map_or(f64::NAN, |x| x as f64),
+            self.last
+                .map_or(f64::NAN, |x| self.isolation.class(x) as f64),
+        ];
+        dst.extend(values.map(|value| CounterReading {
+            value,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: false,
+            valid: true,
+        }));
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for name in ["cpu", "cpu-class"] {
+            dst(name);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        for _ in 0..2 {
+            dst(CounterMeta::new()
+                .with_direction(Direction::Neutral)
+                .with_detail(true)
+                .with_gauge(true));
+        }
+    }
+}
+
+#[test]
+fn test_candidates() {
+    let isolation = Isolation {
+        isolated: vec![3, 2],
+        nohz_full: vec![1, 2, 3],
+    };
+    assert_eq!(isolation.candidates(), [3, 2, 1]);
+    assert_eq!(isolation.class(2), 2);
+    assert_eq!(isolation.class(1), 1);
+    assert_eq!(isolation.class(0), 0);
+}
@@ -0,0 +1,168 @@
+use super::{CounterReading, Counters};
+use ::criterion::{
+    Throughput,
+    measurement::{Measurement, ValueFormatter},
+};
+use std::cell::RefCell;
+
+/// Reports a counter as a [criterion](::criterion) measurement instead of wall-clock time.
+///
+/// This lets existing criterion benchmarks report e.g. instructions or cache misses per iteration.
+/// Criterion analyzes a single value, so one counter is selected by name.
+/// Requires the `criterion` feature.
+/// ```no_run
+/// use criterion::{Criterion, criterion_group, criterion_main};
+/// use quick_perf_event::counters::CriterionMeasurement;
+///
+/// fn bench(c: &mut Criterion<CriterionMeasurement>) {
+///     c.bench_function("sum", |b| b.iter(|| (0..1000).map(std::hint::black_box).sum::<u64>()));
+/// }
+///
+/// criterion_group! {
+///     name = benches;
+///     config = Criterion::default().with_measurement(CriterionMeasurement::from_env("instr").unwrap());
+///     targets = bench
+/// }
+/// criterion_main!(benches);
+/// ```
+pub struct CriterionMeasurement<C = Box<dyn Counters>> {
+    counters: RefCell<C>,
+    index: usize,
+    buffer: RefCell<Vec<CounterReading>>,
+    formatter: Units,
+}
+
+impl CriterionMeasurement {
+    /// Measures the counter `name` of the default counters, see [`counters_from_env`](super::counters_from_env).
+    ///
+    /// Returns `None` with a warning message to stderr if there is no such counter.
+    pub fn from_env(name: &str) -> Option<Self> {
+        Self::new(super::counters_from_env(), name)
+    }
+}
+
+impl<C: Counters> CriterionMeasurement<C> {
+    /// Measures the counter `name` of `counters`.
+    ///
+    /// Returns `None` with a warning message to stderr if there is no such counter.
+    pub fn new(counters: C, name: &str) -> Option<Self> {
+        let mut names = Vec::new();
+        counters.names(&mut |x| names.push(x.to_string()));
+        let Some(index) = names.iter().position(|x| x == name) else {
+            eprintln!("no counter named {name:?} for criterion measurement");
+            return None;
+        };
+        Some(CriterionMeasurement {
+            counters: RefCell::new(counters),
+            index,
+            buffer: RefCell::new(Vec::new()),
+            formatter: Units {
+                value: prefixed(name),
+                per_byte: prefixed(&format!("{name}/byte")),
+                per_element: prefixed(&format!("{name}/elem")),
+                per_bit: prefixed(&format!("{name}/bit")),
+            },
+        })
+    }
+}
+
+impl<C: Counters> Measurement for CriterionMeasurement<C> {
+    type Intermediate = ();
+    type Value = f64;
+
+    fn start(&self) {
+        let mut counters = self.counters.borrow_mut();
+        counters.reset();
+        counters.enable();
+    }
+
+    fn end(&self, _: ()) -> f64 {
+        let mut counters = self.counters.borrow_mut();
+        counters.disable();
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.clear();
+        counters.read(&mut buffer);
+        buffer[self.index].value
+    }
+
+    fn add(&self, v1: &f64, v2: &f64) -> f64 {
+        v1 + v2
+    }
+
+    fn zero(&self) -> f64 {
+        0.0
+    }
+
+    fn to_f64(&self, value: &f64) -> f64 {
+        *value
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &self.formatter
+    }
+}
+
+const PREFIXES: [(&str, f64); 7] = [
+    ("n", 1e-9),
+    ("µ", 1e-6),
+    ("m", 1e-3),
+    ("", 1.0),
+    ("k", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+];
+
+/// Returns the unit with each of [`PREFIXES`].
+///
+/// Criterion requires static unit names, so these are leaked once per measurement.
+fn prefixed(unit: &str) -> [&'static str; 7] {
+    PREFIXES.map(|(prefix, _)| &*format!("{prefix}{unit}").leak())
+}
+
+/// Scales values by the largest prefix not exceeding `typical`, returning the prefixed unit.
+fn scale(typical: f64, values: &mut [f64], units: &[&'static str; 7]) -> &'static str {
+    let i = PREFIXES
+        .iter()
+        .rposition(|x| x.1 <= typical.abs())
+        .unwrap_or(0);
+    for x in values {
+        *x /= PREFIXES[i].1;
+    }
+    units[i]
+}
+
+struct Units {
+    value: [&'static str; 7],
+    per_byte: [&'static str; 7],
+    per_element: [&'static str; 7],
+    per_bit: [&'static str; 7],
+}
+
+impl ValueFormatter for Units {
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
+        scale(typical_value, values, &self.value)
+    }
+
+    fn scale_throughputs(
+        &self,
+        typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        let (n, units) = match *throughput {
+            Throughput::Bytes(n) | Throughput::BytesDecimal(n) => (n, &self.per_byte),
+            Throughput::Elements(n) | Throughput::ElementsAndBytes { elements: n, .. } => {
+                (n, &self.per_element)
+            }
+            Throughput::Bits(n) => (n, &self.per_bit),
+        };
+        for x in values.iter_mut() {
+            *x /= n as f64;
+        }
+        scale(typical_value / n as f64, values, units)
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        self.value[3]
+    }
+}
@@ -0,0 +1,72 @@
+use super::resolve_event_names;
+use crate::config::Config;
+use std::fmt;
+
+/// Describes requested counters that are not supported on the current platform.
+///
+/// Perf counters are only available on Linux with the `perf` feature enabled.
+/// Elsewhere, [`counters_from_env`](super::counters_from_env) substitutes other backends, and prints this description to stderr once per process.
+/// Cross-platform harnesses can use it to adapt their expectations, e.g. by skipping checks on instruction counts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Degradation {
+    /// Why the counters are not supported.
+    pub reason: &'static str,
+    /// The requested counters that are not recorded, e.g. `cycle`.
+    pub unsupported: Vec<String>,
+    /// The backends recorded instead, e.g. `TimeBackend`.
+    pub substitutes: Vec<&'static str>,
+}
+
+impl Degradation {
+    /// Returns the degradation of the counters configured by environment variables, e.g. `QPE_EVENTS`.
+    ///
+    /// Returns `None` if all requested counters are supported.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        if cfg!(all(target_os = "linux", feature = "perf")) || config.get("MANUAL").is_some() {
+            return None;
+        }
+        #[cfg(target_os = "macos")]
+        let (supported, substitutes) = (
+            &super::macos_backend::NAMES[..],
+            vec!["TimeBackend", "MacosBackend"],
+        );
+        #[cfg(not(target_os = "macos"))]
+        let (supported, substitutes): (&[&str], _) = (&[], vec!["TimeBackend"]);
+        let events = config.get("EVENTS");
+        let degradation = Degradation {
+            reason: if cfg!(target_os = "linux") {
+                "the perf feature is disabled"
+            } else {
+                "perf counters are only available on Linux"
+            },
+            unsupported: resolve_event_names(events.as_deref())
+                .into_iter()
+                .filter(|x| !supported.contains(x))
+                .map(String::from)
+                .collect(),
+            substitutes,
+        };
+        (!degradation.unsupported.is_empty()).then_some(degradation)
+    }
+}
+
+/// Prints a notice describing the degradation, at most once per process.
+#[cfg(not(all(target_os = "linux", feature = "perf")))]
+pub(crate) fn warn_degradation(config: &Config) {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    if let Some(degradation) = Degradation::from_config(config) {
+        WARNED.call_once(|| eprintln!("{degradation}"));
+    }
+}
+
+impl fmt::Display for Degradation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "some counters are not supported: {}", self.reason)?;
+        writeln!(f, "  unsupported: {}", self.unsupported.join(", "))?;
+        write!(f, "  recorded instead: {}", self.substitutes.join(", "))
+    }
+}
@@ -0,0 +1,460 @@
+use super::{CounterMeta, CounterReading, CounterState, Counters, Direction, count_counters};
+use crate::config::Config;
+use std::{iter::Peekable, str::CharIndices};
+
+/// A counter wrapper that appends columns computed from the inner counters.
+///
+/// Each derived column is an arithmetic expression over counters recorded by the inner counters, e.g. instructions per cycle.
+/// Expressions are evaluated on the readings before normalization and are therefore not divided by the `scale` of a benchmark.
+/// A derived reading is marked as multiplexed if any counter it refers to is.
+///
+/// Derived columns may be configured with [`with_ratio`](Self::with_ratio) and [`with_expr`](Self::with_expr),
+/// or by name using [`with_names`](Self::with_names).
+pub struct Derived<C> {
+    state: CounterState,
+    inner: C,
+    num_inner: usize,
+    columns: Vec<Column>,
+}
+
+/// Named columns added by [`Derived::from_env`] if all counters they refer to are recorded.
+const AUTOMATIC: &[&str] = &["fe-bound", "be-bound", "br-rate", "freq"];
+
+/// Alternative names of named columns, e.g. `br-miss-rate` for `br-rate`.
+const ALIASES: &[(&str, &str)] = &[("br-miss-rate", "br-rate")];
+
+/// Returns the expression and metadata of a named column, see [`Derived::with_names`].
+fn named(name: &str) -> Option<(&'static str, CounterMeta)> {
+    let lower = CounterMeta::new();
+    let higher = CounterMeta::new().with_direction(Direction::HigherIsBetter);
+
+    // Keep this clean. Users are expected to read this match statement
+    // to discover available derived column names.
+    Some(match name {
+        "ipc" => ("instr / cycle", higher),
+        "l1-rate" => ("l1-miss / instr", lower),
+        "llc-rate" => ("llc-miss / instr", lower),
+        // fraction of cycles stalled in the frontend and backend
+        "fe-bound" => ("stall-fe / cycle", lower.with_percent(true)),
+        "be-bound" => ("stall-be / cycle", lower.with_percent(true)),
+        // fraction of branches that were mispredicted
+        "br-rate" => ("br-miss / branch", lower.with_percent(true)),
+        // average clock frequency in GHz while the thread was running
+        "freq" => (
+            "cycle / t-clock / 1e9",
+            CounterMeta::new().with_direction(Direction::Neutral),
+        ),
+        _ => return None,
+    })
+}
+
+struct Column {
+    name: String,
+    expr: Expr,
+    meta: CounterMeta,
+}
+
+impl<C: Counters> Derived<C> {
+    /// Wraps `inner` without adding any derived columns.
+    pub fn new(inner: C) -> Self {
+        Derived {
+            state: CounterState::default(),
+            num_inner: count_counters(&inner),
+            inner,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Wraps `inner`, adding the derived columns listed in `QPE_DERIVED` and `QPE_EXPR`.
+    ///
+    /// `QPE_DERIVED` is a comma separated list of names accepted by [`with_names`](Self::with_names).
+    /// `QPE_EXPR` is a comma separated list of `name=expression` pairs, see [`with_expr`](Self::with_expr).
+    /// An expression followed by `%` is shown as a [percentage](CounterMeta::with_percent), e.g. `miss=llc-miss / l1-miss %`.
+    /// Additionally, some named columns are added automatically if all counters they refer to are recorded, e.g. `fe-bound` if `stall-fe` and `cycle` are recorded.
+    pub fn from_env(inner: C) -> Self {
+        Self::from_config(inner, &Config::default())
+    }
+
+    pub(crate) fn from_config(inner: C, config: &Config) -> Self {
+        let mut this = match config.get("DERIVED") {
+            Some(names) => Self::with_names(inner, names.split(",")),
+            None => Self::new(inner),
+        };
+        if let Some(exprs) = config.get("EXPR") {
+            for def in exprs.split(",") {
+                let Some((name, expr)) = def.split_once("=") else {
+                    eprintln!("invalid expression column {def:?}: expected name=expression");
+                    continue;
+                };
+                let (expr, percent) = match expr.trim_end().strip_suffix("%") {
+                    Some(expr) => (expr, true),
+                    None => (expr, false),
+                };
+                let meta = CounterMeta::new().with_percent(percent);
+                this = this.with_expr(name.trim(), expr, meta);
+            }
+        }
+        for name in AUTOMATIC {
+            let (expr, meta) = named(name).unwrap();
+            if this.find(name).is_none()
+                && let Ok(expr) = Parser::new(expr, &|counter| this.find(counter)).parse()
+            {
+                this.columns.push(Column {
+                    name: name.to_string(),
+                    expr,
+                    meta,
+                });
+            }
+        }
+        this
+    }
+
+    /// Wraps `inner`, adding derived columns from a list of names.
+    ///
+    /// Like the counter names of [`PerfBackend`](super::PerfBackend), these are aliases defined by this crate.
+    /// `br-miss-rate` is accepted as another name for the `br-rate` column.
+    /// Invalid names are skipped with a warning message to stderr.
+    pub fn with_names<'a>(inner: C, names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut this = Self::new(inner);
+        for name in names {
+            let name = (ALIASES.iter().find(|x| x.0 == name)).map_or(name, |x| x.1);
+            match named(name) {
+                Some((expr, meta)) => this = this.with_expr(name, expr, meta),
+                None => eprintln!("invalid derived counter name: {name:?}"),
+            }
+        }
+        this
+    }
+
+    /// Adds a column named `name` computing the ratio of the counters named `numerator` and `denominator`.
+    ///
+    /// If either counter is not recorded, the column is skipped with a warning message to stderr.
+    pub fn with_ratio(
+        self,
+        name: &str,
+        numerator: &str,
+        denominator: &str,
+        meta: CounterMeta,
+    ) -> Self {
+        self.with_expr(name, &format!("{numerator} / {denominator}"), meta)
+    }
+
+    /// Adds a column named `name` computing an arithmetic expression.
+    ///
+    /// Expressions consist of counter names, numeric literals, the operators `+`, `-`, `*`, `/`, and parentheses.
+    /// Counter names may refer to counters recorded by the inner counters and to previously added derived columns.
+    /// As counter names may contain `-`, subtraction must be surrounded by whitespace (`cycle - kcycle`).
+    ///
+    /// If the expression is invalid or refers to counters that are not recorded,
+    /// the column is skipped with a warning message to stderr.
+    pub fn with_expr(mut self, name: &str, expr: &str, meta: CounterMeta) -> Self {
+        let parsed = Parser::new(expr, &|counter| self.find(counter)).parse();
+        match parsed {
+            Ok(expr) => self.columns.push(Column {
+                name: name.to_string(),
+                expr,
+                meta,
+            }),
+            Err(e) => eprintln!("cannot compute {name:?}: {e}"),
+        }
+        self
+    }
+
+    pub(crate) fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        let mut found = None;
+        let mut i = 0;
+        self.inner.names(&mut |x| {
+            if x == name && found.is_none() {
+                found = Some(i);
+            }
+            i += 1;
+        });
+        found.or_else(|| {
+            let derived = self.columns.iter().position(|x| x.name == name)?;
+            Some(self.num_inner + derived)
+        })
+    }
+}
+
+impl<C: Counters> Counters for Derived<C> {
+    fn enable(&mut self) {
+        self.state.enable("Derived");
+        self.inner.enable();
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("Derived");
+        self.inner.disable();
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("Derived");
+        self.inner.reset();
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        let start = dst.len();
+        self.inner.read(dst);
+        for column in &self.columns {
+            let reading = column.expr.eval(&dst[start..]);
+            dst.push(reading);
+        }
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        self.inner.names(dst);
+        for column in &self.columns {
+            dst(&column.name);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.inner.meta(dst);
+        for column in &self.columns {
+            dst(column.meta);
+        }
+    }
+
+    fn recompute_derived(&self, readings: &mut [CounterReading]) {
+        self.inner
+            .recompute_derived(&mut readings[..self.num_inner]);
+        for (i, column) in self.columns.iter().enumerate() {
+            let i = self.num_inner + i;
+            readings[i] = column.expr.eval(&readings[..i]);
+        }
+    }
+
+    fn scale_unit(&self) -> Option<&str> {
+        self.inner.scale_unit()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Const(f64),
+    Counter(usize),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression.
+    ///
+    /// The result is marked as multiplexed or invalid if any referenced reading is.
+    fn eval(&self, readings: &[CounterReading]) -> CounterReading {
+        match self {
+            Expr::Const(x) => CounterReading {
+                value: *x,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: true,
+            },
+            Expr::Counter(i) => CounterReading {
+                enable_scale: false,
+                ..readings[*i].clone()
+            },
+            Expr::Neg(x) => {
+                let x = x.eval(readings);
+                CounterReading {
+                    value: -x.value,
+                    ..x
+                }
+            }
+            Expr::Binary(a, op, b) => {
+                let a = a.eval(readings);
+                let b = b.eval(readings);
+                let value = match op {
+                    '+' => a.value + b.value,
+                    '-' => a.value - b.value,
+                    '*' => a.value * b.value,
+                    '/' => a.value / b.value,
+                    _ => unreachable!(),
+                };
+                CounterReading {
+                    value,
+                    multiplexed: a.multiplexed || b.multiplexed,
+                    running: a.running.min(b.running),
+                    enable_scale: false,
+                    valid: a.valid && b.valid,
+                }
+            }
+        }
+    }
+}
+
+/// A recursive descent parser for derived column expressions.
+struct Parser<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    resolve: &'a dyn Fn(&str) -> Option<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str, resolve: &'a dyn Fn(&str) -> Option<usize>) -> Self {
+        Parser {
+            src,
+            chars: src.char_indices().peekable(),
+            resolve,
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, String> {
+        let expr = self.sum()?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(c) => Err(format!("unexpected {c:?} in {:?}", self.src)),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|x| x.1.is_whitespace()).is_some() {}
+        self.chars.peek().map(|x| x.1)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.product()?));
+        }
+        Ok(lhs)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.chars.next();
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let inner = self.sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(inner)
+                    }
+                    _ => Err(format!("missing ')' in {:?}", self.src)),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                // digits and '.', optionally followed by an exponent, e.g. `2.5e9` or `1e-3`
+                let (mut prev, mut exponent) = (c, false);
+                let token = self.token(|c| {
+                    let accept = match c {
+                        '0'..='9' => true,
+                        '.' | 'e' | 'E' => !exponent,
+                        '+' | '-' => matches!(prev, 'e' | 'E'),
+                        _ => false,
+                    };
+                    exponent |= matches!(c, 'e' | 'E');
+                    prev = c;
+                    accept
+                });
+                token
+                    .parse()
+                    .map(Expr::Const)
+                    .map_err(|_| format!("invalid number {token:?}"))
+            }
+            Some(c) if c.is_alphabetic() => {
+                let token =
+                    self.token(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+                match (self.resolve)(token) {
+                    Some(i) => Ok(Expr::Counter(i)),
+                    None => Err(format!("counter {token:?} is not recorded")),
+                }
+            }
+            Some(c) => Err(format!("unexpected {c:?} in {:?}", self.src)),
+            None => Err(format!("unexpected end of {:?}", self.src)),
+        }
+    }
+
+    fn token(&mut self, mut accept: impl FnMut(char) -> bool) -> &'a str {
+        let start = self.chars.peek().unwrap().0;
+        let mut end = start;
+        while let Some((i, c)) = self.chars.next_if(|x| accept(x.1)) {
+            end = i + c.len_utf8();
+        }
+        &self.src[start..end]
+    }
+}
+
+#[test]
+fn test_parse_expr() {
+    use Expr::*;
+    let names = ["cycle", "l1-miss", "instr", "cycle:k"];
+    let resolve = |name: &str| names.iter().position(|x| *x == name);
+    let parse = |src| Parser::new(src, &resolve).parse();
+    let b = Box::new;
+    assert_eq!(
+        parse("instr/cycle"),
+        Ok(Binary(b(Counter(2)), '/', b(Counter(0))))
+    );
+    assert_eq!(
+        parse(" l1-miss - 1e3*instr "),
+        Ok(Binary(
+            b(Counter(1)),
+            '-',
+            b(Binary(b(Const(1e3)), '*', b(Counter(2))))
+        ))
+    );
+    assert_eq!(
+        parse("-(cycle + 2) / 0.5"),
+        Ok(Binary(
+            b(Neg(b(Binary(b(Counter(0)), '+', b(Const(2.0)))))),
+            '/',
+            b(Const(0.5))
+        ))
+    );
+    assert_eq!(
+        parse("cycle:k/cycle"),
+        Ok(Binary(b(Counter(3)), '/', b(Counter(0))))
+    );
+    assert_eq!(
+        parse("instr * 1e-3"),
+        Ok(Binary(b(Counter(2)), '*', b(Const(1e-3))))
+    );
+    assert_eq!(
+        parse("cycle / 2.5E+9-1"),
+        Ok(Binary(
+            b(Binary(b(Counter(0)), '/', b(Const(2.5e9)))),
+            '-',
+            b(Const(1.0))
+        ))
+    );
+    assert!(parse("1e").is_err());
+    assert!(parse("2x").is_err());
+    assert!(parse("cycle-instr").is_err());
+    assert!(parse("(cycle").is_err());
+    assert!(parse("cycle instr").is_err());
+    assert!(parse("").is_err());
+}
+
+#[test]
+fn test_alias() {
+    use super::Replay;
+    let inner = Replay::new(
+        vec!["br-miss".into(), "branch".into()],
+        vec![CounterMeta::new(); 2],
+        Vec::new(),
+    );
+    let config = Config::empty().with_value("DERIVED", "br-miss-rate");
+    let derived = Derived::from_config(inner, &config);
+    let mut names = Vec::new();
+    derived.names(&mut |x| names.push(x.to_string()));
+    assert_eq!(names, ["br-miss", "branch", "br-rate"]);
+}
@@ -0,0 +1,123 @@
+use super::{CounterMeta, CounterReading, CounterState, Counters};
+use crate::config::Config;
+use std::fs;
+
+const NAMES: [&str; 4] = ["read_bytes", "write_bytes", "syscr", "syscw"];
+
+/// Counters for the I/O of the process, read from `/proc/self/io`.
+///
+/// This reports the bytes read from and written to storage (`read_bytes`, `write_bytes`) and the number of read and write system calls (`syscr`, `syscw`).
+/// Reads served from the page cache do not count towards `read_bytes`, which allows correlating cache misses with actual disk traffic.
+/// Writes are counted when they are submitted to the page cache, not when they reach the disk.
+/// Usage is that of the whole process, so activity of other threads is included.
+/// Linux only.
+pub struct IoBackend {
+    state: CounterState,
+    /// Usage at the time the counters were enabled.
+    start: Option<[u64; 4]>,
+    total: [u64; 4],
+}
+
+impl IoBackend {
+    /// Creates a new backend.
+    ///
+    /// Returns `None` with a warning message to stderr if `/proc/self/io` cannot be read.
+    pub fn new() -> Option<Self> {
+        if let Err(e) = fs::read_to_string("/proc/self/io") {
+            eprintln!("failed to read /proc/self/io: {e}");
+            return None;
+        }
+        Some(IoBackend {
+            state: CounterState::default(),
+            start: None,
+            total: [0; 4],
+        })
+    }
+
+    /// Creates a backend if the environment variable `QPE_IO` is set to `1`.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        config.flag("IO").then(Self::new).flatten()
+    }
+
+    /// The usage since the last reset.
+    fn current(&self) -> [u64; 4] {
+        let mut total = self.total;
+        if let Some(start) = self.start {
+            let now = usage();
+            for i in 0..4 {
+                total[i] += now[i].saturating_sub(start[i]);
+            }
+        }
+        total
+    }
+}
+
+fn usage() -> [u64; 4] {
+    parse(&fs::read_to_string("/proc/self/io").unwrap_or_default())
+}
+
+/// Extracts the values of [`NAMES`] from the contents of `/proc/self/io`.
+fn parse(text: &str) -> [u64; 4] {
+    let mut values = [0; 4];
+    for line in text.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if let Some(i) = NAMES.iter().position(|x| *x == name) {
+            values[i] = value.trim().parse().unwrap_or(0);
+        }
+    }
+    values
+}
+
+impl Counters for IoBackend {
+    fn enable(&mut self) {
+        self.state.enable("IoBackend");
+        self.start = Some(usage());
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("IoBackend");
+        self.total = self.current();
+        self.start = None;
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("IoBackend");
+        self.total = [0; 4];
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        dst.extend(self.current().map(|value| CounterReading {
+            value: value as f64,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: true,
+        }));
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for name in NAMES {
+            dst(name);
+        }
+    }
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        for name in NAMES {
+            dst(match name {
+                "read_bytes" | "write_bytes" => CounterMeta::new().with_unit("B"),
+                _ => CounterMeta::new(),
+            });
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let text = "rchar: 100\nwchar: 50\nsyscr: 3\nsyscw: 2\nread_bytes: 4096\nwrite_bytes: 8192\ncancelled_write_bytes: 0\n";
+    assert_eq!(parse(text), [4096, 8192, 3, 2]);
+}
@@ -0,0 +1,73 @@
+use super::{CounterMeta, CounterReading, CounterState, Counters, NamedReading};
+use std::cell::{Cell, OnceCell};
+
+/// Counters that are only created when first used.
+///
+/// Opening perf counters costs system calls and file descriptors.
+/// Wrapping them in `Lazy` defers this until a measurement is taken, so instrumented code paths that never run cost nothing.
+pub struct Lazy<C> {
+    state: CounterState,
+    init: Cell<Option<Box<dyn FnOnce() -> C>>>,
+    counters: OnceCell<C>,
+}
+
+impl<C: Counters> Lazy<C> {
+    /// Creates counters that are constructed by `init` when first used.
+    pub fn new(init: impl FnOnce() -> C + 'static) -> Self {
+        Lazy {
+            state: CounterState::default(),
+            init: Cell::new(Some(Box::new(init))),
+            counters: OnceCell::new(),
+        }
+    }
+
+    fn get(&self) -> &C {
+        self.counters.get_or_init(|| (self.init.take().unwrap())())
+    }
+
+    fn get_mut(&mut self) -> &mut C {
+        self.get();
+        self.counters.get_mut().unwrap()
+    }
+}
+
+impl<C: Counters> Counters for Lazy<C> {
+    fn enable(&mut self) {
+        self.state.enable("Lazy");
+        self.get_mut().enable();
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("Lazy");
+        self.get_mut().disable();
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("Lazy");
+        self.get_mut().reset();
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        self.get_mut().read(dst);
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        self.get().names(dst);
+    }
+
+    fn read_named(&mut self, dst: &mut Vec<NamedReading>) {
+        self.get_mut().read_named(dst);
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.get().meta(dst);
+    }
+
+    fn recompute_derived(&self, readings: &mut [CounterReading]) {
+        self.get().recompute_derived(readings);
+    }
+
+    fn scale_unit(&self) -> Option<&str> {
+        self.get().scale_unit()
+    }
+}
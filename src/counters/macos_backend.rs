@@ -0,0 +1,120 @@
+use super::{CounterMeta, CounterReading, CounterState, Counters, event_meta, resolve_event_names};
+use crate::config::Config;
+use std::mem::MaybeUninit;
+
+/// The counters supported by [`MacosBackend`].
+pub(crate) const NAMES: [&str; 3] = ["t-clock", "pg-fault", "ctx-sw"];
+
+/// Counters for macOS, where perf events are not available.
+///
+/// This reports the CPU time spent in user and kernel mode in seconds as `t-clock`, the number of page faults as `pg-fault`, and the number of voluntary and involuntary context switches as `ctx-sw`.
+/// The names match those of the perf counters on Linux, so reports are comparable across platforms.
+/// Values are obtained using `getrusage`, so activity of other threads is included.
+/// Hardware counters like cycles and instructions require elevated privileges on macOS and are not supported.
+/// macOS only.
+pub struct MacosBackend {
+    state: CounterState,
+    /// Indices into [`NAMES`] of the recorded counters.
+    counters: Vec<usize>,
+    /// Usage at the time the counters were enabled.
+    start: Option<[f64; 3]>,
+    total: [f64; 3],
+}
+
+impl Default for MacosBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MacosBackend {
+    /// Creates a new instance recording the counters listed in `QPE_EVENTS`, or the default set if the variable is not defined.
+    ///
+    /// Requested counters that are not supported are skipped, see [`Degradation`](super::Degradation).
+    pub fn new() -> Self {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Self {
+        let events = config.get("EVENTS");
+        Self::with_counter_names(resolve_event_names(events.as_deref()))
+    }
+
+    /// Creates an instance recording the supported counters among `names`.
+    pub fn with_counter_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        MacosBackend {
+            state: CounterState::default(),
+            counters: names
+                .into_iter()
+                .filter_map(|name| NAMES.iter().position(|x| *x == name))
+                .collect(),
+            start: None,
+            total: [0.0; 3],
+        }
+    }
+
+    /// The usage since the last reset.
+    fn current(&self) -> [f64; 3] {
+        let mut total = self.total;
+        if let Some(start) = self.start {
+            let now = usage();
+            for i in 0..3 {
+                total[i] += now[i] - start[i];
+            }
+        }
+        total
+    }
+}
+
+fn usage() -> [f64; 3] {
+    let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+    let usage = unsafe {
+        libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr());
+        usage.assume_init()
+    };
+    let seconds = |x: libc::timeval| x.tv_sec as f64 + x.tv_usec as f64 * 1e-6;
+    [
+        seconds(usage.ru_utime) + seconds(usage.ru_stime),
+        (usage.ru_minflt + usage.ru_majflt) as f64,
+        (usage.ru_nvcsw + usage.ru_nivcsw) as f64,
+    ]
+}
+
+impl Counters for MacosBackend {
+    fn enable(&mut self) {
+        self.state.enable("MacosBackend");
+        self.start = Some(usage());
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("MacosBackend");
+        self.total = self.current();
+        self.start = None;
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("MacosBackend");
+        self.total = [0.0; 3];
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        let current = self.current();
+        dst.extend(self.counters.iter().map(|&i| CounterReading {
+            value: current[i],
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: true,
+        }));
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for &i in &self.counters {
+            dst(NAMES[i]);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.names(&mut |name| dst(event_meta(name)));
+    }
+}
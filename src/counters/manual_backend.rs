@@ -1,3 +1,4 @@
+use crate::config::Config;
 use std::time::Duration;
 
 use crate::counters::Counters;
@@ -21,7 +22,11 @@ pub struct ManualBackend {
 
 impl ManualBackend {
     pub fn from_env() -> Option<Self> {
-        let var = std::env::var("QPE_MANUAL").ok()?;
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let var = config.get("MANUAL")?;
         let duration = if var == "read" {
             None
         } else {
@@ -31,7 +36,7 @@ impl ManualBackend {
         };
         Some(ManualBackend {
             duration,
-            marker: std::env::var("QPE_MANUAL_MARKER").unwrap_or_default(),
+            marker: config.get("MANUAL_MARKER").unwrap_or_default(),
         })
     }
 
@@ -0,0 +1,261 @@
+use super::{CounterMeta, CounterReading, CounterState, Counters, Direction};
+use crate::config::Config;
+use std::{
+    ffi::{CStr, c_char, c_int, c_uint, c_ulonglong, c_void},
+    mem,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+const NAMES: [&str; 3] = ["gpu-util", "gpu-mem-util", "gpu-energy"];
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// GPU counters obtained from the NVIDIA Management Library (NVML).
+///
+/// This is intended for benchmarks offloading work to CUDA devices.
+/// It reports the mean GPU utilization (`gpu-util`) and memory controller utilization (`gpu-mem-util`) in percent, and the energy consumed by the GPU in joules (`gpu-energy`).
+/// Memory controller utilization is the fraction of time device memory was read or written, which indicates how much of the memory bandwidth was used.
+/// While counters are enabled, a background thread samples the utilization periodically.
+/// NVML itself averages utilization over a period of up to a second, so short measurements should be repeated or scaled up.
+/// The utilization columns are [gauges](CounterMeta::gauge), as they are averages rather than amounts.
+/// Energy is only supported on Volta and newer GPUs, otherwise it is reported as not [valid](CounterReading::valid).
+///
+/// `libnvidia-ml.so.1` is loaded at runtime, so no CUDA toolkit is required to build.
+/// Linux only, requires the `nvml` feature.
+pub struct NvmlBackend {
+    counter_state: CounterState,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    nvml: Nvml,
+    enabled: bool,
+    samples: usize,
+    /// Sums of the sampled GPU and memory utilization.
+    utilization: [f64; 2],
+    /// Energy counter in millijoules at the time the counters were enabled.
+    energy_start: Option<f64>,
+    energy: f64,
+}
+
+impl NvmlBackend {
+    /// Creates a backend for the GPU with the given index.
+    ///
+    /// Returns `None` with a warning message to stderr if NVML cannot be loaded or the device cannot be opened.
+    pub fn new(device: u32) -> Option<Self> {
+        let nvml = Nvml::load(device)
+            .map_err(|e| eprintln!("failed to initialize NVML: {e}"))
+            .ok()?;
+        let state = Arc::new(Mutex::new(State {
+            nvml,
+            enabled: false,
+            samples: 0,
+            utilization: [0.0; 2],
+            energy_start: None,
+            energy: 0.0,
+        }));
+        let weak = Arc::downgrade(&state);
+        thread::spawn(move || sample_periodically(weak));
+        Some(NvmlBackend {
+            counter_state: CounterState::default(),
+            state,
+        })
+    }
+
+    /// Creates a backend for the GPU index in the environment variable `QPE_NVML`, e.g. `0`.
+    ///
+    /// Returns `None` if the variable is not set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        Self::new(config.parse("NVML")?)
+    }
+}
+
+fn sample_periodically(state: Weak<Mutex<State>>) {
+    loop {
+        thread::sleep(SAMPLE_INTERVAL);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        if state.enabled {
+            state.sample();
+        }
+    }
+}
+
+impl State {
+    fn sample(&mut self) {
+        if let Some([gpu, memory]) = self.nvml.utilization() {
+            self.utilization[0] += gpu;
+            self.utilization[1] += memory;
+            self.samples += 1;
+        }
+    }
+
+    /// The energy consumed since the last reset.
+    fn energy(&self) -> f64 {
+        match self.energy_start {
+            Some(start) => self.energy + self.nvml.energy() - start,
+            None => self.energy,
+        }
+    }
+}
+
+impl Counters for NvmlBackend {
+    fn enable(&mut self) {
+        self.counter_state.enable("NvmlBackend");
+        let mut state = self.state.lock().unwrap();
+        state.energy_start = Some(state.nvml.energy());
+        state.enabled = true;
+    }
+
+    fn disable(&mut self) {
+        self.counter_state.disable("NvmlBackend");
+        let mut state = self.state.lock().unwrap();
+        // Ensure short measurements get at least one sample.
+        state.sample();
+        state.energy = state.energy();
+        state.energy_start = None;
+        state.enabled = false;
+    }
+
+    fn reset(&mut self) {
+        self.counter_state.reset("NvmlBackend");
+        let mut state = self.state.lock().unwrap();
+        state.samples = 0;
+        state.utilization = [0.0; 2];
+        state.energy = 0.0;
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        let state = self.state.lock().unwrap();
+        let energy = state.energy() * 1e-3;
+        let values = [
+            state.utilization[0] / state.samples as f64,
+            state.utilization[1] / state.samples as f64,
+            energy,
+        ];
+        dst.extend(
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| CounterReading {
+                    value,
+                    multiplexed: false,
+                    running: 1.0,
+                    enable_scale: i == 2,
+                    valid: !value.is_nan(),
+                }),
+        );
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for name in NAMES {
+            dst(name);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        dst(CounterMeta::new()
+            .with_direction(Direction::Neutral)
+            .with_unit("%")
+            .with_gauge(true));
+        dst(CounterMeta::new()
+            .with_direction(Direction::Neutral)
+            .with_unit("%")
+            .with_gauge(true));
+        dst(CounterMeta::new().with_unit("J"));
+    }
+}
+
+type Device = *mut c_void;
+
+#[repr(C)]
+struct Utilization {
+    gpu: c_uint,
+    memory: c_uint,
+}
+
+/// Functions of a dynamically loaded NVML library, bound to a device.
+struct Nvml {
+    device: Device,
+    get_utilization: unsafe extern "C" fn(Device, *mut Utilization) -> c_int,
+    get_energy: unsafe extern "C" fn(Device, *mut c_ulonglong) -> c_int,
+    shutdown: unsafe extern "C" fn() -> c_int,
+}
+
+// NVML functions are thread safe.
+unsafe impl Send for Nvml {}
+
+/// Looks up a function in a library opened using `dlopen`.
+///
+/// `F` must be a function pointer type matching the signature of the function.
+unsafe fn symbol<F: Copy>(lib: *mut c_void, name: &CStr) -> Result<F, String> {
+    let ptr = unsafe { libc::dlsym(lib, name.as_ptr()) };
+    if ptr.is_null() {
+        return Err(format!("symbol {name:?} not found"));
+    }
+    Ok(unsafe { mem::transmute_copy::<*mut c_void, F>(&ptr) })
+}
+
+impl Nvml {
+    fn load(index: u32) -> Result<Self, String> {
+        unsafe {
+            let lib = libc::dlopen(c"libnvidia-ml.so.1".as_ptr(), libc::RTLD_NOW);
+            if lib.is_null() {
+                return Err("libnvidia-ml.so.1 not found".to_string());
+            }
+            let init: unsafe extern "C" fn() -> c_int = symbol(lib, c"nvmlInit_v2")?;
+            let get_handle: unsafe extern "C" fn(c_uint, *mut Device) -> c_int =
+                symbol(lib, c"nvmlDeviceGetHandleByIndex_v2")?;
+            let error_string: unsafe extern "C" fn(c_int) -> *const c_char =
+                symbol(lib, c"nvmlErrorString")?;
+            let check = |result: c_int| {
+                if result == 0 {
+                    Ok(())
+                } else {
+                    Err(CStr::from_ptr(error_string(result))
+                        .to_string_lossy()
+                        .into_owned())
+                }
+            };
+            let mut nvml = Nvml {
+                device: std::ptr::null_mut(),
+                get_utilization: symbol(lib, c"nvmlDeviceGetUtilizationRates")?,
+                get_energy: symbol(lib, c"nvmlDeviceGetTotalEnergyConsumption")?,
+                shutdown: symbol(lib, c"nvmlShutdown")?,
+            };
+            check(init())?;
+            check(get_handle(index, &mut nvml.device))
+                .map_err(|e| format!("failed to open GPU {index}: {e}"))?;
+            Ok(nvml)
+        }
+    }
+
+    fn utilization(&self) -> Option<[f64; 2]> {
+        let mut utilization = Utilization { gpu: 0, memory: 0 };
+        let result = unsafe { (self.get_utilization)(self.device, &mut utilization) };
+        (result == 0).then_some([utilization.gpu as f64, utilization.memory as f64])
+    }
+
+    /// Returns the energy consumed since the driver was loaded in millijoules, or NaN if not supported.
+    fn energy(&self) -> f64 {
+        let mut energy = 0;
+        let result = unsafe { (self.get_energy)(self.device, &mut energy) };
+        if result == 0 { energy as f64 } else { f64::NAN }
+    }
+}
+
+impl Drop for Nvml {
+    fn drop(&mut self) {
+        unsafe {
+            (self.shutdown)();
+        }
+    }
+}
@@ -1,10 +1,26 @@
-use super::{CounterReading, Counters};
+use super::{
+    CounterMeta, CounterReading, CounterState, Counters, column_name, event_meta, parse_modifiers,
+    resolve_event_names,
+};
+use crate::config::Config;
+#[cfg(target_arch = "x86_64")]
+use crate::perf_sys::UserPage;
 use perf_event::{
     Builder, Counter,
     events::{Cache, CacheId, CacheOp, CacheResult, Hardware, Software},
 };
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    mem,
+    os::fd::{AsRawFd, RawFd},
+    sync::{Arc, Mutex, Weak},
+    thread::{self, ThreadId},
+};
 
-/// A [`CounterBackend`] containing [`perf_event`] counters.
+/// A [`Counters`] implementation containing [`perf_event`] counters.
 ///
 /// Note that this crate uses the `perf-event` crate from the `perf-event2` package, not the `perf-event` package.
 ///
@@ -12,20 +28,45 @@ use perf_event::{
 /// This means that the provided counters might not all run for the exact same duration due to multiplexing performed by the kernel.
 /// See [perf_event] documentation for more details.
 /// You may provide your own set of counters using [`with_counters`](Self::with_counters).
+///
+/// Reading a counter is retried if it fails with a transient error such as `EINTR`.
+/// If it still fails, a warning is printed to stderr and the reading is marked as not [`valid`](CounterReading::valid).
 pub struct PerfBackend {
+    state: CounterState,
     counters: Vec<(Option<String>, Counter, f64)>,
+    /// Counters opened on every CPU, recorded after `counters`.
+    per_cpu: Vec<PerCpuCounter>,
+    /// Counters shared with other instances, recorded after `counters`.
+    shared: Vec<SharedCounter>,
+}
+
+impl Default for PerfBackend {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PerfBackend {
     /// Creates a new [`PerfBackend`] instance using counters listed in `QPE_EVENTS`
     /// or the default set if the variable is not defined.
+    ///
+    /// If any name in `QPE_EVENTS` is prefixed with `+` or `-`, the list modifies the default set instead of replacing it.
+    /// For example, `-kcycle,+instr` removes `kcycle` and adds `instr` unless it is already included.
     pub fn new() -> Self {
-        let events = std::env::var("QPE_EVENTS");
-        let events = events
-            .as_deref()
-            .unwrap_or("cycle,kcycle,instr,l1-miss,llc-miss,br-miss,t-clock")
-            .split(",");
-        Self::with_counter_names(events)
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Self {
+        let events = config.get("EVENTS");
+        let events = resolve_event_names(events.as_deref());
+        if config.flag("PER_CPU") {
+            Self::with_per_cpu_counter_names(events.iter().copied())
+        } else if config.flag("SHARE_COUNTERS") {
+            Self::with_shared_counter_names(events.iter().copied())
+        } else {
+            Self::with_counter_names(events.iter().copied())
+                .with_continuous(config.flag("CONTINUOUS"))
+        }
     }
 
     /// Builds a [`PerfBackend`] instance from a list of event names.
@@ -34,52 +75,112 @@ impl PerfBackend {
     /// They are aliases for counter configurations defined by this crate.
     /// The names are chosen to fit in the output format table without line-wrapping.
     ///
+    /// A name may be followed by `perf`-style modifiers after a colon, e.g. `cycle:u` or `instr:ukp`:
+    /// - `u` counts events in user space and `k` counts events in the kernel.
+    ///   If either is given, the other privilege level is excluded, otherwise the default of the event applies, which is user space only except for `kcycle`.
+    /// - `p` pins the counter.
+    ///   The kernel always keeps pinned counters on the PMU instead of multiplexing them with other counters.
+    ///   If a pinned counter cannot be scheduled, it stops counting and its readings are marked as not [`valid`](CounterReading::valid).
+    ///
+    /// Modifiers other than `p` are part of the column name, e.g. `cycle:k`.
+    ///
     /// Invalid names and counters that cannot be opened (e.g. due to permission issues) are skipped with a warning message to stderr.
     pub fn with_counter_names<'a>(counters: impl IntoIterator<Item = &'a str>) -> Self {
-        let counters = counters
+        let counters: Vec<_> = counters
             .into_iter()
             .filter_map(|name| {
-                let mut scale = 1.0;
-
-                // Keep this clean. Users are expected to read this match statement
-                // to discover available counter names.
-                let mut builder = match name {
-                    "cycle" => Builder::new(Hardware::CPU_CYCLES),
-                    "kcycle" => {
-                        let mut builder = Builder::new(Hardware::CPU_CYCLES);
-                        builder.exclude_user(true).exclude_kernel(false);
-                        builder
-                    }
-                    "instr" => Builder::new(Hardware::INSTRUCTIONS),
-                    "l1-miss" => Builder::new(Cache {
-                        which: CacheId::L1D,
-                        operation: CacheOp::READ,
-                        result: CacheResult::MISS,
-                    }),
-                    "llc-miss" => Builder::new(Hardware::CACHE_MISSES),
-                    "br-miss" => Builder::new(Hardware::BRANCH_MISSES),
-                    "t-clock" => {
-                        // time is reported by the kernel in nanoseconds, we convert to seconds.
-                        scale = 1.0e-9;
-                        Builder::new(Software::TASK_CLOCK)
-                    }
-                    _ => {
-                        eprintln!("invalid counter name: {name:?}");
-                        return None;
-                    }
-                };
-                builder.inherit(true);
-                match builder.build() {
-                    Err(e) => {
-                        eprintln!("failed to create counter {name:?}: {e}");
-                        None
-                    }
-                    Ok(counter) => Some((Some(name.to_string()), counter, scale)),
+                let (counter, scale) = open(name, None, true)?;
+                Some((Some(column_name(name)), counter, scale))
+            })
+            .collect();
+        Self::with_counters(counters)
+    }
+
+    /// Like [`with_counter_names`](Self::with_counter_names), but pins all counters.
+    ///
+    /// This is equivalent to adding the `p` modifier to each name.
+    pub fn with_pinned_counter_names<'a>(counters: impl IntoIterator<Item = &'a str>) -> Self {
+        let names: Vec<String> = counters
+            .into_iter()
+            .map(|name| {
+                if name.contains(':') {
+                    format!("{name}p")
+                } else {
+                    format!("{name}:p")
                 }
             })
             .collect();
-        PerfBackend { counters }
+        Self::with_counter_names(names.iter().map(String::as_str))
+    }
+
+    /// Like [`with_counter_names`](Self::with_counter_names), but shares counters with other instances created this way.
+    ///
+    /// The PMU has a limited number of counter slots, so opening the same events for multiple instances causes multiplexing.
+    /// Instead, each event is opened once per thread and kept enabled while any instance uses it.
+    /// Each instance tracks the difference between readings taken when it is enabled and disabled.
+    /// Counters are only shared between instances created on the same thread, since they count events of the thread that opened them.
+    /// Unlike other counters, they do not count threads spawned by it.
+    ///
+    /// On x86-64, shared counters are read from user space using `rdpmc` where the kernel allows it, avoiding the system call for reading.
+    /// This only applies to reads on the thread that opened the counters, other threads fall back to system calls.
+    /// Counters opened by [`with_counter_names`](Self::with_counter_names) are not read using `rdpmc`, since it misses the events of spawned threads.
+    pub fn with_shared_counter_names<'a>(counters: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut backend = Self::with_counters([]);
+        backend.shared = counters
+            .into_iter()
+            .filter_map(|name| {
+                Some(SharedCounter {
+                    name: column_name(name),
+                    shared: open_shared(name)?,
+                    enabled: false,
+                    start: [0; 3],
+                    total: [0; 3],
+                    valid: true,
+                })
+            })
+            .collect();
+        backend
+    }
+
+    /// Like [`with_counter_names`](Self::with_counter_names), but counts events of all processes on every online CPU and reports their sum.
+    ///
+    /// Counters opened for the calling thread only count threads and processes it spawns after the counters are opened.
+    /// This also captures work offloaded to threads or processes that already exist, like a thread pool created beforehand, at the cost of including unrelated activity on the system.
+    /// Opening system-wide counters usually requires `CAP_PERFMON` or `perf_event_paranoid` set to `0` or less.
+    /// If a counter cannot be opened on every CPU, a warning is printed to stderr and it is opened for the calling thread instead.
+    /// For a default instance, this is enabled by setting `QPE_PER_CPU` to `1`.
+    pub fn with_per_cpu_counter_names<'a>(counters: impl IntoIterator<Item = &'a str>) -> Self {
+        let cpus = fs::read_to_string("/sys/devices/system/cpu/online")
+            .ok()
+            .and_then(|x| super::cgroup::parse_cpu_list(&x));
+        if cpus.is_none() {
+            eprintln!("failed to read the list of online CPUs");
+        }
+        let mut backend = Self::with_counters([]);
+        backend.per_cpu = counters
+            .into_iter()
+            .filter_map(|name| {
+                // Opening the counter for this thread first reports invalid names only once.
+                let (counter, scale) = open(name, None, true)?;
+                let per_cpu = cpus.as_ref().and_then(|cpus| {
+                    cpus.iter()
+                        .map(|&cpu| Some(open(name, Some(cpu), false)?.0))
+                        .collect::<Option<Vec<_>>>()
+                });
+                let counters = per_cpu.unwrap_or_else(|| {
+                    eprintln!("counting {name:?} for this process only");
+                    vec![counter]
+                });
+                Some(PerCpuCounter {
+                    name: column_name(name),
+                    counters,
+                    scale,
+                })
+            })
+            .collect();
+        backend
     }
+
     /// Constructs a [`PerfBackend`] instance from a set of counters.
     ///
     /// Each counter may be associated with a name.
@@ -92,49 +193,440 @@ impl PerfBackend {
         counters: impl IntoIterator<Item = (Option<String>, Counter, f64)>,
     ) -> Self {
         PerfBackend {
+            state: CounterState::default(),
             counters: counters.into_iter().collect(),
+            per_cpu: Vec::new(),
+            shared: Vec::new(),
+        }
+    }
+
+    /// Keep counters enabled continuously, computing the values of each measurement from readings taken when enabling and disabling.
+    ///
+    /// This replaces the system calls for enabling, disabling, and resetting each counter with a single read.
+    /// It reduces the overhead of harnesses recording many tiny measurements, at the cost of a little precision, as more of the overhead of reading counters is included in the measurement.
+    /// Counters opened by [`with_shared_counter_names`](Self::with_shared_counter_names) are always continuous.
+    /// Unnamed counters are not affected.
+    ///
+    /// For a default instance, this is enabled by setting `QPE_CONTINUOUS` to `1`.
+    pub fn with_continuous(mut self, continuous: bool) -> Self {
+        if !continuous {
+            return self;
+        }
+        let (named, unnamed) = mem::take(&mut self.counters)
+            .into_iter()
+            .partition::<Vec<_>, _>(|x| x.0.is_some());
+        self.counters = unnamed;
+        let continuous = named.into_iter().map(|(name, mut counter, scale)| {
+            counter
+                .enable()
+                .unwrap_or_else(|e| failed("enable", name.as_deref(), e));
+            SharedCounter {
+                name: name.unwrap(),
+                shared: Arc::new(Shared::new(counter, scale, false)),
+                enabled: false,
+                start: [0; 3],
+                total: [0; 3],
+                valid: true,
+            }
+        });
+        self.shared = continuous.chain(mem::take(&mut self.shared)).collect();
+        self
+    }
+
+    /// Returns the name and file descriptor of each counter.
+    ///
+    /// This allows code across an FFI boundary, such as a C library with its own measurement hooks, to control the same counters.
+    /// It may disable and re-enable counters around its internal phases using the `PERF_EVENT_IOC_DISABLE` and `PERF_EVENT_IOC_ENABLE` ioctls.
+    /// Counts accumulated while enabled are attributed to the reading being recorded.
+    /// Counters must be returned to their previous state before control returns to this crate.
+    /// The file descriptors are owned by this backend and must not be closed.
+    /// Shared and [continuous](Self::with_continuous) counters are always enabled, disabling shared counters affects all instances using them.
+    /// [Per-CPU](Self::with_per_cpu_counter_names) counters are returned once per CPU.
+    pub fn raw_fds(&self) -> impl Iterator<Item = (Option<&str>, RawFd)> {
+        let owned = self
+            .counters
+            .iter()
+            .map(|(name, counter, _)| (name.as_deref(), counter.as_raw_fd()));
+        let per_cpu = self.per_cpu.iter().flat_map(|x| {
+            x.counters
+                .iter()
+                .map(|counter| (Some(x.name.as_str()), counter.as_raw_fd()))
+        });
+        let shared = self.shared.iter().map(|x| {
+            (
+                Some(x.name.as_str()),
+                x.shared.counter.lock().unwrap().as_raw_fd(),
+            )
+        });
+        owned.chain(per_cpu).chain(shared)
+    }
+}
+
+/// Opens the counter named `name`, returning it with the scale applied to its values.
+///
+/// If `cpu` is `None`, the counter counts events of the calling thread, and threads and processes it spawns if `inherit` is set.
+/// Otherwise, it counts events of all processes on that CPU.
+fn open(name: &str, cpu: Option<usize>, inherit: bool) -> Option<(Counter, f64)> {
+    let mut scale = 1.0;
+    let (event, modifiers) = parse_modifiers(name);
+    let modifiers = match modifiers {
+        Ok(modifiers) => modifiers,
+        Err(modifier) => {
+            eprintln!("invalid modifier {modifier:?} in counter name {name:?}");
+            return None;
+        }
+    };
+
+    // Keep this clean. Users are expected to read this match statement
+    // to discover available counter names.
+    let mut builder = match event {
+        "cycle" => Builder::new(Hardware::CPU_CYCLES),
+        "kcycle" => {
+            let mut builder = Builder::new(Hardware::CPU_CYCLES);
+            builder.exclude_user(true).exclude_kernel(false);
+            builder
+        }
+        "instr" => Builder::new(Hardware::INSTRUCTIONS),
+        "l1-miss" => Builder::new(Cache {
+            which: CacheId::L1D,
+            operation: CacheOp::READ,
+            result: CacheResult::MISS,
+        }),
+        "l1i-miss" => Builder::new(Cache {
+            which: CacheId::L1I,
+            operation: CacheOp::READ,
+            result: CacheResult::MISS,
+        }),
+        "l1-wmiss" => Builder::new(Cache {
+            which: CacheId::L1D,
+            operation: CacheOp::WRITE,
+            result: CacheResult::MISS,
+        }),
+        "llc-miss" => Builder::new(Hardware::CACHE_MISSES),
+        "branch" => Builder::new(Hardware::BRANCH_INSTRUCTIONS),
+        "br-miss" => Builder::new(Hardware::BRANCH_MISSES),
+        "stall-fe" => Builder::new(Hardware::STALLED_CYCLES_FRONTEND),
+        "stall-be" => Builder::new(Hardware::STALLED_CYCLES_BACKEND),
+        "t-clock" => {
+            // time is reported by the kernel in nanoseconds, we convert to seconds.
+            scale = 1.0e-9;
+            Builder::new(Software::TASK_CLOCK)
+        }
+        "pg-fault" => Builder::new(Software::PAGE_FAULTS),
+        "ctx-sw" => Builder::new(Software::CONTEXT_SWITCHES),
+        "cpu-mig" => Builder::new(Software::CPU_MIGRATIONS),
+        _ => {
+            eprintln!("invalid counter name: {name:?}");
+            return None;
+        }
+    };
+    match cpu {
+        None => builder.inherit(inherit),
+        Some(cpu) => builder.any_pid().one_cpu(cpu),
+    };
+    if modifiers.privilege() {
+        builder
+            .exclude_user(!modifiers.user)
+            .exclude_kernel(!modifiers.kernel);
+    }
+    builder.pinned(modifiers.pinned);
+    match builder.build() {
+        Err(e) => {
+            match cpu {
+                None => eprintln!("failed to create counter {name:?}: {e}"),
+                Some(cpu) => eprintln!("failed to create counter {name:?} on CPU {cpu}: {e}"),
+            }
+            None
+        }
+        Ok(counter) => Some((counter, scale)),
+    }
+}
+
+/// A counter kept enabled while any instance uses it.
+///
+/// Counters opened by [`PerfBackend::with_shared_counter_names`] are registered in [`SHARED`] to be used by multiple instances.
+/// [Continuous](PerfBackend::with_continuous) counters are used by a single instance.
+struct Shared {
+    counter: Mutex<Counter>,
+    scale: f64,
+    /// The thread measured by the counter.
+    thread: ThreadId,
+    /// The metadata page for reading the counter from user space, if supported.
+    #[cfg(target_arch = "x86_64")]
+    page: Option<UserPage>,
+}
+
+// The page is only read by `user_snapshot`, which checks that it runs on the measured thread.
+#[cfg(target_arch = "x86_64")]
+unsafe impl Send for Shared {}
+#[cfg(target_arch = "x86_64")]
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    /// If `user_read` is set, the counter is read from user space where supported.
+    ///
+    /// This must only be set for counters that are not inherited, as `rdpmc` misses the events of child threads.
+    #[cfg_attr(not(target_arch = "x86_64"), allow(unused_variables))]
+    fn new(counter: Counter, scale: f64, user_read: bool) -> Self {
+        Shared {
+            #[cfg(target_arch = "x86_64")]
+            page: user_read.then(|| UserPage::new(&counter).ok()).flatten(),
+            counter: Mutex::new(counter),
+            scale,
+            thread: thread::current().id(),
+        }
+    }
+
+    /// Reads the counter from user space if possible.
+    ///
+    /// This requires running on the measured thread, as `rdpmc` reads the hardware counter of the current CPU.
+    fn user_snapshot(&self) -> Option<Snapshot> {
+        if self.thread != thread::current().id() {
+            return None;
+        }
+        #[cfg(target_arch = "x86_64")]
+        return self.page.as_ref()?.snapshot();
+        #[cfg(not(target_arch = "x86_64"))]
+        return None;
+    }
+}
+
+thread_local! {
+    /// Shared counters opened on this thread, by name.
+    static SHARED: RefCell<HashMap<String, Weak<Shared>>> = RefCell::new(HashMap::new());
+}
+
+fn open_shared(name: &str) -> Option<Arc<Shared>> {
+    SHARED.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if let Some(shared) = registry.get(name).and_then(Weak::upgrade) {
+            return Some(shared);
+        }
+        let (mut counter, scale) = open(name, None, false)?;
+        counter
+            .enable()
+            .unwrap_or_else(|e| failed("enable", Some(name), e));
+        let shared = Arc::new(Shared::new(counter, scale, true));
+        registry.insert(name.to_string(), Arc::downgrade(&shared));
+        Some(shared)
+    })
+}
+
+/// The count, time enabled, and time running in nanoseconds.
+type Snapshot = [u64; 3];
+
+/// Number of attempts at reading a counter if reading fails with a transient error.
+const READ_ATTEMPTS: usize = 3;
+
+/// Reads a counter, retrying on transient errors.
+///
+/// Returns `None` with a warning message to stderr if reading fails.
+fn read_snapshot(counter: &mut Counter, name: Option<&str>) -> Option<Snapshot> {
+    let mut attempt = 1;
+    loop {
+        match counter.read_full() {
+            Ok(reading) => {
+                return Some([
+                    reading.count(),
+                    reading.time_enabled().unwrap().as_nanos() as u64,
+                    reading.time_running().unwrap().as_nanos() as u64,
+                ]);
+            }
+            Err(e)
+                if attempt < READ_ATTEMPTS
+                    && matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) =>
+            {
+                attempt += 1;
+            }
+            Err(e) => {
+                let name = name.unwrap_or("<unnamed>");
+                eprintln!("PerfBackend: failed to read counter {name:?}: {e}");
+                return None;
+            }
+        }
+    }
+}
+
+/// Converts a snapshot to a reading, scaling the count to the time enabled to compensate for multiplexing.
+fn to_reading(snapshot: Option<Snapshot>, scale: f64) -> CounterReading {
+    match snapshot {
+        Some([count, enabled, running]) => CounterReading {
+            value: count as f64 * scale * enabled as f64 / running as f64,
+            multiplexed: enabled != running,
+            running: if enabled == 0 {
+                1.0
+            } else {
+                running as f64 / enabled as f64
+            },
+            enable_scale: true,
+            valid: true,
+        },
+        None => CounterReading {
+            value: f64::NAN,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: false,
+        },
+    }
+}
+
+/// The use of a [`Shared`] counter by one instance, tracking the differences between readings when enabled and disabled.
+struct SharedCounter {
+    name: String,
+    shared: Arc<Shared>,
+    enabled: bool,
+    /// The snapshot taken when this instance was last enabled.
+    start: Snapshot,
+    /// The accumulated differences of previous enabled periods.
+    total: Snapshot,
+    /// False if reading the counter failed since the last reset.
+    valid: bool,
+}
+
+impl SharedCounter {
+    fn snapshot(&mut self) -> Option<Snapshot> {
+        if let Some(snapshot) = self.shared.user_snapshot() {
+            return Some(snapshot);
+        }
+        let snapshot = read_snapshot(&mut self.shared.counter.lock().unwrap(), Some(&self.name));
+        self.valid &= snapshot.is_some();
+        snapshot
+    }
+
+    /// Returns the accumulated differences, including the current enabled period.
+    fn value(&mut self) -> Option<Snapshot> {
+        let mut value = self.total;
+        if self.enabled {
+            let now = self.snapshot()?;
+            for i in 0..3 {
+                value[i] += now[i].wrapping_sub(self.start[i]);
+            }
+        }
+        self.valid.then_some(value)
+    }
+}
+
+/// A counter opened on each CPU, see [`PerfBackend::with_per_cpu_counter_names`].
+struct PerCpuCounter {
+    name: String,
+    counters: Vec<Counter>,
+    scale: f64,
+}
+
+impl PerCpuCounter {
+    /// Returns the sum of the readings on all CPUs.
+    fn read(&mut self) -> CounterReading {
+        let mut sum = CounterReading {
+            value: 0.0,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: true,
+        };
+        for counter in &mut self.counters {
+            let snapshot = read_snapshot(counter, Some(&self.name));
+            // Counters on CPUs that went offline may never have run.
+            if snapshot.is_some_and(|x| x[2] == 0) {
+                continue;
+            }
+            let reading = to_reading(snapshot, self.scale);
+            sum.value += reading.value;
+            sum.multiplexed |= reading.multiplexed;
+            sum.running = sum.running.min(reading.running);
+            sum.valid &= reading.valid;
+        }
+        sum
+    }
+
+    fn apply(&mut self, operation: &str, f: fn(&mut Counter) -> std::io::Result<()>) {
+        for counter in &mut self.counters {
+            f(counter).unwrap_or_else(|e| failed(operation, Some(&self.name), e));
         }
     }
 }
 
 impl Counters for PerfBackend {
     fn enable(&mut self) {
-        for x in &mut self.counters {
-            x.1.enable().unwrap();
+        self.state.enable("PerfBackend");
+        for (name, counter, _) in &mut self.counters {
+            counter
+                .enable()
+                .unwrap_or_else(|e| failed("enable", name.as_deref(), e));
+        }
+        for counter in &mut self.per_cpu {
+            counter.apply("enable", Counter::enable);
+        }
+        for counter in &mut self.shared {
+            counter.start = counter.snapshot().unwrap_or_default();
+            counter.enabled = true;
         }
     }
 
     fn disable(&mut self) {
-        for x in &mut self.counters {
-            x.1.disable().unwrap();
+        self.state.disable("PerfBackend");
+        for counter in &mut self.shared {
+            if let Some(value) = counter.value() {
+                counter.total = value;
+            }
+            counter.enabled = false;
+        }
+        for counter in &mut self.per_cpu {
+            counter.apply("disable", Counter::disable);
+        }
+        for (name, counter, _) in &mut self.counters {
+            counter
+                .disable()
+                .unwrap_or_else(|e| failed("disable", name.as_deref(), e));
         }
     }
 
     fn reset(&mut self) {
-        for x in &mut self.counters {
-            x.1.reset().unwrap();
+        self.state.reset("PerfBackend");
+        for (name, counter, _) in &mut self.counters {
+            counter
+                .reset()
+                .unwrap_or_else(|e| failed("reset", name.as_deref(), e));
+        }
+        for counter in &mut self.per_cpu {
+            counter.apply("reset", Counter::reset);
+        }
+        for counter in &mut self.shared {
+            counter.total = [0; 3];
+            counter.valid = true;
         }
     }
 
     fn read(&mut self, dst: &mut Vec<CounterReading>) {
         dst.extend(self.counters.iter_mut().filter(|x| x.0.is_some()).map(
-            |(_, counter, scale)| {
-                let reading = counter.read_full().unwrap();
-                CounterReading {
-                    value: reading.count() as f64
-                        * *scale
-                        * reading.time_enabled().unwrap().as_secs_f64()
-                        / reading.time_running().unwrap().as_secs_f64(),
-                    multiplexed: reading.time_enabled() != reading.time_running(),
-                    enable_scale: true,
-                }
-            },
+            |(name, counter, scale)| to_reading(read_snapshot(counter, name.as_deref()), *scale),
         ));
+        dst.extend(self.per_cpu.iter_mut().map(PerCpuCounter::read));
+        dst.extend(
+            self.shared
+                .iter_mut()
+                .map(|counter| to_reading(counter.value(), counter.shared.scale)),
+        );
     }
 
     fn names(&self, dst: &mut dyn FnMut(&str)) {
         for name in self.counters.iter().filter_map(|x| x.0.as_ref()) {
             dst(name);
         }
+        for counter in &self.per_cpu {
+            dst(&counter.name);
+        }
+        for counter in &self.shared {
+            dst(&counter.name);
+        }
     }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        self.names(&mut |name| dst(event_meta(name)));
+    }
+}
+
+fn failed(operation: &str, name: Option<&str>, error: std::io::Error) -> ! {
+    let name = name.unwrap_or("<unnamed>");
+    panic!("PerfBackend: failed to {operation} counter {name:?}: {error}")
 }
@@ -0,0 +1,471 @@
+use super::{CounterMeta, CounterReading, Counters, Direction};
+
+/// A fixed set of readings computed from one or more measurements, presented as counters.
+///
+/// This is used to report readings derived from multiple measurements, such as statistics over repeated runs.
+/// Enabling, disabling, and resetting have no effect.
+pub(crate) struct Replay {
+    names: Vec<String>,
+    meta: Vec<CounterMeta>,
+    readings: Vec<CounterReading>,
+    scale_unit: Option<String>,
+}
+
+impl Replay {
+    /// Computes mean, sample standard deviation, minimum, maximum, and percentiles of each counter across samples.
+    ///
+    /// For each counter `x`, this reports the columns `x` (mean), `x-sd`, `x-min`, `x-max`, followed by one column per percentile (e.g. `x-p50`).
+    /// [Gauge](CounterMeta::gauge) counters only report the column `x`, containing the reading of the last sample.
+    /// The number of [outlier](outliers) samples is reported in an additional column `outliers`.
+    /// If `exclude_outliers` is set, outliers do not contribute to the statistics.
+    /// Each sample must contain the readings of `counters` in order.
+    pub fn summary(
+        counters: &dyn Counters,
+        samples: &[Vec<CounterReading>],
+        percentiles: &[f64],
+        exclude_outliers: bool,
+    ) -> Self {
+        let mut this = Self::empty();
+        let mut meta = Vec::new();
+        counters.meta(&mut |x| meta.push(x));
+        let outliers = outliers(samples, &meta);
+        let num_outliers = outliers.iter().filter(|x| **x).count();
+        let all_samples = samples;
+        let samples: Vec<&Vec<CounterReading>> = if exclude_outliers {
+            all_samples
+                .iter()
+                .zip(&outliers)
+                .filter(|x| !x.1)
+                .map(|x| x.0)
+                .collect()
+        } else {
+            all_samples.iter().collect()
+        };
+        let n = samples.len() as f64;
+        let mut i = 0;
+        counters.names(&mut |name| {
+            if meta[i].gauge() {
+                if let Some(last) = samples.last() {
+                    this.push(name.to_string(), meta[i], last[i].clone());
+                }
+                i += 1;
+                return;
+            }
+            let values = || samples.iter().map(|x| x[i].value);
+            let mean = values().sum::<f64>() / n;
+            let variance = if samples.len() > 1 {
+                values().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1.0)
+            } else {
+                0.0
+            };
+            let min = values().fold(f64::INFINITY, f64::min);
+            let max = values().fold(f64::NEG_INFINITY, f64::max);
+            let reading = |value| CounterReading {
+                value,
+                multiplexed: samples.iter().any(|x| x[i].multiplexed),
+                running: (samples.iter().map(|x| x[i].running)).fold(1.0, f64::min),
+                enable_scale: samples[0][i].enable_scale,
+                valid: samples.iter().all(|x| x[i].valid),
+            };
+            this.push(name.to_string(), meta[i], reading(mean));
+            this.push(
+                format!("{name}-sd"),
+                CounterMeta::new(),
+                reading(variance.sqrt()),
+            );
+            this.push(format!("{name}-min"), meta[i], reading(min));
+            this.push(format!("{name}-max"), meta[i], reading(max));
+            let mut sorted: Vec<f64> = values().collect();
+            sorted.sort_by(f64::total_cmp);
+            for p in percentiles {
+                this.push(
+                    format!("{name}-p{p}"),
+                    meta[i],
+                    reading(percentile(&sorted, *p)),
+                );
+            }
+            i += 1;
+        });
+        let note = if exclude_outliers {
+            "outliers were excluded from aggregates"
+        } else {
+            "some samples are outliers"
+        };
+        this.push(
+            "outliers".into(),
+            CounterMeta::new()
+                .with_direction(Direction::Neutral)
+                .with_note(note),
+            CounterReading {
+                value: num_outliers as f64,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: true,
+            },
+        );
+        this
+    }
+
+    /// Subtracts the readings of an overhead measurement from those of a benchmark.
+    ///
+    /// For each counter `x`, this reports the corrected value as `x` and the uncorrected value as `x-raw`.
+    /// Derived columns such as ratios are not subtracted, but [recomputed](Counters::recompute_derived) from the corrected values.
+    /// [Gauge](CounterMeta::gauge) counters are not subtracted, but reported as measured.
+    /// The uncorrected values are marked as [detail](CounterMeta::detail) columns.
+    pub fn overhead_corrected(
+        counters: &dyn Counters,
+        measured: &[CounterReading],
+        overhead: &[CounterReading],
+    ) -> Self {
+        let mut this = Self::empty();
+        let mut meta = Vec::new();
+        counters.meta(&mut |x| meta.push(x));
+        let mut names = Vec::new();
+        counters.names(&mut |x| names.push(x.to_string()));
+        let mut corrected: Vec<CounterReading> = (measured.iter().zip(overhead).zip(&meta))
+            .map(|((measured, overhead), meta)| {
+                if meta.gauge() {
+                    return measured.clone();
+                }
+                CounterReading {
+                    value: measured.value - overhead.value,
+                    multiplexed: measured.multiplexed || overhead.multiplexed,
+                    running: measured.running.min(overhead.running),
+                    enable_scale: measured.enable_scale,
+                    valid: measured.valid && overhead.valid,
+                }
+            })
+            .collect();
+        counters.recompute_derived(&mut corrected);
+        for ((name, meta), reading) in names.iter().zip(&meta).zip(corrected) {
+            this.push(name.clone(), *meta, reading);
+        }
+        for (i, name) in names.iter().enumerate() {
+            this.push(
+                format!("{name}-raw"),
+                meta[i].with_detail(true),
+                measured[i].clone(),
+            );
+        }
+        this
+    }
+
+    /// Presents the given readings as counters.
+    pub fn new(names: Vec<String>, meta: Vec<CounterMeta>, readings: Vec<CounterReading>) -> Self {
+        Replay {
+            names,
+            meta,
+            readings,
+            scale_unit: None,
+        }
+    }
+
+    /// Presents the current readings of `counters` normalized per `unit` of scale.
+    ///
+    /// Counters divided by the scale are renamed from `x` to `x/unit`, e.g. `cycle/byte`.
+    pub fn per_unit(counters: &mut dyn Counters, unit: &str) -> Self {
+        let mut this = Self::empty();
+        counters.read(&mut this.readings);
+        let mut i = 0;
+        counters.names(&mut |x| {
+            this.names.push(if this.readings[i].enable_scale {
+                format!("{x}/{unit}")
+            } else {
+                x.to_string()
+            });
+            i += 1;
+        });
+        counters.meta(&mut |x| this.meta.push(x));
+        this.scale_unit = Some(unit.to_string());
+        this
+    }
+
+    /// Presents the current readings of `counters`, with those listed in `normalization` divided by the amount of a base instead of the scale.
+    ///
+    /// `normalization` maps counter names to units, `bases` maps units to the amount of work in that unit.
+    /// Counters divided by a base are renamed from `x` to `x/unit`, e.g. `time/byte`, and are no longer divided by the scale.
+    /// If the base of a counter is missing, its reading is NaN and not valid.
+    pub fn per_base(
+        counters: &mut dyn Counters,
+        normalization: &[(String, String)],
+        bases: &[(String, f64)],
+    ) -> Self {
+        let mut this = Self::empty();
+        counters.read(&mut this.readings);
+        let mut i = 0;
+        counters.names(&mut |x| {
+            let reading = &mut this.readings[i];
+            i += 1;
+            match normalization.iter().find(|n| n.0 == x) {
+                Some((_, unit)) => {
+                    let amount = (bases.iter().find(|b| b.0 == *unit)).map_or(f64::NAN, |b| b.1);
+                    reading.value /= amount;
+                    reading.enable_scale = false;
+                    reading.valid &= !amount.is_nan();
+                    this.names.push(format!("{x}/{unit}"));
+                }
+                None => this.names.push(x.to_string()),
+            }
+        });
+        counters.meta(&mut |x| this.meta.push(x));
+        this.scale_unit = counters.scale_unit().map(str::to_string);
+        this
+    }
+
+    /// Sets the unit of the scale, see [`Counters::scale_unit`].
+    pub fn with_scale_unit(mut self, unit: Option<String>) -> Self {
+        self.scale_unit = unit;
+        self
+    }
+
+    /// Replaces the readings, keeping names and metadata.
+    pub fn set_readings(&mut self, readings: Vec<CounterReading>) {
+        self.readings = readings;
+    }
+
+    fn empty() -> Self {
+        Replay {
+            names: Vec::new(),
+            meta: Vec::new(),
+            readings: Vec::new(),
+            scale_unit: None,
+        }
+    }
+
+    fn push(&mut self, name: String, meta: CounterMeta, reading: CounterReading) {
+        self.names.push(name);
+        self.meta.push(meta);
+        self.readings.push(reading);
+    }
+}
+
+/// Flags samples with outlying readings.
+///
+/// A sample is an outlier if any of its non-detail readings has a modified z-score above 3.5,
+/// i.e. it deviates from the median by more than 3.5 times the median absolute deviation, scaled to match the standard deviation of a normal distribution.
+/// Counters with a median absolute deviation of zero are ignored, as are [gauges](CounterMeta::gauge) and sets of fewer than three samples.
+fn outliers(samples: &[Vec<CounterReading>], meta: &[CounterMeta]) -> Vec<bool> {
+    let mut outliers = vec![false; samples.len()];
+    if samples.len() < 3 {
+        return outliers;
+    }
+    for (i, meta) in meta.iter().enumerate() {
+        if meta.detail() || meta.gauge() {
+            continue;
+        }
+        let median_of = |mut values: Vec<f64>| {
+            values.sort_by(f64::total_cmp);
+            percentile(&values, 50.0)
+        };
+        let median = median_of(samples.iter().map(|x| x[i].value).collect());
+        let mad = median_of(
+            samples
+                .iter()
+                .map(|x| (x[i].value - median).abs())
+                .collect(),
+        );
+        if mad == 0.0 || !mad.is_finite() {
+            continue;
+        }
+        for (outlier, sample) in outliers.iter_mut().zip(samples) {
+            if (sample[i].value - median).abs() / (1.4826 * mad) > 3.5 {
+                *outlier = true;
+            }
+        }
+    }
+    outliers
+}
+
+/// Computes a percentile of sorted values, interpolating linearly between the closest ranks.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let below = sorted[rank.floor() as usize];
+    let above = sorted[rank.ceil() as usize];
+    below + (above - below) * rank.fract()
+}
+
+impl Counters for Replay {
+    fn enable(&mut self) {}
+
+    fn disable(&mut self) {}
+
+    fn reset(&mut self) {}
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        dst.extend(self.readings.iter().cloned());
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for name in &self.names {
+            dst(name);
+        }
+    }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        for meta in &self.meta {
+            dst(*meta);
+        }
+    }
+
+    fn scale_unit(&self) -> Option<&str> {
+        self.scale_unit.as_deref()
+    }
+}
+
+#[test]
+fn test_summary() {
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let samples: Vec<_> = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]
+        .into_iter()
+        .map(|x| vec![reading(x), reading(1.0)])
+        .collect();
+    let counters = Replay {
+        names: vec!["a".into(), "b".into()],
+        meta: vec![CounterMeta::new(); 2],
+        readings: Vec::new(),
+        scale_unit: None,
+    };
+    let summary = Replay::summary(&counters, &samples, &[50.0, 90.0], false);
+    assert_eq!(
+        summary.names,
+        [
+            "a", "a-sd", "a-min", "a-max", "a-p50", "a-p90", "b", "b-sd", "b-min", "b-max",
+            "b-p50", "b-p90", "outliers"
+        ]
+    );
+    let stats: Vec<f64> = summary.readings.iter().map(|x| x.value).collect();
+    assert_eq!(stats[0], 5.0);
+    assert!((stats[1] - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+    assert_eq!(stats[2..5], [2.0, 9.0, 4.5]);
+    assert!((stats[5] - 7.6).abs() < 1e-12);
+    // 9.0 deviates from the median 4.5 by nine times the median absolute deviation.
+    assert_eq!(stats[6..], [1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn test_summary_gauge() {
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: false,
+        valid: true,
+    };
+    let samples: Vec<_> = [3.0, 5.0, 4.0]
+        .into_iter()
+        .map(|x| vec![reading(x)])
+        .collect();
+    let counters = Replay::new(
+        vec!["cpu".into()],
+        vec![CounterMeta::new().with_gauge(true)],
+        Vec::new(),
+    );
+    let summary = Replay::summary(&counters, &samples, &[99.0], false);
+    assert_eq!(summary.names, ["cpu", "outliers"]);
+    assert_eq!(summary.readings[0].value, 4.0);
+}
+
+#[test]
+fn test_outliers() {
+    let samples: Vec<_> = [10.0, 11.0, 9.0, 10.0, 10.5, 50.0, 9.5]
+        .into_iter()
+        .map(|value| {
+            vec![CounterReading {
+                value,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: true,
+                valid: true,
+            }]
+        })
+        .collect();
+    let flagged = outliers(&samples, &[CounterMeta::new()]);
+    assert_eq!(flagged, [false, false, false, false, false, true, false]);
+    assert!(outliers(&samples, &[CounterMeta::new().with_detail(true)]) == [false; 7]);
+}
+
+#[test]
+fn test_per_unit() {
+    use super::TimeBackend;
+    let mut counters = (
+        TimeBackend::new(),
+        Replay::new(
+            vec!["cycle".into()],
+            vec![CounterMeta::new()],
+            vec![CounterReading {
+                value: 1.0,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: true,
+                valid: true,
+            }],
+        ),
+    );
+    let per_unit = Replay::per_unit(&mut counters, "byte");
+    assert_eq!(per_unit.names, ["time", "cycle/byte"]);
+    assert_eq!(per_unit.scale_unit(), Some("byte"));
+}
+
+#[test]
+fn test_per_base() {
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let mut counters = Replay::new(
+        vec!["cycle".into(), "time".into(), "l1-miss".into()],
+        vec![CounterMeta::new(); 3],
+        vec![reading(8.0), reading(2.0), reading(4.0)],
+    );
+    let normalization = [
+        ("time".to_string(), "byte".to_string()),
+        ("l1-miss".to_string(), "row".to_string()),
+    ];
+    let mut per_base = Replay::per_base(&mut counters, &normalization, &[("byte".into(), 4.0)]);
+    assert_eq!(per_base.names, ["cycle", "time/byte", "l1-miss/row"]);
+    let mut readings = Vec::new();
+    per_base.read(&mut readings);
+    assert_eq!(readings[0].scaled_value(2), 4.0);
+    assert_eq!(readings[1].scaled_value(2), 0.5);
+    assert!(!readings[2].valid);
+}
+
+#[test]
+fn test_overhead_corrected() {
+    use super::Derived;
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let inner = Replay::new(
+        vec!["instr".into(), "cycle".into(), "cpu".into()],
+        vec![
+            CounterMeta::new(),
+            CounterMeta::new(),
+            CounterMeta::new().with_gauge(true),
+        ],
+        Vec::new(),
+    );
+    let counters = Derived::with_names(inner, ["ipc"]);
+    let measured = [reading(30.0), reading(12.0), reading(3.0), reading(2.5)];
+    let overhead = [reading(10.0), reading(2.0), reading(1.0), reading(5.0)];
+    let mut corrected = Replay::overhead_corrected(&counters, &measured, &overhead);
+    assert_eq!(corrected.names[..4], ["instr", "cycle", "cpu", "ipc"]);
+    let mut readings = Vec::new();
+    corrected.read(&mut readings);
+    let values: Vec<f64> = readings.iter().map(|x| x.value).collect();
+    assert_eq!(values, [20.0, 10.0, 3.0, 2.0, 30.0, 12.0, 3.0, 2.5]);
+}
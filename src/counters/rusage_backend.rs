@@ -0,0 +1,120 @@
+use super::{CounterMeta, CounterReading, CounterState, Counters};
+use crate::config::Config;
+use std::mem::MaybeUninit;
+
+const NAMES: [&str; 5] = ["rss-grow", "minflt", "majflt", "vcsw", "ivcsw"];
+
+/// Counters based on `getrusage`, available on all Unix platforms.
+///
+/// This complements [`PerfBackend`](crate::counters::PerfBackend) where perf events are not available, e.g. on macOS or in restricted containers.
+/// It reports the growth of the peak resident set size in bytes as `rss-grow`, along with the number of minor and major page faults (`minflt`, `majflt`) and voluntary and involuntary context switches (`vcsw`, `ivcsw`).
+/// Usage is that of the whole process, so activity of other threads is included.
+/// Since the peak resident set size never decreases, `rss-grow` is zero for measurements that do not exceed the peak of earlier ones.
+pub struct RusageBackend {
+    state: CounterState,
+    /// Usage at the time the counters were enabled.
+    start: Option<[i64; 5]>,
+    total: [i64; 5],
+}
+
+impl Default for RusageBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RusageBackend {
+    pub fn new() -> Self {
+        RusageBackend {
+            state: CounterState::default(),
+            start: None,
+            total: [0; 5],
+        }
+    }
+
+    /// Creates a backend if the environment variable `QPE_RUSAGE` is set to `1`.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        config.flag("RUSAGE").then(Self::new)
+    }
+
+    /// The usage since the last reset.
+    fn current(&self) -> [i64; 5] {
+        let mut total = self.total;
+        if let Some(start) = self.start {
+            let now = usage();
+            for i in 0..5 {
+                total[i] += now[i] - start[i];
+            }
+        }
+        total
+    }
+}
+
+fn usage() -> [i64; 5] {
+    let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+    let usage = unsafe {
+        libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr());
+        usage.assume_init()
+    };
+    // Linux reports the resident set size in kilobytes, macOS in bytes.
+    let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    [
+        usage.ru_maxrss as i64 * rss_unit,
+        usage.ru_minflt as i64,
+        usage.ru_majflt as i64,
+        usage.ru_nvcsw as i64,
+        usage.ru_nivcsw as i64,
+    ]
+}
+
+impl Counters for RusageBackend {
+    fn enable(&mut self) {
+        self.state.enable("RusageBackend");
+        self.start = Some(usage());
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("RusageBackend");
+        self.total = self.current();
+        self.start = None;
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("RusageBackend");
+        self.total = [0; 5];
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        dst.extend(
+            self.current()
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| CounterReading {
+                    value: value as f64,
+                    multiplexed: false,
+                    running: 1.0,
+                    // The growth of the peak is not proportional to the amount of work.
+                    enable_scale: i != 0,
+                    valid: true,
+                }),
+        );
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for name in NAMES {
+            dst(name);
+        }
+    }
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        for name in NAMES {
+            dst(match name {
+                "rss-grow" => CounterMeta::new().with_unit("B"),
+                _ => CounterMeta::new(),
+            });
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Once;
+
+/// Tracks whether a [`Counters`](super::Counters) implementation is enabled, to detect calls in an invalid order.
+///
+/// In debug builds, invalid calls panic with a message naming the backend and the offending call.
+/// In release builds, the first invalid call prints the message to stderr instead, so a long benchmark run is not aborted.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CounterState {
+    enabled: bool,
+}
+
+impl CounterState {
+    pub fn enable(&mut self, backend: &str) {
+        if self.enabled {
+            report(backend, "enable called while counters are already enabled");
+        }
+        self.enabled = true;
+    }
+
+    pub fn disable(&mut self, backend: &str) {
+        if !self.enabled {
+            report(backend, "disable called while counters are not enabled");
+        }
+        self.enabled = false;
+    }
+
+    pub fn reset(&self, backend: &str) {
+        if self.enabled {
+            report(
+                backend,
+                "reset called while counters are enabled, disable them first",
+            );
+        }
+    }
+}
+
+fn report(backend: &str, message: &str) {
+    if cfg!(debug_assertions) {
+        panic!("{backend}: {message}");
+    }
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| eprintln!("{backend}: {message}"));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic = "TimeBackend: enable called while counters are already enabled"]
+fn test_double_enable() {
+    let mut state = CounterState::default();
+    state.enable("TimeBackend");
+    state.disable("TimeBackend");
+    state.reset("TimeBackend");
+    state.enable("TimeBackend");
+    state.enable("TimeBackend");
+}
@@ -1,10 +1,11 @@
-use super::{Counters, CounterReading};
+use super::{CounterMeta, CounterReading, CounterState, Counters};
 use std::time::{Duration, Instant};
 
 /// A counter that records the duration of time it is enabled for.
 ///
 /// The counter is named `time`.
 pub struct TimeBackend {
+    state: CounterState,
     time: Result<Duration, Instant>,
 }
 
@@ -17,6 +18,7 @@ impl Default for TimeBackend {
 impl TimeBackend {
     pub fn new() -> Self {
         Self {
+            state: CounterState::default(),
             time: Ok(Duration::ZERO),
         }
     }
@@ -24,33 +26,43 @@ impl TimeBackend {
 
 impl Counters for TimeBackend {
     fn enable(&mut self) {
-        let Ok(duration) = self.time else {
-            panic!("already enabled")
-        };
-        self.time = Err(Instant::now() - duration);
+        self.state.enable("TimeBackend");
+        if let Ok(duration) = self.time {
+            self.time = Err(Instant::now() - duration);
+        }
     }
 
     fn disable(&mut self) {
-        let Err(start) = self.time else {
-            panic!("already disabled")
-        };
-        self.time = Ok(Instant::now() - start);
+        self.state.disable("TimeBackend");
+        if let Err(start) = self.time {
+            self.time = Ok(Instant::now() - start);
+        }
     }
 
     fn reset(&mut self) {
-        assert!(self.time.is_ok(), "perf while enabled");
+        self.state.reset("TimeBackend");
         self.time = Ok(Duration::ZERO)
     }
 
     fn read(&mut self, dst: &mut Vec<CounterReading>) {
         dst.push(CounterReading {
-            value: self.time.expect("perf read while enabled").as_secs_f64(),
+            value: match self.time {
+                Ok(duration) => duration,
+                Err(start) => start.elapsed(),
+            }
+            .as_secs_f64(),
             multiplexed: false,
+            running: 1.0,
             enable_scale: false,
+            valid: true,
         });
     }
 
     fn names(&self, dst: &mut dyn FnMut(&str)) {
         dst("time");
     }
+
+    fn meta(&self, dst: &mut dyn FnMut(CounterMeta)) {
+        dst(CounterMeta::new().with_unit("s"));
+    }
 }
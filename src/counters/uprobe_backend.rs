@@ -0,0 +1,157 @@
+use super::{CounterReading, CounterState, Counters};
+use crate::config::Config;
+use crate::{perf_sys::*, symbols::read_symbols};
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, Read},
+    os::unix::ffi::OsStrExt,
+};
+
+/// A [`Counters`] implementation that counts calls to functions of the running executable.
+///
+/// Each function is traced using a uprobe attached to the current executable.
+/// Functions are looked up in the symbol table by their demangled path without generic arguments, e.g. `my_crate::hot_path`.
+/// Demangling requires the `symbolize` feature, without it only functions with unmangled names, e.g. `#[unsafe(no_mangle)]` functions, can be found.
+/// If a function has multiple monomorphizations, calls to all of them are counted.
+/// The counter for a function `f` is named `calls:f`.
+///
+/// Functions must not be inlined into their callers, so you will usually want to mark them `#[inline(never)]`.
+/// The executable must not be stripped.
+/// Creating uprobes usually requires elevated privileges, see `perf_event_paranoid`.
+pub struct UprobeBackend {
+    state: CounterState,
+    counters: Vec<(String, Vec<File>)>,
+}
+
+impl UprobeBackend {
+    /// Creates a new [`UprobeBackend`] instance tracing the functions listed in `QPE_UPROBES`.
+    ///
+    /// Returns `None` if the variable is not set.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let var = config.get("UPROBES")?;
+        Some(Self::new(var.split(",").map(str::trim)))
+    }
+
+    /// Creates a new [`UprobeBackend`] counting calls to each of `functions`.
+    ///
+    /// Functions that cannot be found or traced are skipped with a warning message to stderr.
+    pub fn new<'a>(functions: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut this = UprobeBackend {
+            state: CounterState::default(),
+            counters: Vec::new(),
+        };
+        let symbols = std::env::current_exe().and_then(|exe| Ok((read_symbols(&exe)?, exe)));
+        let (symbols, exe) = match symbols {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("failed to read symbols of current executable: {e}");
+                return this;
+            }
+        };
+        let exe = CString::new(exe.as_os_str().as_bytes()).unwrap();
+        let probe_type = match std::fs::read_to_string("/sys/bus/event_source/devices/uprobe/type")
+        {
+            Ok(x) => x.trim().parse::<u32>().ok(),
+            Err(_) => None,
+        };
+        let Some(probe_type) = probe_type else {
+            eprintln!("failed to create uprobes: uprobe event source not available");
+            return this;
+        };
+        for function in functions {
+            let files: io::Result<Vec<File>> = symbols
+                .iter()
+                .filter(|x| strip_generics(&x.name) == function)
+                .map(|x| open_uprobe(probe_type, &exe, x.file_offset))
+                .collect();
+            match files {
+                Ok(files) if files.is_empty() => {
+                    eprintln!("failed to create uprobe for {function:?}: function not found");
+                }
+                Ok(files) => this.counters.push((format!("calls:{function}"), files)),
+                Err(e) => eprintln!("failed to create uprobe for {function:?}: {e}"),
+            }
+        }
+        this
+    }
+
+    fn ioctl(&mut self, request: libc::c_ulong) {
+        for file in self.counters.iter().flat_map(|x| &x.1) {
+            ioctl(file, request);
+        }
+    }
+}
+
+/// Removes generic arguments from a demangled path, e.g. `a::f::<u32>` becomes `a::f`.
+fn strip_generics(name: &str) -> &str {
+    name.split_once("::<").map_or(name, |x| x.0)
+}
+
+fn open_uprobe(probe_type: u32, exe: &CString, offset: u64) -> io::Result<File> {
+    perf_event_open(PerfEventAttr {
+        kind: probe_type,
+        flags: FLAG_DISABLED | FLAG_INHERIT,
+        config1: exe.as_ptr() as u64,
+        config2: offset,
+        ..Default::default()
+    })
+}
+
+impl Counters for UprobeBackend {
+    fn enable(&mut self) {
+        self.state.enable("UprobeBackend");
+        self.ioctl(PERF_EVENT_IOC_ENABLE);
+    }
+
+    fn disable(&mut self) {
+        self.state.disable("UprobeBackend");
+        self.ioctl(PERF_EVENT_IOC_DISABLE);
+    }
+
+    fn reset(&mut self) {
+        self.state.reset("UprobeBackend");
+        self.ioctl(PERF_EVENT_IOC_RESET);
+    }
+
+    fn read(&mut self, dst: &mut Vec<CounterReading>) {
+        for (name, files) in &mut self.counters {
+            let mut value = 0;
+            let mut valid = true;
+            for file in files {
+                let mut buffer = [0u8; 8];
+                match file.read_exact(&mut buffer) {
+                    Ok(()) => value += u64::from_ne_bytes(buffer),
+                    Err(e) => {
+                        eprintln!("failed to read uprobe {name:?}: {e}");
+                        valid = false;
+                    }
+                }
+            }
+            dst.push(CounterReading {
+                value: if valid { value as f64 } else { f64::NAN },
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: true,
+                valid,
+            });
+        }
+    }
+
+    fn names(&self, dst: &mut dyn FnMut(&str)) {
+        for (name, _) in &self.counters {
+            dst(name);
+        }
+    }
+}
+
+#[test]
+fn test_strip_generics() {
+    assert_eq!(strip_generics("a::f"), "a::f");
+    assert_eq!(strip_generics("a::f::<u32>"), "a::f");
+    assert_eq!(strip_generics("<a::S as b::T>::f"), "<a::S as b::T>::f");
+}
@@ -0,0 +1,192 @@
+use crate::{
+    Report, RowKey,
+    counters::{CounterMeta, CounterReading, Direction},
+    formats::Format,
+    labels::LabelMeta,
+    report::Row,
+};
+use std::{collections::HashMap, error::Error, time::SystemTime};
+
+/// A comparison of two reports, see [`Report::diff`].
+#[derive(Clone, Debug, Default)]
+pub struct DiffReport {
+    pub label_meta: &'static [LabelMeta],
+    /// The counters present in both reports.
+    pub counter_names: Vec<String>,
+    pub counter_meta: Vec<CounterMeta>,
+    pub rows: Vec<DiffRow>,
+}
+
+/// The normalized values of one set of labels in both reports.
+#[derive(Clone, Debug)]
+pub struct DiffRow {
+    pub labels: Vec<String>,
+    /// The values of the baseline, in the order of [`DiffReport::counter_names`].
+    pub old: Vec<f64>,
+    /// The values of the compared report, in the order of [`DiffReport::counter_names`].
+    pub new: Vec<f64>,
+}
+
+impl DiffRow {
+    /// Returns the change of the `i`th counter.
+    pub fn delta(&self, i: usize) -> f64 {
+        self.new[i] - self.old[i]
+    }
+
+    /// Returns the new value of the `i`th counter divided by the old value.
+    pub fn ratio(&self, i: usize) -> f64 {
+        self.new[i] / self.old[i]
+    }
+}
+
+impl Report {
+    /// Compares this report against a baseline.
+    ///
+    /// Rows are matched by labels, if a report contains multiple rows with the same labels, the last one is used.
+    /// Rows without a match and counters not present in both reports are omitted.
+    /// Counter metadata is taken from this report.
+    pub fn diff(&self, baseline: &Report) -> DiffReport {
+        self.diff_by(baseline, &RowKey::new())
+    }
+
+    /// Like [`diff`](Self::diff), but matches rows by the labels selected by `key`.
+    pub fn diff_by(&self, baseline: &Report, key: &RowKey) -> DiffReport {
+        let counters: Vec<(usize, usize)> = (0..self.counter_names.len())
+            .filter_map(|i| Some((i, baseline.counter_index(&self.counter_names[i])?)))
+            .collect();
+        let old: HashMap<Vec<(String, String)>, &Row> = (baseline.rows.iter())
+            .map(|row| (key.key(baseline.label_meta, &row.labels), row))
+            .collect();
+        let mut rows: Vec<DiffRow> = Vec::new();
+        for row in &self.rows {
+            let Some(old) = old.get(&key.key(self.label_meta, &row.labels)) else {
+                continue;
+            };
+            let diff = DiffRow {
+                labels: row.labels.clone(),
+                old: counters.iter().map(|x| old.value(x.1)).collect(),
+                new: counters.iter().map(|x| row.value(x.0)).collect(),
+            };
+            match rows.iter_mut().find(|x| x.labels == row.labels) {
+                Some(existing) => *existing = diff,
+                None => rows.push(diff),
+            }
+        }
+        DiffReport {
+            label_meta: self.label_meta,
+            counter_names: counters
+                .iter()
+                .map(|x| self.counter_names[x.0].clone())
+                .collect(),
+            counter_meta: counters.iter().map(|x| self.counter_meta[x.0]).collect(),
+            rows,
+        }
+    }
+}
+
+impl DiffReport {
+    /// Returns the rows and names of counters that changed in the unfavorable [direction](Direction) by more than `threshold` percent of the old value.
+    pub fn regressions(&self, threshold: f64) -> Vec<(&DiffRow, &str)> {
+        let mut regressions = Vec::new();
+        for row in &self.rows {
+            for (i, name) in self.counter_names.iter().enumerate() {
+                if crate::regression::is_regression(
+                    self.counter_meta[i],
+                    row.old[i],
+                    row.new[i],
+                    threshold,
+                ) {
+                    regressions.push((row, name.as_str()));
+                }
+            }
+        }
+        regressions
+    }
+
+    /// Converts the comparison to a report for rendering.
+    ///
+    /// For each counter `x`, this contains the columns `x-delta` and `x-ratio`, as well as the [detail](CounterMeta::detail) columns `x-old` and `x-new`.
+    pub fn to_report(&self) -> Report {
+        let mut report = Report {
+            label_meta: self.label_meta,
+            ..Default::default()
+        };
+        for (name, meta) in self.counter_names.iter().zip(&self.counter_meta) {
+            report
+                .counter_names
+                .extend(["delta", "ratio", "old", "new"].map(|suffix| format!("{name}-{suffix}")));
+            let neutral = meta.with_direction(Direction::Neutral);
+            report.counter_meta.extend([
+                *meta,
+                *meta,
+                neutral.with_detail(true),
+                neutral.with_detail(true),
+            ]);
+        }
+        for row in &self.rows {
+            let reading = |value| CounterReading {
+                value,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: !value.is_nan(),
+            };
+            report.rows.push(Row {
+                labels: row.labels.clone(),
+                scale: 1,
+                start_time: SystemTime::UNIX_EPOCH,
+                readings: (0..self.counter_names.len())
+                    .flat_map(|i| [row.delta(i), row.ratio(i), row.old[i], row.new[i]])
+                    .map(reading)
+                    .collect(),
+            });
+        }
+        report
+    }
+
+    /// Writes the comparison to a format, see [`to_report`](Self::to_report).
+    pub fn write(&self, format: &mut dyn Format) -> Result<(), Box<dyn Error>> {
+        self.to_report().write(format)
+    }
+}
+
+#[test]
+fn test_diff() {
+    let row = |label: &str, value| Row {
+        labels: vec![label.to_string()],
+        scale: 2,
+        start_time: SystemTime::UNIX_EPOCH,
+        readings: vec![CounterReading {
+            value,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: true,
+        }],
+    };
+    let report = |rows| Report {
+        label_meta: &const { [LabelMeta::new("label")] },
+        counter_names: vec!["cycle".into()],
+        counter_meta: vec![CounterMeta::new()],
+        rows,
+        ..Default::default()
+    };
+    let old = report(vec![row("a", 100.0), row("b", 100.0)]);
+    let new = report(vec![row("a", 120.0), row("c", 100.0)]);
+    let diff = new.diff(&old);
+    assert_eq!(diff.rows.len(), 1);
+    assert_eq!(diff.rows[0].delta(0), 10.0);
+    assert_eq!(diff.rows[0].ratio(0), 1.2);
+    assert_eq!(diff.regressions(10.0).len(), 1);
+    assert!(diff.regressions(25.0).is_empty());
+    assert_eq!(
+        new.diff_by(&old, &RowKey::new().with_ignored(["label"]))
+            .rows
+            .len(),
+        2
+    );
+    assert_eq!(
+        diff.to_report().counter_names,
+        ["cycle-delta", "cycle-ratio", "cycle-old", "cycle-new"]
+    );
+}
@@ -0,0 +1,237 @@
+//! Keeping instrumentation in production builds.
+//!
+//! An [`embedded`](QuickPerfEvent::embedded) instance opens its counters on first use and writes no output.
+//! Instead, readings are accumulated into [`Aggregates`] with bounded memory, which the application can expose through its own metrics endpoint.
+//! This allows the same instrumentation code to be used in benchmarks and in the shipped service.
+//! ```
+//! use quick_perf_event::{Config, QuickPerfEvent, embedded::Aggregates};
+//!
+//! let aggregates = Aggregates::new();
+//! let mut perf = QuickPerfEvent::<str>::embedded(&Config::from_env(), &aggregates);
+//! perf.run(|| (0..1000).map(std::hint::black_box).sum::<u64>())
+//!     .record(1000, "sum");
+//! for row in aggregates.snapshot().rows {
+//!     println!("{:?}: {} runs", row.labels, row.count);
+//! }
+//! ```
+
+use crate::{
+    Config, Labels, QuickPerfEvent,
+    counters::{CounterReading, Counters, Lazy, counters_from_config, read_by_name},
+    formats::Format,
+    labels::LabelMeta,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+impl<L: Labels + ?Sized> QuickPerfEvent<L> {
+    /// Create a `QuickPerfEvent` for production use, accumulating readings into `aggregates`.
+    ///
+    /// Counters are configured from `config` like those of [`from_config`](Self::from_config), but only opened when the first measurement is taken.
+    /// The output format settings are ignored, nothing is written to stdout.
+    /// Settings that report to stderr or fail when finished, such as `QPE_SUMMARY` and `QPE_STRICT_MUX`, are ignored as well.
+    pub fn embedded(config: &Config, aggregates: &Aggregates) -> Self {
+        let counters_config = config.clone();
+        let counters: Box<dyn Counters> =
+            Box::new(Lazy::new(move || counters_from_config(&counters_config)));
+        let format: Box<dyn Format> = Box::new(aggregates.clone());
+        let mut qpe = QuickPerfEvent::new(counters, format)
+            .with_measurement_config(config)
+            .with_strict_multiplexing(false)
+            .with_summary_file(None);
+        qpe.quiet = true;
+        qpe
+    }
+}
+
+/// The number of readings and the mean and maximum of each counter for one set of labels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelAggregate {
+    pub labels: Vec<String>,
+    pub count: u64,
+    /// The number of valid readings of each counter, which contribute to `mean` and `max`.
+    ///
+    /// Invalid readings, e.g. failed reads, are skipped so they do not turn the mean into NaN.
+    pub valid: Vec<u64>,
+    /// Normalized counter values, in the order of [`Snapshot::counter_names`].
+    pub mean: Vec<f64>,
+    pub max: Vec<f64>,
+}
+
+/// The state of [`Aggregates`] at some point in time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub label_names: Vec<String>,
+    pub counter_names: Vec<String>,
+    /// Aggregates in order of the first reading of their labels.
+    pub rows: Vec<LabelAggregate>,
+    /// The number of readings that were not aggregated because the maximum number of labels was reached.
+    pub dropped: u64,
+}
+
+/// A [`Format`] accumulating the count, mean, and maximum of each counter per set of labels.
+///
+/// Memory use is bounded by the [maximum number of labels](Self::with_max_labels), readings with additional labels are dropped.
+/// `Aggregates` is a handle to shared state, so a clone can be kept to take [snapshots](Self::snapshot) from any thread while another clone is used as the format.
+#[derive(Clone)]
+pub struct Aggregates {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    max_labels: usize,
+    snapshot: Snapshot,
+    /// The index of each set of labels in the rows of the snapshot.
+    index: HashMap<Vec<String>, usize>,
+    reading_buffer: Vec<CounterReading>,
+}
+
+impl Default for Aggregates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Aggregates {
+    /// Creates aggregates for up to 1024 sets of labels.
+    pub fn new() -> Self {
+        Aggregates {
+            state: Arc::new(Mutex::new(State {
+                max_labels: 1024,
+                snapshot: Snapshot::default(),
+                index: HashMap::new(),
+                reading_buffer: Vec::new(),
+            })),
+        }
+    }
+
+    /// Set the maximum number of distinct sets of labels.
+    pub fn with_max_labels(self, max_labels: usize) -> Self {
+        self.state.lock().unwrap().max_labels = max_labels;
+        self
+    }
+
+    /// Returns a copy of the current aggregates.
+    pub fn snapshot(&self) -> Snapshot {
+        self.state.lock().unwrap().snapshot.clone()
+    }
+
+    /// Removes all aggregates, e.g. after exporting them.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.snapshot.rows.clear();
+        state.snapshot.dropped = 0;
+        state.index.clear();
+    }
+}
+
+impl Format for Aggregates {
+    fn push(
+        &mut self,
+        scale: usize,
+        _start_time: SystemTime,
+        counters: &mut dyn Counters,
+        labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let snapshot = &mut state.snapshot;
+        if snapshot.counter_names.is_empty() {
+            snapshot.label_names = label_meta.iter().map(|x| x.name().to_string()).collect();
+            counters.names(&mut |x| snapshot.counter_names.push(x.to_string()));
+        }
+        let mut label_vec = Vec::new();
+        labels(&mut |x| label_vec.push(x.to_string()));
+        let i = match state.index.get(&label_vec) {
+            Some(&i) => i,
+            None if snapshot.rows.len() < state.max_labels => {
+                let width = snapshot.counter_names.len();
+                state.index.insert(label_vec.clone(), snapshot.rows.len());
+                snapshot.rows.push(LabelAggregate {
+                    labels: label_vec,
+                    count: 0,
+                    valid: vec![0; width],
+                    mean: vec![0.0; width],
+                    max: vec![f64::NEG_INFINITY; width],
+                });
+                snapshot.rows.len() - 1
+            }
+            None => {
+                snapshot.dropped += 1;
+                return Ok(());
+            }
+        };
+        state.reading_buffer.clear();
+        read_by_name(counters, &snapshot.counter_names, &mut state.reading_buffer);
+        let row = &mut snapshot.rows[i];
+        row.count += 1;
+        for (((valid, mean), max), reading) in (row.valid.iter_mut())
+            .zip(&mut row.mean)
+            .zip(&mut row.max)
+            .zip(&state.reading_buffer)
+        {
+            if !reading.valid {
+                continue;
+            }
+            *valid += 1;
+            let value = reading.scaled_value(scale);
+            *mean += (value - *mean) / *valid as f64;
+            *max = max.max(value);
+        }
+        Ok(())
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        _label_meta: &'static [LabelMeta],
+        _counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_aggregates() {
+    use crate::counters::{CounterMeta, Replay};
+    let mut aggregates = Aggregates::new().with_max_labels(2);
+    let readings = [
+        ("a", 1, 2.0),
+        ("b", 1, 1.0),
+        ("a", 1, f64::NAN),
+        ("a", 4, 16.0),
+        ("c", 1, 1.0),
+    ];
+    for (label, scale, value) in readings {
+        let reading = CounterReading {
+            value,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: !value.is_nan(),
+        };
+        let mut counters = Replay::new(vec!["x".into()], vec![CounterMeta::new()], vec![reading]);
+        aggregates
+            .push(
+                scale,
+                SystemTime::UNIX_EPOCH,
+                &mut counters,
+                &mut |dst| dst(label),
+                &[],
+            )
+            .unwrap();
+    }
+    let snapshot = aggregates.snapshot();
+    assert_eq!(snapshot.counter_names, ["x"]);
+    assert_eq!(snapshot.dropped, 1);
+    assert_eq!(snapshot.rows.len(), 2);
+    assert_eq!(snapshot.rows[0].labels, ["a"]);
+    assert_eq!(snapshot.rows[0].count, 3);
+    assert_eq!(snapshot.rows[0].valid, [2]);
+    assert_eq!(snapshot.rows[0].mean, [3.0]);
+    assert_eq!(snapshot.rows[0].max, [4.0]);
+}
@@ -0,0 +1,135 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// Common sources of noise in measurements detected on the current system.
+///
+/// Frequency scaling, turbo boost, and simultaneous multithreading (SMT) make counters such as `cycle` and `time` vary between runs.
+/// For a default instance, the environment is detected at construction and a warning listing the detected noise sources is printed to stderr once per process.
+/// Setting `QPE_ENV_CHECK` to `0` disables this.
+/// Information that cannot be read, e.g. on other platforms than Linux, is `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Environment {
+    /// The frequency scaling governors other than `performance` used by any CPU, e.g. `powersave`.
+    pub governors: Vec<String>,
+    /// Whether turbo boost is enabled.
+    pub turbo: Option<bool>,
+    /// Whether SMT is active.
+    pub smt: Option<bool>,
+    /// The number of thermal throttling events of all CPUs since boot.
+    pub throttle_count: Option<u64>,
+}
+
+impl Environment {
+    /// Reads the current state of the system.
+    pub fn detect() -> Self {
+        let mut governors = Vec::new();
+        for cpu in cpu_dirs() {
+            if let Some(governor) = read(cpu.join("cpufreq/scaling_governor"))
+                && governor != "performance"
+                && !governors.contains(&governor)
+            {
+                governors.push(governor);
+            }
+        }
+        let turbo = match read(format!("{CPU_DIR}/intel_pstate/no_turbo")) {
+            Some(no_turbo) => Some(no_turbo == "0"),
+            None => read(format!("{CPU_DIR}/cpufreq/boost")).map(|x| x == "1"),
+        };
+        Environment {
+            governors,
+            turbo,
+            smt: read(format!("{CPU_DIR}/smt/active")).map(|x| x == "1"),
+            throttle_count: throttle_count(),
+        }
+    }
+
+    /// Returns a description of each detected noise source, except thermal throttling.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.governors.is_empty() {
+            warnings.push(format!(
+                "frequency scaling governor is {}, not performance",
+                self.governors.join(", ")
+            ));
+        }
+        if self.turbo == Some(true) {
+            warnings.push("turbo boost is enabled".to_string());
+        }
+        if self.smt == Some(true) {
+            warnings.push("SMT is active, sibling threads share cores".to_string());
+        }
+        warnings
+    }
+
+    /// Returns the number of thermal throttling events since the environment was detected.
+    pub fn throttled_since(&self) -> Option<u64> {
+        Some(throttle_count()?.saturating_sub(self.throttle_count?))
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "measurements may be noisy:")?;
+        for warning in self.warnings() {
+            write!(f, "\n  {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the detected noise sources, at most once per process.
+pub(crate) fn warn_environment(environment: &Environment) {
+    static WARNED: Once = Once::new();
+    if !environment.warnings().is_empty() {
+        WARNED.call_once(|| eprintln!("{environment}"));
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> Option<String> {
+    Some(fs::read_to_string(path).ok()?.trim().to_string())
+}
+
+/// Returns the directories of all CPUs, e.g. `/sys/devices/system/cpu/cpu0`.
+fn cpu_dirs() -> impl Iterator<Item = PathBuf> {
+    fs::read_dir(CPU_DIR).into_iter().flatten().filter_map(|x| {
+        let x = x.ok()?;
+        let name = x.file_name();
+        let index = name.to_str()?.strip_prefix("cpu")?;
+        index.parse::<usize>().ok()?;
+        Some(x.path())
+    })
+}
+
+fn throttle_count() -> Option<u64> {
+    let mut total = None;
+    for cpu in cpu_dirs() {
+        for counter in ["core_throttle_count", "package_throttle_count"] {
+            if let Some(count) =
+                read(cpu.join("thermal_throttle").join(counter)).and_then(|x| x.parse::<u64>().ok())
+            {
+                *total.get_or_insert(0) += count;
+            }
+        }
+    }
+    total
+}
+
+#[test]
+fn test_warnings() {
+    let environment = Environment {
+        governors: vec!["powersave".into()],
+        turbo: Some(false),
+        smt: None,
+        throttle_count: Some(0),
+    };
+    assert_eq!(
+        environment.warnings(),
+        ["frequency scaling governor is powersave, not performance"]
+    );
+    assert!(Environment::default().warnings().is_empty());
+}
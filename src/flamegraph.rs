@@ -0,0 +1,212 @@
+use crate::{
+    attribution::sampled_event, config::Config, perf_data::file_name, perf_sys::*,
+    symbols::Symbolizer,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Records call stacks during each measurement and writes them as folded stacks per label, for rendering as a flame graph.
+///
+/// While a measurement is running, the call stack is sampled every `period` events.
+/// Samples are grouped by the labels of the recorded [`Reading`](crate::Reading).
+/// When the [`QuickPerfEvent`](crate::QuickPerfEvent) is dropped, a file per label is written to the output directory, e.g. `qpe-flamegraph/name=sort.folded`.
+/// Each line of the file is a stack of functions separated by `;`, starting at the root, followed by the number of samples.
+/// This is the input format of [`inferno-flamegraph`](https://github.com/jonhoo/inferno) and `flamegraph.pl`.
+///
+/// Call stacks are obtained by the kernel walking frame pointers, so build with `-C force-frame-pointers=yes` to get complete stacks.
+/// Functions are resolved like for [`Attribution`](crate::Attribution), frames outside the executable are written as addresses.
+/// Only the thread starting the measurement is sampled, and only user space code is included.
+/// Linux only.
+pub struct Flamegraph {
+    sampler: Sampler,
+    dir: PathBuf,
+    /// Records since the last record.
+    pending: Vec<u8>,
+    lost: u64,
+    /// Sample counts of stacks by label, with addresses starting at the leaf.
+    labels: Vec<(String, HashMap<Vec<u64>, usize>)>,
+}
+
+impl Flamegraph {
+    /// Creates flame graphs written to the directory in the environment variable `QPE_FLAMEGRAPH_DIR`.
+    ///
+    /// The sampled event is read from `QPE_FLAMEGRAPH_EVENT` and defaults to `t-clock`, see [`new`](Self::new).
+    /// Returns `None` if `QPE_FLAMEGRAPH_DIR` is not set or the event cannot be sampled.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let dir = config.get("FLAMEGRAPH_DIR")?;
+        let event = config.get("FLAMEGRAPH_EVENT");
+        Self::new(event.as_deref().unwrap_or("t-clock"), dir)
+    }
+
+    /// Creates flame graphs of an event, written to files in `dir`.
+    ///
+    /// Supported events are `br-miss`, `llc-miss`, `l1-miss`, `cycle`, and `t-clock`, as defined for [`PerfBackend`](crate::counters::PerfBackend).
+    /// Returns `None` with a warning message to stderr if the event cannot be sampled.
+    pub fn new(event: &str, dir: impl AsRef<Path>) -> Option<Self> {
+        let Some((kind, config, period)) = sampled_event(event) else {
+            eprintln!("invalid flamegraph event: {event:?}");
+            return None;
+        };
+        let attr = PerfEventAttr {
+            kind,
+            config,
+            sample_period: period,
+            sample_type: PERF_SAMPLE_CALLCHAIN,
+            flags: FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+        let sampler = Sampler::new(attr, 8)
+            .map_err(|e| eprintln!("failed to sample {event:?}: {e}"))
+            .ok()?;
+        Some(Flamegraph {
+            sampler,
+            dir: dir.as_ref().to_path_buf(),
+            pending: Vec::new(),
+            lost: 0,
+            labels: Vec::new(),
+        })
+    }
+
+    /// Set the number of events per sample.
+    ///
+    /// The default is 10000 for cache and branch misses, 1000000 for cycles, and 1ms for `t-clock`.
+    pub fn with_period(mut self, period: u64) -> Self {
+        self.sampler.set_period(period);
+        self
+    }
+
+    pub(crate) fn enable(&mut self) {
+        ioctl(self.sampler.file(), PERF_EVENT_IOC_ENABLE);
+    }
+
+    pub(crate) fn disable(&mut self) {
+        ioctl(self.sampler.file(), PERF_EVENT_IOC_DISABLE);
+        self.lost += self.sampler.drain_records(&mut self.pending);
+    }
+
+    /// Assigns the stacks sampled since the last record to a label.
+    pub(crate) fn record(&mut self, labels: String) {
+        let stacks = match self.labels.iter().position(|x| x.0 == labels) {
+            Some(i) => &mut self.labels[i].1,
+            None => {
+                self.labels.push((labels, HashMap::new()));
+                &mut self.labels.last_mut().unwrap().1
+            }
+        };
+        for stack in callchains(&self.pending) {
+            *stacks.entry(stack).or_default() += 1;
+        }
+        self.pending.clear();
+    }
+
+    /// Writes a file per label and discards all samples.
+    pub(crate) fn dump_and_reset(&mut self) {
+        if self.labels.is_empty() {
+            return;
+        }
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            eprintln!("failed to create {}: {e}", self.dir.display());
+            return;
+        }
+        let mut symbolizer = Symbolizer::new()
+            .map_err(|e| eprintln!("failed to load symbols: {e}"))
+            .ok();
+        for (labels, stacks) in self.labels.drain(..) {
+            let mut folded = HashMap::<String, usize>::new();
+            for (stack, count) in stacks {
+                let frames: Vec<String> = (stack.iter().enumerate().rev())
+                    .map(|(i, &address)| {
+                        // return addresses point after the call instruction
+                        let lookup = if i == 0 {
+                            address
+                        } else {
+                            address.saturating_sub(1)
+                        };
+                        match symbolizer
+                            .as_mut()
+                            .and_then(|x| x.locate(lookup)?.function.as_deref())
+                        {
+                            Some(function) => function.replace(';', ":"),
+                            None => format!("{address:#x}"),
+                        }
+                    })
+                    .collect();
+                *folded.entry(frames.join(";")).or_default() += count;
+            }
+            let mut folded: Vec<_> = folded.into_iter().collect();
+            folded.sort();
+            let mut out = String::new();
+            for (stack, count) in folded {
+                writeln!(out, "{stack} {count}").unwrap();
+            }
+            let name = if labels.is_empty() {
+                "all".to_string()
+            } else {
+                file_name(&labels)
+            };
+            let path = self.dir.join(format!("{name}.folded"));
+            if let Err(e) = fs::write(&path, out) {
+                eprintln!("failed to write {}: {e}", path.display());
+            }
+        }
+        if self.lost > 0 {
+            eprintln!("{} flamegraph samples were lost", self.lost);
+            self.lost = 0;
+        }
+        eprintln!("flamegraphs written to {}", self.dir.display());
+    }
+}
+
+/// Markers in a callchain separating kernel and user space frames are the highest 4095 values.
+const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+
+/// Returns the call stacks of the sample records in `records`, starting at the leaf.
+///
+/// Samples must contain only the instruction pointer and the callchain.
+fn callchains(records: &[u8]) -> Vec<Vec<u64>> {
+    let word = |offset: usize| u64::from_ne_bytes(records[offset..offset + 8].try_into().unwrap());
+    let mut stacks = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= records.len() {
+        let kind = u32::from_ne_bytes(records[offset..offset + 4].try_into().unwrap());
+        let len = u16::from_ne_bytes(records[offset + 6..offset + 8].try_into().unwrap()).max(8);
+        if kind == PERF_RECORD_SAMPLE && len >= 24 {
+            let nr = word(offset + 16) as usize;
+            let stack: Vec<u64> = (0..nr.min((len as usize - 24) / 8))
+                .map(|i| word(offset + 24 + 8 * i))
+                .filter(|&x| x < PERF_CONTEXT_MAX)
+                .collect();
+            if stack.is_empty() {
+                stacks.push(vec![word(offset + 8)]);
+            } else {
+                stacks.push(stack);
+            }
+        }
+        offset += len as usize;
+    }
+    stacks
+}
+
+#[test]
+fn test_callchains() {
+    let mut records = Vec::new();
+    let mut push = |words: &[u64]| {
+        records.extend_from_slice(&PERF_RECORD_SAMPLE.to_ne_bytes());
+        records.extend_from_slice(&0u16.to_ne_bytes());
+        records.extend_from_slice(&(8 + 8 * words.len() as u16).to_ne_bytes());
+        for x in words {
+            records.extend_from_slice(&x.to_ne_bytes());
+        }
+    };
+    push(&[0x10, 3, -512i64 as u64, 0x10, 0x20]);
+    push(&[0x30, 0]);
+    assert_eq!(callchains(&records), [vec![0x10, 0x20], vec![0x30]]);
+}
@@ -1,18 +1,43 @@
+#[cfg(feature = "csv")]
 mod csv;
+mod discard;
+mod export;
+#[cfg(feature = "live")]
 mod live;
+#[cfg(feature = "live")]
 mod live_table;
+mod repro;
+#[cfg(feature = "markdown")]
 mod tabled;
 mod tabled_float;
 
+pub use crate::transform::Aggregate;
+#[cfg(feature = "csv")]
 pub use csv::Csv;
+pub use discard::Discard;
+pub use export::Export;
+#[cfg(feature = "live")]
 pub use live::Live;
-pub use live_table::LiveTable;
-pub use tabled::Tabled;
-pub use tabled_float::TabledFloat;
+#[cfg(feature = "live")]
+pub use live_table::{Alignment, LiveTable};
+pub use repro::Repro;
+#[cfg(feature = "markdown")]
+pub use tabled::{Highlight, NumberFormat, Reference, Sort, SummaryRow, Tabled};
+pub use tabled_float::{FloatFormat, TabledFloat};
 
-use crate::{counters::Counters, labels::LabelMeta};
-use std::error::Error;
+use crate::{
+    Metadata,
+    config::Config,
+    counters::{CounterMeta, Counters},
+    labels::LabelMeta,
+    transform::{ExtraLabels, RepIndex, ScaleCheck, Throughput, Timestamp, Transform},
+};
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+};
 
+#[allow(clippy::type_complexity)]
 pub trait Format {
     fn push(
         &mut self,
@@ -29,6 +54,7 @@ pub trait Format {
     ) -> Result<(), Box<dyn Error>>;
 }
 
+#[allow(clippy::type_complexity)]
 impl Format for Box<dyn Format> {
     fn push(
         &mut self,
@@ -50,20 +76,205 @@ impl Format for Box<dyn Format> {
     }
 }
 
+/// The header of the scale column, including the [unit](Counters::scale_unit) if there is one.
+#[cfg_attr(not(any(feature = "csv", feature = "live")), allow(dead_code))]
+fn scale_header(counters: &dyn Counters) -> String {
+    match counters.scale_unit() {
+        Some(unit) => format!("scale ({unit})"),
+        None => "scale".to_string(),
+    }
+}
+
+/// The header of a counter column, including the [unit](CounterMeta::unit) if there is one.
+#[cfg_attr(not(any(feature = "live", feature = "markdown")), allow(dead_code))]
+fn counter_header(name: &str, meta: CounterMeta) -> String {
+    match meta.unit() {
+        Some(unit) => format!("{name} [{unit}]"),
+        None => name.to_string(),
+    }
+}
+
 pub fn format_from_env() -> Box<dyn Format> {
-    match std::env::var("QPE_FORMAT").as_deref() {
-        Ok("csv") => Box::new(Csv::new()),
-        Ok("md") => Box::new(Tabled::new()),
-        x => {
-            match x {
-                Ok(requested) => {
-                    eprintln!(
-                        "unrecognized value for QPE_FORMAT: {requested:?}.\nSupported values: csv, md"
-                    );
-                }
-                Err(_) => {}
-            }
-            Box::new(Live::new())
+    format_from_config(&Config::default())
+}
+
+/// The values of `QPE_FORMAT` supported with the enabled features.
+const SUPPORTED_FORMATS: &[&str] = &[
+    #[cfg(feature = "live")]
+    "live",
+    #[cfg(feature = "markdown")]
+    "md",
+    #[cfg(feature = "csv")]
+    "csv",
+    "all",
+];
+
+/// Constructs a format from settings, see [`register`].
+pub type FormatFactory = dyn Fn(&Config) -> Box<dyn Format> + Send + Sync;
+
+static REGISTRY: Mutex<Vec<(String, Arc<FormatFactory>)>> = Mutex::new(Vec::new());
+
+/// Register a format selectable by setting `QPE_FORMAT` to `name`.
+///
+/// This allows applications and other crates to add exporters, e.g. `register("json", |_| Box::new(MyJson::new()))`.
+/// The factory receives the [`Config`] the format is created from, so it may read its own settings.
+/// Registered formats take precedence over built-in formats of the same name.
+/// Registering a name again replaces the previous factory.
+pub fn register(name: &str, factory: impl Fn(&Config) -> Box<dyn Format> + Send + Sync + 'static) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|x| x.0 != name);
+    registry.push((name.to_string(), Arc::new(factory)));
+}
+
+/// Like [`format_from_env`], but reads settings from a [`Config`].
+///
+/// If the `live` feature is disabled, the first available of markdown, CSV, and [`Discard`] is used by default.
+/// If `QPE_REP_INDEX` is set, a [`RepIndex`] column is added.
+/// If `QPE_THROUGHPUT` is set, a [`Throughput`] column is added.
+/// If `QPE_CHECK_SCALE` is set, readings are checked by [`ScaleCheck`].
+/// If `QPE_TIMESTAMP` is set, a [`Timestamp`] column is added.
+/// If `QPE_LABELS` is set, [`ExtraLabels`] are added.
+/// If `QPE_REPRO` is set, a [`Repro`] snippet is written to the given file.
+pub fn format_from_config(config: &Config) -> Box<dyn Format> {
+    let mut format = base_format_from_config(config);
+    if config.flag("REP_INDEX") {
+        format = Box::new(RepIndex::new().then(format));
+    }
+    if config.flag("THROUGHPUT") {
+        format = Box::new(Throughput.then(format));
+    }
+    if config.flag("CHECK_SCALE") {
+        format = Box::new(ScaleCheck::new().then(format));
+    }
+    if let Some(timestamp) = config.parse::<Timestamp>("TIMESTAMP") {
+        format = Box::new(timestamp.then(format));
+    }
+    if let Some(labels) = config.parse::<ExtraLabels>("LABELS") {
+        format = Box::new(labels.then(format));
+    }
+    if let Some(path) = config.get("REPRO") {
+        let mut settings = config.settings();
+        settings.retain(|x| x.0 != "REPRO");
+        format = Box::new(Repro::new(format, path).with_settings(settings));
+    }
+    format
+}
+
+fn base_format_from_config(config: &Config) -> Box<dyn Format> {
+    let requested = config.get("FORMAT");
+    let registered = REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|x| Some(&x.0) == requested.as_ref())
+        .map(|x| x.1.clone());
+    if let Some(factory) = registered {
+        return factory(config);
+    }
+    match requested.as_deref() {
+        #[cfg(feature = "csv")]
+        Some("csv") => Box::new(Csv::new().with_metadata(metadata(config))),
+        #[cfg(feature = "markdown")]
+        Some("md") => Box::new(tabled_from_config(config)),
+        #[cfg(feature = "live")]
+        Some("live") => default_format(config),
+        Some("all") => {
+            let dir = config.get("EXPORT_DIR").unwrap_or("qpe-export".to_string());
+            Box::new(Export::new(default_format(config), dir).with_metadata(metadata(config)))
         }
+        Some(requested) => {
+            let registry = REGISTRY.lock().unwrap();
+            let supported: Vec<&str> = SUPPORTED_FORMATS
+                .iter()
+                .copied()
+                .chain(registry.iter().map(|x| x.0.as_str()))
+                .collect();
+            eprintln!(
+                "unrecognized value for {}: {requested:?}.\nSupported values: {}",
+                config.name("FORMAT"),
+                supported.join(", ")
+            );
+            default_format(config)
+        }
+        // the live table is meant for terminals, piped output is more useful as CSV
+        #[cfg(feature = "csv")]
+        None if !std::io::IsTerminal::is_terminal(&std::io::stdout()) => {
+            Box::new(Csv::new().with_metadata(metadata(config)))
+        }
+        None => default_format(config),
     }
 }
+
+#[allow(unreachable_code, unused_variables)]
+fn default_format(config: &Config) -> Box<dyn Format> {
+    #[cfg(feature = "live")]
+    return Box::new(
+        Live::new()
+            .with_coalesce(config.flag("COALESCE"))
+            .with_line_len(config.parse("LINE_LEN"))
+            .with_header_interval(config.parse("HEADER_INTERVAL"))
+            .with_ascii(config.flag("ASCII"))
+            .with_float_format(config.parse("FLOAT_FORMAT").unwrap_or_default()),
+    );
+    #[cfg(feature = "markdown")]
+    return Box::new(tabled_from_config(config));
+    #[cfg(feature = "csv")]
+    return Box::new(Csv::new().with_metadata(metadata(config)));
+    Box::new(Discard)
+}
+
+/// Returns the metadata written by formats if `QPE_METADATA` is set.
+#[cfg_attr(not(any(feature = "csv", feature = "markdown")), allow(dead_code))]
+fn metadata(config: &Config) -> Option<Metadata> {
+    config
+        .flag("METADATA")
+        .then(|| Metadata::from_config(config))
+}
+
+#[cfg(feature = "markdown")]
+#[cfg_attr(not(feature = "csv"), allow(unused_variables))]
+fn tabled_from_config(config: &Config) -> Tabled {
+    let tabled = Tabled::new()
+        .with_aggregate(config.parse("AGGREGATE"))
+        .with_row_key(crate::RowKey::from_config(config))
+        .with_metadata(metadata(config))
+        .with_sort(config.parse("SORT"))
+        .with_summary_row(config.parse("SUMMARY_ROW"))
+        .with_reference(config.parse("REFERENCE"))
+        .with_number_format(config.parse("NUMBER_FORMAT").unwrap_or_default())
+        .with_float_format(config.parse("FLOAT_FORMAT").unwrap_or_default());
+    let Some(highlight) = config.parse::<Highlight>("HIGHLIGHT") else {
+        return tabled;
+    };
+    let Some(path) = config.get("BASELINE") else {
+        eprintln!(
+            "{} requires a baseline, set {}",
+            config.name("HIGHLIGHT"),
+            config.name("BASELINE")
+        );
+        return tabled;
+    };
+    #[cfg(feature = "csv")]
+    match crate::Report::from_csv(&path) {
+        Ok(baseline) => {
+            let threshold = config.parse("HIGHLIGHT_THRESHOLD").unwrap_or(5.0);
+            return tabled.with_highlight(Some((highlight, baseline, threshold)));
+        }
+        Err(e) => eprintln!("failed to read baseline {path}: {e}"),
+    }
+    #[cfg(not(feature = "csv"))]
+    eprintln!("reading the baseline {path} requires the csv feature");
+    tabled
+}
+
+#[test]
+fn test_register() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    register("test-discard", |_| {
+        CREATED.fetch_add(1, Ordering::Relaxed);
+        Box::new(Discard)
+    });
+    format_from_config(&Config::empty().with_value("FORMAT", "test-discard"));
+    assert_eq!(CREATED.load(Ordering::Relaxed), 1);
+}
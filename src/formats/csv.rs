@@ -1,31 +1,75 @@
-use super::Format;
+use super::{Format, scale_header};
 use crate::{
-    counters::{CounterReading, Counters},
+    Metadata,
+    counters::{CounterReading, Counters, read_by_name},
     labels::LabelMeta,
     visit,
 };
 use std::{
     error::Error,
-    io::{Write, stdout},
-    iter,
+    io::{Write, sink, stdout},
+    iter, mem,
     time::UNIX_EPOCH,
 };
 
+/// Streams readings as CSV records.
+///
+/// Each record contains the labels, start time, scale, and counter values, followed by whether any counter was `multiplexed`.
+/// The last columns hold the fraction of time each counter was running, e.g. `cycle/running`, so unreliable counters can be weighted or discarded individually.
 pub struct Csv {
     header_written: bool,
+    metadata: Option<Metadata>,
+    /// The counters in the header, in order.
+    counter_names: Vec<String>,
     reading_buffer: Vec<CounterReading>,
     writer: csv::Writer<Box<dyn Write>>,
 }
 
+impl Default for Csv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Csv {
     pub fn new() -> Self {
         Csv {
             header_written: false,
+            metadata: None,
+            counter_names: Vec::new(),
             reading_buffer: Vec::new(),
-            writer: csv::Writer::from_writer(Box::new(stdout())),
+            writer: csv_writer(Box::new(stdout())),
         }
     }
+
+    /// Write records to `writer` instead of stdout.
+    pub fn with_writer(mut self, writer: impl Write + 'static) -> Self {
+        self.writer = csv_writer(Box::new(writer));
+        self
+    }
+
+    /// Write metadata as comment lines starting with `#` before the header, e.g. `# cores: 8`.
+    ///
+    /// [`Report::from_csv`](crate::Report::from_csv) skips these lines, other tools may need to be told to.
+    pub fn with_metadata(mut self, metadata: Option<Metadata>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// Creates a csv writer that quotes fields starting with `#`, so they are not mistaken for comments when read back.
+fn csv_writer(output: Box<dyn Write>) -> csv::Writer<Box<dyn Write>> {
+    csv::WriterBuilder::new()
+        .comment(Some(b'#'))
+        .from_writer(output)
 }
+
+/// Flushes the csv writer and returns the underlying writer, leaving a placeholder.
+fn take_output(writer: &mut csv::Writer<Box<dyn Write>>) -> Result<Box<dyn Write>, Box<dyn Error>> {
+    let writer = mem::replace(writer, csv_writer(Box::new(sink())));
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
 impl Format for Csv {
     fn push(
         &mut self,
@@ -38,19 +82,32 @@ impl Format for Csv {
         let mut err = Ok(());
         if !self.header_written {
             self.header_written = true;
+            if let Some(metadata) = &self.metadata {
+                // comments are written to the underlying writer, which the csv writer only gives up by value
+                let mut output = take_output(&mut self.writer)?;
+                for (name, value) in metadata.entries() {
+                    writeln!(output, "# {name}: {}", value.replace('\n', " "))?;
+                }
+                self.writer = csv_writer(output);
+            }
             visit(label_meta, &mut |x| {
                 if err.is_ok() {
                     err = self.writer.write_field(x.name())
                 }
             });
             self.writer.write_field("start_time")?;
-            self.writer.write_field("scale")?;
+            self.writer.write_field(scale_header(counters))?;
+            self.counter_names.clear();
             counters.names(&mut |x| {
+                self.counter_names.push(x.to_string());
                 if err.is_ok() {
                     err = self.writer.write_field(x)
                 }
             });
             self.writer.write_field("multiplexed")?;
+            for name in &self.counter_names {
+                self.writer.write_field(format!("{name}/running"))?;
+            }
             self.writer.write_record(iter::empty::<&[u8]>())?;
         }
         labels(&mut |x| {
@@ -60,15 +117,15 @@ impl Format for Csv {
         });
         err?;
         self.writer.write_field(
-            &start_time
+            start_time
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs_f64()
                 .to_string(),
         )?;
         self.reading_buffer.clear();
-        counters.read(&mut self.reading_buffer);
-        self.writer.write_field(&scale.to_string())?;
+        read_by_name(counters, &self.counter_names, &mut self.reading_buffer);
+        self.writer.write_field(scale.to_string())?;
         let mut any_multiplexed = false;
         for reading in &self.reading_buffer {
             any_multiplexed |= reading.multiplexed;
@@ -76,6 +133,9 @@ impl Format for Csv {
                 .write_field(reading.scaled_value(scale).to_string())?;
         }
         self.writer.write_field(any_multiplexed.to_string())?;
+        for reading in &self.reading_buffer {
+            self.writer.write_field(reading.running.to_string())?;
+        }
         self.writer.write_record(iter::empty::<&[u8]>())?;
         self.writer.flush()?;
         Ok(())
@@ -87,6 +147,8 @@ impl Format for Csv {
         _counters: &mut dyn Counters,
     ) -> Result<(), Box<dyn Error>> {
         self.header_written = false;
+        // a new writer accepts a different number of fields in the next table
+        self.writer = csv_writer(take_output(&mut self.writer)?);
         Ok(())
     }
 }
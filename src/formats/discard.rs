@@ -0,0 +1,31 @@
+use super::Format;
+use crate::{counters::Counters, labels::LabelMeta};
+use std::error::Error;
+
+/// A format that discards all readings.
+///
+/// This is the default format if no output format features are enabled.
+/// To process readings programmatically in a minimal build, implement a custom [`Format`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Discard;
+
+impl Format for Discard {
+    fn push(
+        &mut self,
+        _scale: usize,
+        _start_time: std::time::SystemTime,
+        _counters: &mut dyn Counters,
+        _labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        _label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        _label_meta: &'static [LabelMeta],
+        _counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use super::Format;
+use crate::{Metadata, Report, counters::Counters, labels::LabelMeta};
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Wraps a format, additionally writing the session in every available format to a directory, see [`Report::export_all`].
+///
+/// The files are written when the report is finished.
+/// This avoids rerunning long sessions to obtain another representation of the results.
+pub struct Export<F> {
+    inner: F,
+    dir: PathBuf,
+    basename: String,
+    metadata: Option<Metadata>,
+    report: Report,
+}
+
+impl<F: Format> Export<F> {
+    /// Creates an export to `dir` with the basename `report`.
+    pub fn new(inner: F, dir: impl Into<PathBuf>) -> Self {
+        Export {
+            inner,
+            dir: dir.into(),
+            basename: "report".to_string(),
+            metadata: None,
+            report: Report::default(),
+        }
+    }
+
+    /// Set the name of the files without extension.
+    pub fn with_basename(mut self, basename: &str) -> Self {
+        self.basename = basename.to_string();
+        self
+    }
+
+    /// Write metadata at the start of the CSV and markdown files, and as a `metadata` object in the JSON file.
+    pub fn with_metadata(mut self, metadata: Option<Metadata>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+impl<F: Format> Format for Export<F> {
+    fn push(
+        &mut self,
+        scale: usize,
+        start_time: SystemTime,
+        counters: &mut dyn Counters,
+        labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        self.report
+            .push(scale, start_time, counters, labels, label_meta)?;
+        self.inner
+            .push(scale, start_time, counters, labels, label_meta)
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        label_meta: &'static [LabelMeta],
+        counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.dump_and_reset(label_meta, counters)?;
+        if !self.report.rows.is_empty() {
+            (self.report).export(&self.dir, &self.basename, self.metadata.as_ref())?;
+        }
+        self.report.rows.clear();
+        Ok(())
+    }
+}
+
+impl Report {
+    /// Writes the report in every available format to files in `dir`, named `basename` with the extension of the format.
+    ///
+    /// The formats are CSV (`.csv`, requires the `csv` feature), JSON (`.json`), a markdown table (`.md`, requires the `markdown` feature), and an HTML table (`.html`).
+    /// The directory is created if it does not exist, existing files are overwritten.
+    pub fn export_all(&self, dir: impl AsRef<Path>, basename: &str) -> Result<(), Box<dyn Error>> {
+        self.export(dir.as_ref(), basename, None)
+    }
+
+    fn export(
+        &self,
+        dir: &Path,
+        basename: &str,
+        metadata: Option<&Metadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let path = |extension: &str| dir.join(format!("{basename}.{extension}"));
+        #[cfg(feature = "csv")]
+        self.write(
+            &mut super::Csv::new()
+                .with_writer(fs::File::create(path("csv"))?)
+                .with_metadata(metadata.cloned()),
+        )?;
+        #[cfg(feature = "markdown")]
+        self.write(
+            &mut super::Tabled::new()
+                .with_writer(fs::File::create(path("md"))?)
+                .with_metadata(metadata.cloned()),
+        )?;
+        fs::write(path("json"), json(self, metadata))?;
+        fs::write(path("html"), html(self, basename))?;
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Formats the rows as a JSON object with the metadata if any, the scale unit, and an array of rows.
+///
+/// Counter values are normalized, values that are not finite are written as `null`.
+fn json(report: &Report, metadata: Option<&Metadata>) -> String {
+    let mut out = String::from("{\n");
+    if let Some(metadata) = metadata {
+        let entries: Vec<String> = (metadata.entries().iter())
+            .map(|(name, value)| format!("{}: {}", json_string(name), json_string(value)))
+            .collect();
+        writeln!(out, "  \"metadata\": {{{}}},", entries.join(", ")).unwrap();
+    }
+    let unit = report
+        .scale_unit
+        .as_deref()
+        .map_or("null".into(), json_string);
+    writeln!(out, "  \"scale_unit\": {unit},\n  \"rows\": [").unwrap();
+    for (i, row) in report.rows.iter().enumerate() {
+        let labels: Vec<String> = (report.label_meta.iter())
+            .zip(&row.labels)
+            .map(|(meta, value)| format!("{}: {}", json_string(meta.name()), json_string(value)))
+            .collect();
+        let counters: Vec<String> = (report.counter_names.iter().enumerate())
+            .map(|(j, name)| format!("{}: {}", json_string(name), json_number(row.value(j))))
+            .collect();
+        let start_time = row.start_time.duration_since(UNIX_EPOCH).unwrap();
+        write!(
+            out,
+            "    {{\"labels\": {{{}}}, \"start_time\": {}, \"scale\": {}, \"counters\": {{{}}}, \"multiplexed\": {}}}",
+            labels.join(", "),
+            start_time.as_secs_f64(),
+            row.scale,
+            counters.join(", "),
+            row.readings.iter().any(|x| x.multiplexed),
+        )
+        .unwrap();
+        out.push_str(if i + 1 < report.rows.len() {
+            ",\n"
+        } else {
+            "\n"
+        });
+    }
+    out.push_str("  ]\n}\n");
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats the rows as an HTML document containing a table.
+///
+/// Like the markdown table, this omits [detail](crate::counters::CounterMeta::detail) columns.
+fn html(report: &Report, title: &str) -> String {
+    let columns: Vec<usize> = (0..report.counter_names.len())
+        .filter(|&i| !report.counter_meta.get(i).is_some_and(|x| x.detail()))
+        .collect();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<table>",
+        html_escape(title)
+    )
+    .unwrap();
+    out.push_str("<tr>");
+    let scale = match &report.scale_unit {
+        Some(unit) => format!("scale ({unit})"),
+        None => "scale".to_string(),
+    };
+    let header = (report.label_meta.iter().map(|x| x.name()))
+        .chain([scale.as_str()])
+        .chain(columns.iter().map(|&i| report.counter_names[i].as_str()));
+    for name in header {
+        write!(out, "<th>{}</th>", html_escape(name)).unwrap();
+    }
+    out.push_str("</tr>\n");
+    for row in &report.rows {
+        out.push_str("<tr>");
+        for label in &row.labels {
+            write!(out, "<td>{}</td>", html_escape(label)).unwrap();
+        }
+        write!(out, "<td>{}</td>", row.scale).unwrap();
+        for &i in &columns {
+            write!(out, "<td>{:.3}</td>", row.value(i)).unwrap();
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+#[test]
+fn test_json() {
+    use crate::{Row, counters::CounterReading};
+    static META: [LabelMeta; 1] = [LabelMeta::new("name")];
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let report = Report {
+        label_meta: &META,
+        counter_names: vec!["time".into(), "cycle".into()],
+        counter_meta: Vec::new(),
+        scale_unit: None,
+        rows: vec![Row {
+            labels: vec!["a \"b\"".into()],
+            scale: 2,
+            start_time: UNIX_EPOCH,
+            readings: vec![reading(3.0), reading(f64::NAN)],
+        }],
+    };
+    assert_eq!(
+        json(&report, None),
+        "{\n  \"scale_unit\": null,\n  \"rows\": [\n    {\"labels\": {\"name\": \"a \\\"b\\\"\"}, \"start_time\": 0, \"scale\": 2, \"counters\": {\"time\": 1.5, \"cycle\": null}, \"multiplexed\": false}\n  ]\n}\n"
+    );
+}
@@ -1,23 +1,276 @@
-use super::{Format, LiveTable, TabledFloat};
+use super::{Alignment, FloatFormat, Format, LiveTable, counter_header, scale_header};
 use crate::{
-    counters::{CounterReading, Counters, count_counters},
+    config::Config,
+    counters::{CounterReading, Counters, read_by_name},
     labels::LabelMeta,
     visit,
 };
-use std::{env, error::Error, iter};
+use std::{error::Error, io, iter};
 
 pub struct Live {
     inner: Option<Inner>,
+    coalesce: bool,
+    line_len: Option<usize>,
+    header_interval: Option<usize>,
+    ascii: bool,
+    float_format: FloatFormat,
 }
 
 struct Inner {
     table: LiveTable,
+    label_meta: &'static [LabelMeta],
+    /// The names of all counters, including hidden ones.
+    counter_names: Vec<String>,
     reading_buffer: Vec<CounterReading>,
+    /// Whether each counter is shown, detail counters are hidden.
+    shown: Vec<bool>,
+    /// Notes of the shown counters.
+    notes: Vec<Option<&'static str>>,
+    /// Whether each shown counter is displayed as a percentage.
+    percent: Vec<bool>,
+    /// Notes of counters with marked values, printed below the table.
+    used_notes: Vec<&'static str>,
+    /// Whether any value was marked as multiplexed or invalid.
+    any_multiplexed: bool,
+    any_invalid: bool,
+    pending: Option<Coalesced>,
+    /// The cells of the header row.
+    header: Vec<String>,
+    /// Repeat the header after this many rows.
+    header_interval: Option<usize>,
+    /// Rows written since the header.
+    rows: usize,
+    /// The requested width of each column.
+    widths: Vec<usize>,
+    ascii: bool,
+    /// The line length the table is laid out for.
+    line_len: usize,
+    /// If `true`, the line length follows the size of the terminal.
+    auto_line_len: bool,
+    float_format: FloatFormat,
+}
+
+impl Inner {
+    fn write_header(&mut self) -> io::Result<()> {
+        for cell in &self.header {
+            self.table.push(cell.clone())?;
+        }
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Starts a new table if the terminal was resized and repeats the header if the interval has passed.
+    ///
+    /// Must be called before writing a row.
+    fn start_row(&mut self) -> io::Result<()> {
+        if self.auto_line_len
+            && let Some(line_len) = terminal_line_len()
+            && line_len != self.line_len
+        {
+            self.table.end_table()?;
+            self.line_len = line_len;
+            self.table = new_table(self.label_meta, &self.widths, line_len, self.ascii);
+            self.write_header()?;
+        } else if let Some(interval) = self.header_interval
+            && interval > 0
+            && self.rows == interval
+        {
+            self.write_header()?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn push_label(&mut self, i: usize, label: &str) -> io::Result<()> {
+        let label = self.label_meta[i].fit(label).into_owned();
+        self.table.push(label)
+    }
+
+    /// Pushes the value cells, marking values with a note, multiplexed values, and invalid values.
+    fn push_values(&mut self, cells: impl Iterator<Item = Cell>) -> io::Result<()> {
+        for ((cell, note), percent) in cells.zip(&self.notes).zip(&self.percent) {
+            let text = if *percent {
+                self.float_format.display_percent(cell.value).to_string()
+            } else {
+                self.float_format.display(cell.value).to_string()
+            };
+            let mut marks = String::new();
+            if let Some(note) = note
+                && cell.value != 0.0
+            {
+                if !self.used_notes.contains(note) {
+                    self.used_notes.push(note);
+                }
+                marks.push('*');
+            }
+            if cell.multiplexed {
+                self.any_multiplexed = true;
+                marks.push('~');
+            }
+            if !cell.valid {
+                self.any_invalid = true;
+                marks.push('?');
+            }
+            if marks.is_empty() {
+                self.table.push(text)?;
+            } else {
+                let width = self.float_format.width();
+                self.table.push(format!(
+                    "{:>width$}",
+                    format!("{}{marks}", text.trim_start())
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A counter value with the flags of the readings it was computed from.
+#[derive(Clone, Copy)]
+struct Cell {
+    value: f64,
+    multiplexed: bool,
+    valid: bool,
+}
+
+impl Cell {
+    fn new(reading: &CounterReading, scale: usize) -> Self {
+        Cell {
+            value: reading.scaled_value(scale),
+            multiplexed: reading.multiplexed,
+            valid: reading.valid,
+        }
+    }
+}
+
+/// Consecutive records with identical labels, aggregated into a single row.
+struct Coalesced {
+    labels: Vec<String>,
+    runs: usize,
+    scale: f64,
+    /// The sums of the values, with the flags of all merged readings.
+    sums: Vec<Cell>,
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+impl Coalesced {
+    fn new(labels: Vec<String>, scale: usize, sums: Vec<Cell>) -> Self {
+        Coalesced {
+            labels,
+            runs: 1,
+            scale: scale as f64,
+            min: sums.iter().map(|x| x.value).collect(),
+            max: sums.iter().map(|x| x.value).collect(),
+            sums,
+        }
+    }
+
+    fn add(&mut self, scale: usize, cells: &[Cell]) {
+        self.runs += 1;
+        self.scale += scale as f64;
+        for (i, cell) in cells.iter().enumerate() {
+            let sum = &mut self.sums[i];
+            sum.value += cell.value;
+            sum.multiplexed |= cell.multiplexed;
+            sum.valid &= cell.valid;
+            self.min[i] = self.min[i].min(cell.value);
+            self.max[i] = self.max[i].max(cell.value);
+        }
+    }
+
+    /// The largest difference between the minimum and maximum of any counter, relative to its mean.
+    fn spread(&self) -> f64 {
+        let runs = self.runs as f64;
+        (self.sums.iter().zip(&self.min).zip(&self.max))
+            .map(|((sum, min), max)| (max - min) / (sum.value / runs).abs())
+            .filter(|x| x.is_finite())
+            .fold(0.0, f64::max)
+    }
+
+    fn write(&self, inner: &mut Inner) -> io::Result<()> {
+        inner.start_row()?;
+        for (i, label) in self.labels.iter().enumerate() {
+            inner.push_label(i, label)?;
+        }
+        let runs = self.runs as f64;
+        inner.table.push(self.runs.to_string())?;
+        let spread = inner
+            .float_format
+            .display_percent(self.spread())
+            .to_string();
+        inner.table.push(spread)?;
+        inner
+            .table
+            .push(inner.float_format.display(self.scale / runs).to_string())?;
+        inner.push_values(self.sums.iter().map(|x| Cell {
+            value: x.value / runs,
+            ..*x
+        }))
+    }
+}
+
+impl Default for Live {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Live {
     pub fn new() -> Self {
-        Live { inner: None }
+        Live {
+            inner: None,
+            coalesce: false,
+            line_len: Config::default().parse("LINE_LEN"),
+            header_interval: None,
+            ascii: false,
+            float_format: FloatFormat::new(),
+        }
+    }
+
+    /// Override the line length used for line wrapping.
+    ///
+    /// If `None`, terminal size is detected automatically.
+    /// When the terminal is resized, the table is ended and continued in a new table with a fresh header, laid out for the new size.
+    /// By default, this is set from the environment variable `QPE_LINE_LEN`.
+    pub fn with_line_len(mut self, line_len: Option<usize>) -> Self {
+        self.line_len = line_len;
+        self
+    }
+
+    /// Coalesce consecutive records with identical labels into a single row.
+    ///
+    /// The row shows the number of records in an additional `runs` column and the mean of scale and all counters.
+    /// The `spread` column shows the largest difference between the minimum and maximum of any counter across the records, relative to its mean.
+    /// Values are marked if any merged record was multiplexed or invalid.
+    /// A row is printed once a record with different labels arrives or the report is finished.
+    pub fn with_coalesce(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Repeat the header row after every `interval` rows, so the column names stay visible in long tables.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_HEADER_INTERVAL`.
+    pub fn with_header_interval(mut self, interval: Option<usize>) -> Self {
+        self.header_interval = interval;
+        self
+    }
+
+    /// Set how counter values are formatted, see [`FloatFormat`].
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_FLOAT_FORMAT`.
+    pub fn with_float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    /// Draw the table using only ASCII characters, for logs and terminals not displaying box-drawing characters.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_ASCII`.
+    pub fn with_ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
     }
 }
 
@@ -30,54 +283,112 @@ impl Format for Live {
         labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
         label_meta: &'static [LabelMeta],
     ) -> Result<(), Box<dyn Error>> {
-        let mut err = Ok(());
-        let this = self.inner.get_or_insert_with(|| {
-            let num_counters = count_counters(counters);
-            let mut table = LiveTable::new(
-                label_meta
-                    .iter()
-                    .map(|x| x.width())
-                    .chain(iter::repeat_n(7, num_counters + 1))
-                    .collect(),
-                env::var("QPE_LINE_LEN")
-                    .ok()
-                    .and_then(|x| {
-                        x.parse()
-                            .map_err(|_| {
-                                eprintln!("failed to parse line len: {x:?}");
-                            })
-                            .ok()
-                    })
-                    .or_else(|| terminal_size::terminal_size().map(|x| x.0.0 as usize))
-                    .unwrap_or(160),
-            );
-            let push = &mut |x: &str| {
-                if err.is_ok() {
-                    err = table.push(x.to_string());
+        let coalesce = self.coalesce;
+        if self.inner.is_none() {
+            let line_len = self.line_len.or_else(terminal_line_len).unwrap_or(160);
+            let mut shown = Vec::new();
+            let mut notes = Vec::new();
+            let mut percent = Vec::new();
+            let mut meta = Vec::new();
+            let mut widths: Vec<usize> = label_meta.iter().map(|x| x.width()).collect();
+            let value_width = self.float_format.width();
+            widths.extend(iter::repeat_n(value_width, 1 + 2 * coalesce as usize));
+            counters.meta(&mut |x| {
+                meta.push(x);
+                shown.push(!x.detail());
+                if !x.detail() {
+                    notes.push(x.note());
+                    percent.push(x.percent());
+                    widths.push(x.width().map_or(value_width, |w| w.max(value_width)));
                 }
-            };
+            });
+            let mut header = Vec::new();
+            let push = &mut |x: &str| header.push(x.to_string());
             visit(label_meta, &mut |x| push(x.name()));
-            push("scale");
-            counters.names(push);
-            Inner {
-                table,
-                reading_buffer: Vec::with_capacity(num_counters),
+            if coalesce {
+                push("runs");
+                push("spread");
             }
+            push(&scale_header(counters));
+            let mut counter_names = Vec::new();
+            let mut i = 0;
+            let mut column = header.len();
+            counters.names(&mut |name| {
+                counter_names.push(name.to_string());
+                if shown[i] {
+                    let counter_header = counter_header(name, meta[i]);
+                    // a unit must not make the header wrap, e.g. `t-clock [s]`
+                    if meta[i].unit().is_some() {
+                        widths[column] = widths[column].max(counter_header.chars().count());
+                    }
+                    header.push(counter_header);
+                    column += 1;
+                }
+                i += 1;
+            });
+            let mut inner = Inner {
+                table: new_table(label_meta, &widths, line_len, self.ascii),
+                label_meta,
+                counter_names,
+                reading_buffer: Vec::with_capacity(shown.len()),
+                shown,
+                notes,
+                percent,
+                used_notes: Vec::new(),
+                any_multiplexed: false,
+                any_invalid: false,
+                pending: None,
+                header,
+                header_interval: self.header_interval,
+                rows: 0,
+                widths,
+                ascii: self.ascii,
+                line_len,
+                auto_line_len: self.line_len.is_none(),
+                float_format: self.float_format,
+            };
+            inner.write_header()?;
+            self.inner = Some(inner);
+        }
+        let this = self.inner.as_mut().unwrap();
+        this.reading_buffer.clear();
+        read_by_name(counters, &this.counter_names, &mut this.reading_buffer);
+        let mut i = 0;
+        this.reading_buffer.retain(|_| {
+            i += 1;
+            this.shown[i - 1]
         });
-        let push = &mut |x: &str| {
+        let cells: Vec<Cell> = (this.reading_buffer.iter())
+            .map(|x| Cell::new(x, scale))
+            .collect();
+        if coalesce {
+            let mut label_vec = Vec::new();
+            labels(&mut |l| label_vec.push(l.to_string()));
+            if let Some(pending) = &mut this.pending
+                && pending.labels == label_vec
+            {
+                pending.add(scale, &cells);
+                return Ok(());
+            }
+            let coalesced = Coalesced::new(label_vec, scale, cells);
+            if let Some(pending) = this.pending.replace(coalesced) {
+                pending.write(this)?;
+            }
+            return Ok(());
+        }
+        this.start_row()?;
+        let mut i = 0;
+        let mut err = Ok(());
+        labels(&mut |x| {
             if err.is_ok() {
-                err = this.table.push(x.to_string());
+                err = this.push_label(i, x);
             }
-        };
-        labels(push);
-        this.reading_buffer.clear();
-        counters.read(&mut this.reading_buffer);
+            i += 1;
+        });
         err?;
-        this.table.push(TabledFloat(scale as f64).to_string())?;
-        for reading in &this.reading_buffer {
-            this.table
-                .push(TabledFloat(reading.scaled_value(scale)).to_string())?;
-        }
+        let scale_cell = this.float_format.display(scale as f64).to_string();
+        this.table.push(scale_cell)?;
+        this.push_values(cells.into_iter())?;
         Ok(())
     }
 
@@ -86,9 +397,73 @@ impl Format for Live {
         _label_meta: &'static [LabelMeta],
         _counters: &mut dyn Counters,
     ) -> Result<(), Box<dyn Error>> {
-        if let Some(this) = &mut self.inner {
+        if let Some(mut this) = self.inner.take() {
+            if let Some(pending) = this.pending.take() {
+                pending.write(&mut this)?;
+            }
             this.table.end_table()?;
+            for note in this.used_notes.drain(..) {
+                println!("* {note}");
+            }
+            if this.any_multiplexed {
+                println!("~ counter was multiplexed");
+            }
+            if this.any_invalid {
+                println!("? counter could not be read");
+            }
         }
         Ok(())
     }
 }
+
+fn new_table(
+    label_meta: &[LabelMeta],
+    widths: &[usize],
+    line_len: usize,
+    ascii: bool,
+) -> LiveTable {
+    // labels are left aligned, numbers right aligned for comparing magnitudes
+    let alignments = (label_meta.iter().map(|_| Alignment::Left))
+        .chain(iter::repeat(Alignment::Right))
+        .take(widths.len())
+        .collect();
+    LiveTable::new(widths.to_vec(), line_len)
+        .with_alignments(alignments)
+        .with_ascii(ascii)
+}
+
+fn terminal_line_len() -> Option<usize> {
+    terminal_size::terminal_size().map(|x| x.0.0 as usize)
+}
+
+#[test]
+fn test_coalesced() {
+    let cell = |value, multiplexed, valid| Cell {
+        value,
+        multiplexed,
+        valid,
+    };
+    let mut coalesced = Coalesced::new(
+        vec!["a".into()],
+        1,
+        vec![cell(2.0, false, true), cell(1.0, false, true)],
+    );
+    coalesced.add(1, &[cell(4.0, true, true), cell(1.0, false, false)]);
+    assert_eq!(coalesced.runs, 2);
+    assert!(coalesced.sums[0].multiplexed && coalesced.sums[0].valid);
+    assert!(!coalesced.sums[1].multiplexed && !coalesced.sums[1].valid);
+    assert_eq!(coalesced.spread(), 2.0 / 3.0);
+}
+
+#[test]
+fn test_unit_header_width() {
+    use crate::{QuickPerfEvent, counters::TimeBackend};
+    let live = Live::new().with_line_len(Some(160));
+    let mut perf = QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), live);
+    perf.run(|| ()).record(1, "a");
+    let inner = perf.format_mut().inner.as_ref().unwrap();
+    assert_eq!(inner.header.last().unwrap(), "time [s]");
+    for (header, width) in inner.header.iter().zip(&inner.widths) {
+        assert!(header.chars().count() <= *width, "{header:?} wraps");
+    }
+}
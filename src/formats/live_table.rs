@@ -13,11 +13,21 @@ pub struct LiveTable {
     head_separator: String,
     end_separator: String,
     line: Vec<String>,
+    alignments: Vec<Alignment>,
     table_started: bool,
     field_separator: &'static str,
     line_delimiter: &'static str,
 }
 
+/// The horizontal alignment of the content of a column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Alignment {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
 fn group_width(g: &[usize]) -> usize {
     g.iter().sum::<usize>() + g.len() + 1
 }
@@ -51,50 +61,77 @@ impl LiveTable {
     pub fn new(columns_widths: Vec<usize>, line_width: usize) -> Self {
         let line_width = line_width.max(9);
         let column_groups = compute_column_widths(columns_widths, line_width);
-        let cgl = column_groups.len();
-        LiveTable {
+        let mut table = LiveTable {
             line: Vec::new(),
+            alignments: Vec::new(),
             table_started: false,
             field_separator: "│",
             line_delimiter: "│",
             current_cgroup: 0,
-            column_group_separators: (0..column_groups.len())
-                .map(|i| {
-                    if i == 0 {
-                        Self::make_separtor(
-                            &column_groups[(i + cgl - 1) % cgl],
-                            &column_groups[i],
-                            "├",
-                            &["─", "┴", "┬", "┼"],
-                            "┤",
-                        )
-                    } else {
-                        Self::make_separtor(
-                            &column_groups[(i + cgl - 1) % cgl],
-                            &column_groups[i],
-                            "├",
-                            &["╌", "┴", "┬", "┼"],
-                            "┤",
-                        )
-                    }
-                })
-                .collect(),
-            head_separator: Self::make_separtor(
-                &column_groups[0],
-                &column_groups[0],
-                "┌",
+            column_group_separators: Vec::new(),
+            head_separator: String::new(),
+            end_separator: String::new(),
+            column_groups,
+        };
+        table.make_separators(false);
+        table
+    }
+
+    /// Draw the table using only ASCII characters, e.g. `+`, `-`, and `|`, instead of box-drawing characters.
+    pub fn with_ascii(mut self, ascii: bool) -> Self {
+        let delimiter = if ascii { "|" } else { "│" };
+        self.field_separator = delimiter;
+        self.line_delimiter = delimiter;
+        self.make_separators(ascii);
+        self
+    }
+
+    fn make_separators(&mut self, ascii: bool) {
+        let [corners, group_crosses, crosses, head_crosses, end_crosses]: [&[&str]; 5] = if ascii {
+            [
+                &["+"; 6],
+                &["-", "+", "+", "+"],
+                &["-", "+", "+", "+"],
+                &["-", "-", "+", "+"],
+                &["-", "+", "-", "+"],
+            ]
+        } else {
+            [
+                &["├", "┤", "┌", "┐", "└", "┘"],
+                &["╌", "┴", "┬", "┼"],
+                &["─", "┴", "┬", "┼"],
                 &["─", "─", "┬", "┬"],
-                "┐",
-            ),
-            end_separator: Self::make_separtor(
-                &column_groups[cgl - 1],
-                &column_groups[cgl - 1],
-                "└",
                 &["─", "┴", "─", "┴"],
-                "┘",
-            ),
-            column_groups,
-        }
+            ]
+        };
+        let groups = &self.column_groups;
+        let cgl = groups.len();
+        self.column_group_separators = (0..cgl)
+            .map(|i| {
+                Self::make_separtor(
+                    &groups[(i + cgl - 1) % cgl],
+                    &groups[i],
+                    corners[0],
+                    if i == 0 { crosses } else { group_crosses },
+                    corners[1],
+                )
+            })
+            .collect();
+        self.head_separator =
+            Self::make_separtor(&groups[0], &groups[0], corners[2], head_crosses, corners[3]);
+        self.end_separator = Self::make_separtor(
+            &groups[cgl - 1],
+            &groups[cgl - 1],
+            corners[4],
+            end_crosses,
+            corners[5],
+        );
+    }
+
+    /// Set the alignment of each column, columns without an alignment are centered.
+    pub fn with_alignments(mut self, alignments: Vec<Alignment>) -> Self {
+        self.alignments = alignments;
+        self
     }
 
     pub fn push(&mut self, x: String) -> io::Result<()> {
@@ -121,6 +158,9 @@ impl LiveTable {
 
     fn write_content_lines(&mut self, stdout: &mut StdoutLock) -> io::Result<()> {
         let col_widths = &self.column_groups[self.current_cgroup];
+        let first_column: usize = (self.column_groups[..self.current_cgroup].iter())
+            .map(|x| x.len())
+            .sum();
         let cells = self.line.iter().zip(col_widths);
         let cells: Vec<_> = cells.map(|(s, w)| textwrap::wrap(s, *w)).collect();
         let lines = cells.iter().map(|w| w.len()).max().unwrap();
@@ -128,9 +168,13 @@ impl LiveTable {
             write!(stdout, "{}", self.line_delimiter)?;
             for (ci, (cell, width)) in cells.iter().zip(col_widths).enumerate() {
                 let content = cell.get(l).map(|x| x.as_ref()).unwrap_or("");
-                write!(stdout, "{content:^width$}")?;
+                match self.alignments.get(first_column + ci).copied() {
+                    Some(Alignment::Left) => write!(stdout, "{content:<width$}")?,
+                    Some(Alignment::Right) => write!(stdout, "{content:>width$}")?,
+                    Some(Alignment::Center) | None => write!(stdout, "{content:^width$}")?,
+                }
                 if ci + 1 == cells.len() {
-                    write!(stdout, "{}\n", self.line_delimiter)?;
+                    writeln!(stdout, "{}", self.line_delimiter)?;
                 } else {
                     write!(stdout, "{}", self.field_separator)?;
                 }
@@ -198,7 +242,7 @@ fn test_column_widths() {
     for (requested, line, expected) in cases {
         let computed = compute_column_widths(requested, line);
         for l in &expected {
-            let ll = group_width(&l);
+            let ll = group_width(l);
             assert!(
                 ll == group_width(&expected[0]),
                 "bad test case: {l:?} has length {ll}"
@@ -0,0 +1,156 @@
+use super::Format;
+use crate::{counters::Counters, labels::LabelMeta};
+use std::{error::Error, fmt::Write, fs, path::PathBuf, time::SystemTime};
+
+/// Wraps a format, additionally writing a snippet reproducing the session to a file.
+///
+/// The snippet lists the settings of the session as environment variables, followed by a minimal program recording one reading for each distinct set of labels with the same scale.
+/// This makes it easy to share a benchmark setup, e.g. in a bug report.
+/// The file is written when the report is finished.
+pub struct Repro<F> {
+    inner: F,
+    path: PathBuf,
+    settings: Vec<(String, String)>,
+    counter_names: Vec<String>,
+    /// The labels and scale of the first reading of each distinct set of labels.
+    rows: Vec<(Vec<String>, usize)>,
+}
+
+impl<F: Format> Repro<F> {
+    pub fn new(inner: F, path: impl Into<PathBuf>) -> Self {
+        Repro {
+            inner,
+            path: path.into(),
+            settings: Vec::new(),
+            counter_names: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Set the settings listed as environment variables, by name without the `QPE_` prefix.
+    pub fn with_settings(mut self, settings: Vec<(String, String)>) -> Self {
+        self.settings = settings;
+        self
+    }
+}
+
+impl<F: Format> Format for Repro<F> {
+    fn push(
+        &mut self,
+        scale: usize,
+        start_time: SystemTime,
+        counters: &mut dyn Counters,
+        labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut label_vec = Vec::new();
+        labels(&mut |x| label_vec.push(x.to_string()));
+        if self.counter_names.is_empty() {
+            counters.names(&mut |x| self.counter_names.push(x.to_string()));
+        }
+        if !self.rows.iter().any(|x| x.0 == label_vec) {
+            self.rows.push((label_vec.clone(), scale));
+        }
+        self.inner.push(
+            scale,
+            start_time,
+            counters,
+            &mut |dst| label_vec.iter().for_each(|x| dst(x)),
+            label_meta,
+        )
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        label_meta: &'static [LabelMeta],
+        counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.dump_and_reset(label_meta, counters)?;
+        if !self.rows.is_empty() {
+            let text = snippet(&self.settings, label_meta, &self.counter_names, &self.rows);
+            fs::write(&self.path, text)?;
+        }
+        self.counter_names.clear();
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+/// Converts a label name to a Rust identifier.
+fn ident(name: &str) -> String {
+    let ident: String = name
+        .chars()
+        .map(|x| if x.is_alphanumeric() { x } else { '_' })
+        .collect();
+    if ident.starts_with(|x: char| x.is_ascii_digit()) || ident.is_empty() {
+        format!("_{ident}")
+    } else {
+        ident
+    }
+}
+
+fn snippet(
+    settings: &[(String, String)],
+    label_meta: &[LabelMeta],
+    counter_names: &[String],
+    rows: &[(Vec<String>, usize)],
+) -> String {
+    let mut out = String::new();
+    let env: Vec<String> = settings
+        .iter()
+        .map(|(name, value)| format!("QPE_{name}='{}'", value.replace('\'', r"'\''")))
+        .chain(["cargo run --release".to_string()])
+        .collect();
+    let fields: Vec<String> = label_meta.iter().map(|x| ident(x.name())).collect();
+    let labels_type = if fields.is_empty() { "()" } else { "Labels" };
+    writeln!(out, "// Run with: {}", env.join(" ")).unwrap();
+    writeln!(out, "// Recorded columns: {}", counter_names.join(", ")).unwrap();
+    writeln!(out, "use quick_perf_event::QuickPerfEvent;").unwrap();
+    if !fields.is_empty() {
+        writeln!(
+            out,
+            "\nquick_perf_event::struct_labels! {{\n    struct Labels {{"
+        )
+        .unwrap();
+        for field in &fields {
+            writeln!(out, "        {field}: String,").unwrap();
+        }
+        writeln!(out, "    }}\n}}").unwrap();
+    }
+    writeln!(out, "\nfn main() {{").unwrap();
+    writeln!(
+        out,
+        "    let mut perf: QuickPerfEvent<{labels_type}> = quick_perf_event::from_env();"
+    )
+    .unwrap();
+    for (labels, scale) in rows {
+        let labels = if fields.is_empty() {
+            "()".to_string()
+        } else {
+            let values: Vec<String> = fields
+                .iter()
+                .zip(labels)
+                .map(|(field, value)| format!("{field}: {value:?}.into()"))
+                .collect();
+            format!("Labels {{ {} }}", values.join(", "))
+        };
+        writeln!(out, "    perf.run(|| {{\n        // measured code\n    }})").unwrap();
+        writeln!(out, "    .record({scale}, {labels});").unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+#[test]
+fn test_snippet() {
+    static META: [LabelMeta; 2] = [LabelMeta::new("size"), LabelMeta::new("2d")];
+    let text = snippet(
+        &[("EVENTS".into(), "cycle,instr".into())],
+        &META,
+        &["time".into(), "cycle".into()],
+        &[(vec!["10".into(), "it's".into()], 1000)],
+    );
+    assert!(text.starts_with("// Run with: QPE_EVENTS='cycle,instr' cargo run --release\n"));
+    assert!(text.contains("        _2d: String,\n"));
+    assert!(text.contains(".record(1000, Labels { size: \"10\".into(), _2d: \"it's\".into() });"));
+}
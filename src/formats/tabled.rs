@@ -1,9 +1,19 @@
-use super::Format;
+use super::{FloatFormat, Format, counter_header};
 use crate::{
-    counters::{CounterReading, Counters},
+    Metadata, Report, RowKey,
+    counters::{CounterMeta, CounterReading, Counters, read_by_name},
     labels::LabelMeta,
+    regression::significant_change,
+    transform::Aggregate,
+};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    io::{Write, stdout},
+    iter, mem,
+    str::FromStr,
 };
-use std::{error::Error, iter, mem};
 use tabled::settings::Style;
 
 struct PerfReadingExtra {
@@ -14,16 +24,353 @@ struct PerfReadingExtra {
 
 pub struct Tabled {
     readings: Vec<PerfReadingExtra>,
+    /// The names of all counters, in the order of the readings.
+    counter_names: Vec<String>,
+    /// Names of the counters shown, along with their index in a reading and metadata.
+    columns: Vec<(usize, String, CounterMeta)>,
     markdown: bool,
+    aggregate: Option<Aggregate>,
+    highlight: Option<(Highlight, Report, f64)>,
+    key: RowKey,
+    metadata: Option<Metadata>,
+    sort: Option<Sort>,
+    summary_row: Option<SummaryRow>,
+    reference: Option<Reference>,
+    number_format: NumberFormat,
+    float_format: FloatFormat,
+    output: Box<dyn Write>,
+}
+
+/// How [`Tabled`] marks cells that changed compared to a baseline, see [`Tabled::with_highlight`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Highlight {
+    /// Prefix cells with 🔴 for regressions and 🟢 for improvements.
+    Emoji,
+    /// Wrap cells in `<span>` elements colored red for regressions and green for improvements.
+    ///
+    /// Note that some renderers, including GitHub, strip inline styles.
+    Html,
+}
+
+impl FromStr for Highlight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "emoji" => Ok(Highlight::Emoji),
+            "html" => Ok(Highlight::Html),
+            _ => Err(format!("invalid highlight {s:?}: expected emoji or html")),
+        }
+    }
+}
+
+impl Highlight {
+    fn apply(self, cell: String, improvement: bool) -> String {
+        match (self, improvement) {
+            (Highlight::Emoji, false) => format!("🔴 {cell}"),
+            (Highlight::Emoji, true) => format!("🟢 {cell}"),
+            (Highlight::Html, false) => format!("<span style=\"color:red\">{cell}</span>"),
+            (Highlight::Html, true) => format!("<span style=\"color:green\">{cell}</span>"),
+        }
+    }
+}
+
+/// How [`Tabled`] formats counter values, see [`Tabled::with_number_format`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// Scale values with SI prefixes like [`TabledFloat`](super::TabledFloat), e.g. `3.7 G`.
+    ///
+    /// The prefixes and precision can be changed using [`Tabled::with_float_format`].
+    #[default]
+    Si,
+    /// Three decimal places, e.g. `3700000000.000`.
+    Fixed,
+    /// The shortest representation that parses to the exact value, e.g. `3700000000`.
+    ///
+    /// Unlike the other formats, this shows [percentages](CounterMeta::with_percent) as fractions.
+    Raw,
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "si" => Ok(NumberFormat::Si),
+            "fixed" => Ok(NumberFormat::Fixed),
+            "raw" => Ok(NumberFormat::Raw),
+            _ => Err(format!(
+                "invalid number format {s:?}: expected si, fixed, or raw"
+            )),
+        }
+    }
+}
+
+impl NumberFormat {
+    fn format(self, x: f64, float_format: FloatFormat, meta: CounterMeta) -> String {
+        match self {
+            NumberFormat::Si if meta.percent() => float_format
+                .display_percent(x)
+                .to_string()
+                .trim_start()
+                .to_string(),
+            NumberFormat::Si => float_format.display(x).to_string().trim_start().to_string(),
+            NumberFormat::Fixed if meta.percent() => format!("{:3.3} %", x * 100.0),
+            NumberFormat::Fixed => format!("{x:3.3}"),
+            NumberFormat::Raw => x.to_string(),
+        }
+    }
+}
+
+/// The column [`Tabled`] sorts rows by, see [`Tabled::with_sort`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sort {
+    /// The name of a label or counter.
+    pub column: String,
+    pub descending: bool,
+}
+
+/// Parses a column name, optionally followed by `:asc` or `:desc`, e.g. `cycle:desc`.
+/// Any other suffix is part of the column name, e.g. `cycle:k:desc` sorts by `cycle:k`.
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, descending) = match s.rsplit_once(":") {
+            Some((column, "asc")) => (column, false),
+            Some((column, "desc")) => (column, true),
+            _ => (s, false),
+        };
+        Ok(Sort {
+            column: column.to_string(),
+            descending,
+        })
+    }
+}
+
+/// How [`Tabled`] summarizes each counter in a final row, see [`Tabled::with_summary_row`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SummaryRow {
+    Sum,
+    Mean,
+    /// The geometric mean, which is less dominated by large values than the mean.
+    GeoMean,
+}
+
+impl FromStr for SummaryRow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum" => Ok(SummaryRow::Sum),
+            "mean" => Ok(SummaryRow::Mean),
+            "geomean" => Ok(SummaryRow::GeoMean),
+            _ => Err(format!(
+                "invalid summary row {s:?}: expected sum, mean, or geomean"
+            )),
+        }
+    }
+}
+
+impl SummaryRow {
+    fn name(self) -> &'static str {
+        match self {
+            SummaryRow::Sum => "sum",
+            SummaryRow::Mean => "mean",
+            SummaryRow::GeoMean => "geomean",
+        }
+    }
+
+    /// Summarizes the values, also returning whether any were skipped.
+    ///
+    /// The geometric mean is undefined for values that are not positive, so these are skipped.
+    fn apply(self, values: impl Iterator<Item = f64>) -> (f64, bool) {
+        let (mut acc, mut n, mut skipped) = (0.0, 0.0, false);
+        for x in values {
+            acc += if self == SummaryRow::GeoMean {
+                if !(x > 0.0 && x.is_finite()) {
+                    skipped = true;
+                    continue;
+                }
+                x.ln()
+            } else {
+                x
+            };
+            n += 1.0;
+        }
+        let summary = match self {
+            SummaryRow::Sum => acc,
+            SummaryRow::Mean => acc / n,
+            SummaryRow::GeoMean => (acc / n).exp(),
+        };
+        (summary, skipped)
+    }
+}
+
+/// The rows [`Tabled`] compares other rows to, see [`Tabled::with_reference`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    /// The name of the label identifying reference rows.
+    pub label: String,
+    /// The value of the label in reference rows, e.g. `baseline`.
+    pub value: String,
+}
+
+/// Parses `label=value`, e.g. `impl=baseline`.
+impl FromStr for Reference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, value) = s
+            .split_once("=")
+            .ok_or_else(|| format!("invalid reference {s:?}: expected label=value"))?;
+        Ok(Reference {
+            label: label.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl Reference {
+    /// Returns the index of the reference row of each row.
+    ///
+    /// The reference row of a row has the reference value and otherwise the same labels.
+    fn rows(&self, label_meta: &[LabelMeta], rows: &[Row]) -> Vec<Option<usize>> {
+        let Some(i) = label_meta.iter().position(|x| x.name() == self.label) else {
+            eprintln!("invalid reference: no label {:?}", self.label);
+            return vec![None; rows.len()];
+        };
+        rows.iter()
+            .map(|row| {
+                rows.iter().position(|other| {
+                    other.labels[i] == self.value
+                        && (other.labels.iter().enumerate())
+                            .all(|(j, x)| j == i || *x == row.labels[j])
+                })
+            })
+            .collect()
+    }
+}
+
+/// A table row, possibly aggregated from multiple readings.
+struct Row {
+    labels: Vec<String>,
+    runs: usize,
+    /// Scaled value of each counter.
+    values: Vec<f64>,
+}
+
+impl Default for Tabled {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Tabled {
     pub fn new() -> Self {
         Tabled {
             readings: Vec::new(),
+            counter_names: Vec::new(),
+            columns: Vec::new(),
             markdown: true,
+            aggregate: None,
+            highlight: None,
+            key: RowKey::new(),
+            metadata: None,
+            sort: None,
+            summary_row: None,
+            reference: None,
+            number_format: NumberFormat::Si,
+            float_format: FloatFormat::new(),
+            output: Box::new(stdout()),
         }
     }
+
+    /// Combine all readings with identical labels into a single row.
+    ///
+    /// Rows are ordered by the first occurrence of their labels and show the number of readings in an additional `runs` column.
+    /// This keeps the table compact when recording many readings per configuration, e.g. one per iteration.
+    /// To aggregate rows for other formats, use [`Aggregate`] as a [`Transform`](crate::transform::Transform).
+    pub fn with_aggregate(mut self, aggregate: Option<Aggregate>) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    /// Mark cells that changed by more than `threshold` percent compared to a baseline.
+    ///
+    /// Rows are matched to the last baseline row with identical labels, see [`with_row_key`](Self::with_row_key), and counters are matched by name.
+    /// Whether a change is a regression or an improvement depends on the [direction](crate::counters::Direction) of the counter.
+    /// This helps spotting regressions in tables posted as pull request comments.
+    /// For a default instance, this is set from the environment variables `QPE_HIGHLIGHT`, `QPE_BASELINE`, and `QPE_HIGHLIGHT_THRESHOLD`.
+    pub fn with_highlight(mut self, highlight: Option<(Highlight, Report, f64)>) -> Self {
+        self.highlight = highlight;
+        self
+    }
+
+    /// Set the labels identifying a row when matching rows against the baseline, by default all labels are used.
+    pub fn with_row_key(mut self, key: RowKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Sort rows by a label or counter column, instead of the order they were recorded in.
+    ///
+    /// Label values that are numbers are compared as such and precede other values, which are compared as strings.
+    /// Rows with equal values keep their order.
+    /// For a default instance, this is set from the environment variable `QPE_SORT`, e.g. `cycle:desc`.
+    pub fn with_sort(mut self, sort: Option<Sort>) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Add a final row summarizing each counter across all rows.
+    ///
+    /// The row is set in bold and labeled with the kind of summary, e.g. `mean`, in the first label column, or in an extra column if there are no labels.
+    /// The geometric mean skips values that are not positive, marking the cell with `?`.
+    /// For a default instance, this is set from the environment variable `QPE_SUMMARY_ROW`.
+    pub fn with_summary_row(mut self, summary_row: Option<SummaryRow>) -> Self {
+        self.summary_row = summary_row;
+        self
+    }
+
+    /// Add a column after each counter with the ratio of the value to that of a reference row, e.g. `cycle/ref`.
+    ///
+    /// Reference rows are those where a label has a given value, e.g. `impl=baseline`.
+    /// Each row is compared to the reference row with otherwise identical labels, the cell is empty if there is none.
+    /// This gives the classic comparison of an implementation against others, e.g. over several datasets.
+    /// For a default instance, this is set from the environment variable `QPE_REFERENCE`.
+    pub fn with_reference(mut self, reference: Option<Reference>) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    /// Set how counter values are formatted, by default with SI prefixes.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_NUMBER_FORMAT`.
+    pub fn with_number_format(mut self, number_format: NumberFormat) -> Self {
+        self.number_format = number_format;
+        self
+    }
+
+    /// Set how values are formatted with [`NumberFormat::Si`].
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_FLOAT_FORMAT`.
+    pub fn with_float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    /// Write tables to `output` instead of stdout.
+    pub fn with_writer(mut self, output: impl Write + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Write metadata as a list before each table.
+    pub fn with_metadata(mut self, metadata: Option<Metadata>) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 
 impl Format for Tabled {
@@ -35,6 +382,19 @@ impl Format for Tabled {
         labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
         _label_meta: &'static [LabelMeta],
     ) -> Result<(), Box<dyn Error>> {
+        if self.readings.is_empty() {
+            let mut meta = Vec::new();
+            counters.meta(&mut |x| meta.push(x));
+            self.counter_names.clear();
+            let mut i = 0;
+            counters.names(&mut |name| {
+                self.counter_names.push(name.to_string());
+                if !meta[i].detail() {
+                    self.columns.push((i, name.to_string(), meta[i]));
+                }
+                i += 1;
+            });
+        }
         let mut label_vec = Vec::new();
         labels(&mut |l: &str| label_vec.push(l.to_string()));
         self.readings.push(PerfReadingExtra {
@@ -42,7 +402,7 @@ impl Format for Tabled {
             labels: label_vec,
             counters: {
                 let mut dst = Vec::new();
-                counters.read(&mut dst);
+                read_by_name(counters, &self.counter_names, &mut dst);
                 dst
             },
         });
@@ -52,40 +412,307 @@ impl Format for Tabled {
     fn dump_and_reset(
         &mut self,
         label_meta: &'static [LabelMeta],
-        counters: &mut dyn Counters,
+        _counters: &mut dyn Counters,
     ) -> Result<(), Box<dyn Error>> {
+        let mut rows: Vec<Row> = Vec::new();
+        let baseline_rows: HashMap<Vec<(String, String)>, &crate::Row> = match &self.highlight {
+            Some((_, baseline, _)) => (baseline.rows.iter())
+                .map(|row| (self.key.key(baseline.label_meta, &row.labels), row))
+                .collect(),
+            None => HashMap::new(),
+        };
+        for reading in &mut self.readings {
+            let values = reading
+                .counters
+                .iter()
+                .map(|x| x.scaled_value(reading.scale));
+            if self.aggregate.is_some()
+                && let Some(row) = rows.iter_mut().find(|x| x.labels == reading.labels)
+            {
+                row.runs += 1;
+                for (sum, value) in row.values.iter_mut().zip(values) {
+                    *sum += value;
+                }
+                continue;
+            }
+            rows.push(Row {
+                labels: mem::take(&mut reading.labels),
+                runs: 1,
+                values: values.collect(),
+            });
+        }
+        if self.aggregate == Some(Aggregate::Mean) {
+            for row in &mut rows {
+                for value in &mut row.values {
+                    *value /= row.runs as f64;
+                }
+            }
+        }
+        if let Some(sort) = &self.sort {
+            sort_rows(&mut rows, sort, label_meta, &self.columns);
+        }
         let mut table = tabled::builder::Builder::new();
         table.push_record(label_meta.iter().map(|x| x.name()));
-        for reading in &mut self.readings {
-            table.push_record(mem::take(&mut reading.labels));
-        }
-        let any_multiplexed = self
-            .readings
-            .iter()
-            .flat_map(|x| &x.counters)
-            .any(|x| x.multiplexed);
-        let mut name_i = 0;
-        counters.names(&mut |name| {
-            let readings = || {
-                self.readings
-                    .iter()
-                    .map(|x| x.counters[name_i].scaled_value(x.scale))
-            };
+        for row in &rows {
+            let labels = row.labels.iter().zip(label_meta);
+            table.push_record(labels.map(|(label, meta)| meta.fit(label).into_owned()));
+        }
+        let summary_row = self.summary_row.filter(|_| !rows.is_empty());
+        if let Some(summary_row) = summary_row {
+            let name = format!("**{}**", summary_row.name());
+            table.push_record((0..label_meta.len()).map(|i| match i {
+                0 => name.clone(),
+                _ => String::new(),
+            }));
+            // without label columns, add a column to mark the summary row
+            if label_meta.is_empty() {
+                let empty = iter::repeat_n(String::new(), rows.len() + 1);
+                table.push_column(empty.chain([name]));
+            }
+        }
+        if self.aggregate.is_some() {
+            let total_runs =
+                summary_row.map(|_| format!("**{}**", rows.iter().map(|x| x.runs).sum::<usize>()));
             table.push_column(
-                iter::once(name.to_string()).chain(readings().map(|x| format!("{x:3.3}"))),
+                iter::once("runs".to_string())
+                    .chain(rows.iter().map(|x| x.runs.to_string()))
+                    .chain(total_runs),
             );
-            name_i += 1;
-        });
-        let multiplex_warning = if any_multiplexed {
-            "⚠️ Some counters were multiplexed.\n"
-        } else {
-            "\n"
-        };
+        }
+        let counters = || self.readings.iter().flat_map(|x| &x.counters);
+        let any_multiplexed = counters().any(|x| x.multiplexed);
+        let any_invalid = counters().any(|x| !x.valid);
+        let mut notes = Vec::new();
+        let mut any_skipped = false;
+        let reference_rows = (self.reference.as_ref()).map(|x| x.rows(label_meta, &rows));
+        for (i, name, meta) in mem::take(&mut self.columns) {
+            let baseline_index = (self.highlight.as_ref())
+                .and_then(|(_, baseline, _)| baseline.counter_index(&name));
+            let cells = rows.iter().map(|row| {
+                let x = row.values[i];
+                let mut cell = self.number_format.format(x, self.float_format, meta);
+                if let Some(note) = meta.note()
+                    && x != 0.0
+                {
+                    if !notes.contains(&note) {
+                        notes.push(note);
+                    }
+                    cell.push('*');
+                }
+                if let (Some((highlight, _, threshold)), Some(j)) =
+                    (&self.highlight, baseline_index)
+                    && let Some(old) = baseline_rows.get(&self.key.key(label_meta, &row.labels))
+                    && let Some(improvement) = significant_change(meta, old.value(j), x, *threshold)
+                {
+                    cell = highlight.apply(cell, improvement);
+                }
+                cell
+            });
+            let mut cells: Vec<String> = cells.collect();
+            if let Some(summary_row) = summary_row {
+                let (x, skipped) = summary_row.apply(rows.iter().map(|row| row.values[i]));
+                any_skipped |= skipped;
+                let mark = if skipped { "?" } else { "" };
+                cells.push(format!(
+                    "**{}{mark}**",
+                    self.number_format.format(x, self.float_format, meta)
+                ));
+            }
+            let header = format!("{name}/ref");
+            table.push_column(iter::once(counter_header(&name, meta)).chain(cells));
+            if let Some(reference_rows) = &reference_rows {
+                let ratios: Vec<Option<f64>> = (rows.iter().zip(reference_rows))
+                    .map(|(row, reference)| Some(row.values[i] / rows[(*reference)?].values[i]))
+                    .collect();
+                let mut cells: Vec<String> = (ratios.iter())
+                    .map(|x| x.map_or(String::new(), |x| format!("{x:.2}×")))
+                    .collect();
+                if let Some(summary_row) = summary_row {
+                    let (x, skipped) = summary_row.apply(ratios.iter().flatten().copied());
+                    any_skipped |= skipped;
+                    let mark = if skipped { "?" } else { "" };
+                    cells.push(format!("**{x:.2}×{mark}**"));
+                }
+                table.push_column(iter::once(header).chain(cells));
+            }
+        }
+        let mut warnings = String::new();
+        if any_multiplexed {
+            warnings.push_str("⚠️ Some counters were multiplexed.\n");
+        }
+        if any_invalid {
+            warnings.push_str("⚠️ Some counters could not be read.\n");
+        }
+        if any_skipped {
+            warnings.push_str(
+                "⚠️ Values that are not positive were excluded from the geometric mean (?).\n",
+            );
+        }
+        if warnings.is_empty() {
+            warnings.push('\n');
+        }
         let mut table = table.build();
         if self.markdown {
             table.with(Style::markdown());
         }
-        println!("{multiplex_warning}{table}");
+        if let Some(metadata) = &self.metadata {
+            writeln!(self.output)?;
+            for (name, value) in metadata.entries() {
+                writeln!(self.output, "- **{name}**: {}", value.replace('\n', " "))?;
+            }
+        }
+        writeln!(self.output, "{warnings}{table}")?;
+        for note in notes {
+            writeln!(self.output, "\n\\* {note}")?;
+        }
+        self.output.flush()?;
+        self.readings.clear();
         Ok(())
     }
 }
+
+fn sort_rows(
+    rows: &mut [Row],
+    sort: &Sort,
+    label_meta: &[LabelMeta],
+    columns: &[(usize, String, CounterMeta)],
+) {
+    // Reversing the comparison instead of the sorted rows keeps equal rows in order.
+    let order = |x: Ordering| if sort.descending { x.reverse() } else { x };
+    if let Some(i) = label_meta.iter().position(|x| x.name() == sort.column) {
+        rows.sort_by(|a, b| {
+            let (a, b) = (&a.labels[i], &b.labels[i]);
+            order(match (a.parse::<f64>(), b.parse::<f64>()) {
+                (Ok(a), Ok(b)) => a.total_cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            })
+        });
+    } else if let Some((i, _, _)) = columns.iter().find(|x| x.1 == sort.column) {
+        rows.sort_by(|a, b| order(a.values[*i].total_cmp(&b.values[*i])));
+    } else {
+        eprintln!("cannot sort by {:?}: no such column", sort.column);
+    }
+}
+
+#[test]
+fn test_label_width() {
+    use crate::{QuickPerfEvent, counters::TimeBackend};
+    let path = std::env::temp_dir().join(format!("qpe-label-width-{}.md", std::process::id()));
+    let table = |labels: &[&str]| {
+        let tabled = Tabled::new().with_writer(std::fs::File::create(&path).unwrap());
+        let mut perf = QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), tabled);
+        for label in labels {
+            perf.run(|| ()).record(1, *label);
+        }
+        drop(perf);
+        std::fs::read_to_string(&path).unwrap()
+    };
+    assert!(table(&["a"]).contains("| label |"));
+    let long = "datasets/a-long-directory-name/input.csv";
+    assert!(table(&[long]).contains(&format!("| {long} |")));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_summary_row_output() {
+    use crate::{
+        QuickPerfEvent,
+        counters::{CounterReading, Replay},
+    };
+    let path = std::env::temp_dir().join(format!("qpe-summary-row-{}.md", std::process::id()));
+    let tabled = Tabled::new()
+        .with_writer(std::fs::File::create(&path).unwrap())
+        .with_summary_row(Some(SummaryRow::GeoMean));
+    let counters = Replay::new(
+        vec!["x".into()],
+        vec![CounterMeta::new()],
+        vec![CounterReading::new(0.0)],
+    );
+    let mut perf = QuickPerfEvent::<(), _, _>::new(counters, tabled);
+    perf.run(|| ()).record(1, ());
+    drop(perf);
+    let output = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let summary = output.lines().last().unwrap();
+    assert!(summary.contains("**geomean**"), "{output}");
+    assert!(summary.contains("?**"), "{output}");
+    assert!(output.contains("geometric mean"), "{output}");
+}
+
+#[test]
+fn test_sort_rows() {
+    let row = |label: &str, value| Row {
+        labels: vec![label.to_string()],
+        runs: 1,
+        values: vec![value],
+    };
+    let mut rows = vec![row("10", 1.0), row("9", 3.0), row("b", 2.0)];
+    let label_meta = [LabelMeta::new("size")];
+    let columns = [(0, "cycle".to_string(), CounterMeta::new())];
+    sort_rows(&mut rows, &"size".parse().unwrap(), &label_meta, &columns);
+    let labels = |rows: &[Row]| rows.iter().map(|x| x.labels[0].clone()).collect::<Vec<_>>();
+    assert_eq!(labels(&rows), ["9", "10", "b"]);
+    sort_rows(
+        &mut rows,
+        &"cycle:desc".parse().unwrap(),
+        &label_meta,
+        &columns,
+    );
+    assert_eq!(labels(&rows), ["9", "b", "10"]);
+    let mut rows = vec![row("a", 1.0), row("b", 2.0), row("c", 1.0)];
+    sort_rows(
+        &mut rows,
+        &"cycle:desc".parse().unwrap(),
+        &label_meta,
+        &columns,
+    );
+    assert_eq!(labels(&rows), ["b", "a", "c"]);
+}
+
+#[test]
+fn test_parse_sort() {
+    let sort = |column: &str, descending| Sort {
+        column: column.to_string(),
+        descending,
+    };
+    assert_eq!("cycle".parse(), Ok(sort("cycle", false)));
+    assert_eq!("cycle:desc".parse(), Ok(sort("cycle", true)));
+    assert_eq!("cycle:k".parse(), Ok(sort("cycle:k", false)));
+    assert_eq!("cycle:k:desc".parse(), Ok(sort("cycle:k", true)));
+}
+
+#[test]
+fn test_summary_row() {
+    let values = || [1.0, 4.0, 16.0].into_iter();
+    assert_eq!(SummaryRow::Sum.apply(values()), (21.0, false));
+    assert_eq!(SummaryRow::Mean.apply(values()), (7.0, false));
+    let (geomean, skipped) = SummaryRow::GeoMean.apply(values());
+    assert!((geomean - 4.0).abs() < 1e-9 && !skipped);
+    let invalid = values().chain([0.0, -1.0, f64::NAN, f64::INFINITY]);
+    let (geomean, skipped) = SummaryRow::GeoMean.apply(invalid);
+    assert!((geomean - 4.0).abs() < 1e-9 && skipped);
+}
+
+#[test]
+fn test_reference_rows() {
+    let row = |labels: [&str; 2]| Row {
+        labels: labels.map(str::to_string).to_vec(),
+        runs: 1,
+        values: Vec::new(),
+    };
+    let rows = [
+        row(["ours", "a"]),
+        row(["base", "a"]),
+        row(["base", "b"]),
+        row(["ours", "b"]),
+        row(["ours", "c"]),
+    ];
+    let label_meta = [LabelMeta::new("impl"), LabelMeta::new("data")];
+    let reference: Reference = "impl=base".parse().unwrap();
+    assert_eq!(
+        reference.rows(&label_meta, &rows),
+        [Some(1), Some(1), Some(2), Some(2), None]
+    );
+}
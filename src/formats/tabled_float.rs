@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 /// A wrapper type for rendering floating-point numbers in compact, readable form for tables.
 ///
@@ -9,41 +9,196 @@ use std::fmt;
 /// This is the format used for the `live` output format.
 ///
 /// # Formatting rules
-/// - Negative and non-finite values use an unspecified format with appropriate width
+/// - Non-finite values use an unspecified format with appropriate width
 /// - Values close to 1 are printed as fixed-point with three decimals (`100.000`, `0.010`)
 /// - Larger or smaller magnitudes are scaled with SI prefixes, leaving only one digit past the decimal point (`1.0 k`, `500.0 µ`)
 /// - Very large values are formatted using scientific notation (`5e42`)
 /// - Very small values are rounded down to 0 (`0`)
+/// - Negative values follow the same rules with a leading `-`.
+///   Where the sign does not fit the fixed width, one digit of precision is dropped (`-100.00`, `-500 k`).
 pub struct TabledFloat(pub f64);
 
 impl fmt::Display for TabledFloat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let scale = self.0.log10().floor() as isize;
-        let si_scale = scale.div_euclid(3);
-        if !self.0.is_finite() || self.0.is_sign_negative() {
-            write!(f, "{:7.0e}", self.0)
-        } else if scale >= -2 && scale <= 2 {
-            write!(f, "{:7.3}", self.0)
-        } else {
-            if si_scale > 0 {
-                if let Some(suffix) = ["k", "M", "G", "T"].get(si_scale as usize - 1) {
-                    let scaled = self.0 / (1000f64).powi(si_scale as i32);
-                    write!(f, "{scaled:5.1} {suffix}")
-                } else {
-                    write!(f, "{:7e}", self.0)
+        FloatFormat::new().display(self.0).fmt(f)
+    }
+}
+
+/// The configurable formatter behind [`TabledFloat`].
+///
+/// The defaults match [`TabledFloat`].
+/// Increasing the [precision](Self::with_precision) shows more digits of values scaled by a prefix, and three digits more of others.
+/// Values are padded to the [width](Self::width), which grows as needed to fit the precision.
+/// With [binary prefixes](Self::with_binary), values of at least 1024 are scaled by powers of 1024 (`Ki`, `Mi`, `Gi`, `Ti`), which suits counts of bytes.
+///
+/// For the `live` and `md` output formats, this can be set using the environment variable `QPE_FLOAT_FORMAT`, a comma separated list of `width=N`, `precision=N`, and `binary`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloatFormat {
+    width: usize,
+    precision: usize,
+    binary: bool,
+}
+
+impl Default for FloatFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for FloatFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut format = FloatFormat::new();
+        for option in s.split(",").filter(|x| !x.is_empty()) {
+            let number = |x: &str| {
+                x.parse()
+                    .map_err(|_| format!("invalid float format option {option:?}"))
+            };
+            match option.split_once("=") {
+                Some(("width", x)) => format.width = number(x)?,
+                Some(("precision", x)) => format.precision = number(x)?,
+                None if option == "binary" => format.binary = true,
+                _ => {
+                    return Err(format!(
+                        "invalid float format option {option:?}: expected width=N, precision=N, or binary"
+                    ));
                 }
+            }
+        }
+        Ok(format)
+    }
+}
+
+impl FloatFormat {
+    pub const fn new() -> Self {
+        FloatFormat {
+            width: 7,
+            precision: 1,
+            binary: false,
+        }
+    }
+
+    /// Set the minimum width of formatted values.
+    pub const fn with_width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Set the number of decimal places of values scaled by a prefix, the default is 1.
+    pub const fn with_precision(mut self, precision: usize) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Use binary prefixes instead of SI prefixes for large values.
+    pub const fn with_binary(mut self, binary: bool) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    /// The width of formatted values.
+    pub fn width(&self) -> usize {
+        let prefixed = self.precision + if self.binary { 8 } else { 6 };
+        self.width.max(prefixed)
+    }
+
+    /// Returns a value formatting `x`.
+    pub fn display(self, x: f64) -> impl fmt::Display {
+        struct Display(FloatFormat, f64);
+        impl fmt::Display for Display {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.write(f, self.1)
+            }
+        }
+        Display(self, x)
+    }
+
+    /// Returns a value formatting the fraction `x` as a percentage, e.g. `  1.3 %` for `0.013`.
+    pub fn display_percent(self, x: f64) -> impl fmt::Display {
+        struct Display(FloatFormat, f64);
+        impl fmt::Display for Display {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let x = self.1 * 100.0;
+                let width = self.0.width() - 2;
+                let precision = fit_precision(x, self.0.precision, width);
+                write!(f, "{x:width$.precision$} %")
+            }
+        }
+        Display(self, x)
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, x: f64) -> fmt::Result {
+        let width = self.width();
+        if !x.is_finite() {
+            return write!(f, "{x:width$.0e}");
+        }
+        let abs = x.abs();
+        let scale = abs.log10().floor() as isize;
+        // the power of 1000 or 1024 that values are divided by, 0 for fixed-point values
+        let mut power = if self.binary && abs >= 1024.0 {
+            (abs.log2() / 10.0).floor() as isize
+        } else if (-2..=2).contains(&scale) || (self.binary && scale == 3) {
+            0
+        } else {
+            scale.div_euclid(3)
+        };
+        // rounding may carry over to the next power, e.g. `999.96 k` to `1.0 M`, which is then used instead
+        loop {
+            let base: f64 = if self.binary && power > 0 {
+                1024.0
             } else {
-                if let Some(suffix) = ["m", "µ", "n", "p"].get(-si_scale as usize - 1) {
-                    let scaled = self.0 / (1000f64).powi(si_scale as i32);
-                    write!(f, "{scaled:5.1} {suffix}")
-                } else {
-                    write!(f, "{:7}", 0)
-                }
+                1000.0
+            };
+            let large = if self.binary {
+                ["Ki", "Mi", "Gi", "Ti"]
+            } else {
+                ["k", "M", "G", "T"]
+            };
+            let suffix = match power {
+                0 => "",
+                1.. => match large.get(power as usize - 1) {
+                    Some(suffix) => suffix,
+                    None => return write!(f, "{x:width$e}"),
+                },
+                _ => match ["m", "µ", "n", "p"].get(-power as usize - 1) {
+                    Some(suffix) => suffix,
+                    None => return write!(f, "{:width$}", 0),
+                },
+            };
+            let scaled = x / base.powi(power as i32);
+            let (width, precision) = match suffix {
+                "" => (width, self.precision + 2),
+                _ => (width - 1 - suffix.chars().count(), self.precision),
+            };
+            let precision = fit_precision(scaled, precision, width);
+            let rounded = format!("{scaled:.precision$}").parse::<f64>().unwrap();
+            let limit = if power == 0 && self.binary {
+                1024.0
+            } else {
+                base
+            };
+            if rounded.abs() >= limit {
+                power += 1;
+                continue;
             }
+            return match suffix {
+                "" => write!(f, "{scaled:width$.precision$}"),
+                _ => write!(f, "{scaled:width$.precision$} {suffix}"),
+            };
         }
     }
 }
 
+/// Reduces `precision` so that `x` fits into `width` characters, as far as possible.
+fn fit_precision(x: f64, precision: usize, width: usize) -> usize {
+    // rounding to fewer decimals may add an integer digit, e.g. `999.96` to `1000.0`
+    (0..=precision)
+        .rev()
+        .find(|precision| format!("{x:.precision$}").len() <= width)
+        .unwrap_or(0)
+}
+
 #[test]
 fn test_fixed_float() {
     let cases = [
@@ -70,6 +225,21 @@ fn test_fixed_float() {
         (1e+4, " 10.0 k"),
         (5e+42, "   5e42"),
         (1e+105, "  1e105"),
+        (-1e-20, "      0"),
+        (-1e-6, " -1.0 µ"),
+        (-5e-4, " -500 µ"),
+        (-1e-2, " -0.010"),
+        (-5e+0, " -5.000"),
+        (-5e+1, "-50.000"),
+        (-5e+2, "-500.00"),
+        (-1e+3, " -1.0 k"),
+        (-999.996, " -1.0 k"),
+        (999.96e3, "  1.0 M"),
+        (999.96e-6, "  1.0 m"),
+        (0.99996, "  1.000"),
+        (-1e+4, "-10.0 k"),
+        (-5e+5, " -500 k"),
+        (-5e+42, "  -5e42"),
     ];
     for x in cases {
         assert_eq!(TabledFloat(x.0).to_string(), x.1);
@@ -96,3 +266,22 @@ fn test_fixed_float_special() {
         );
     }
 }
+
+#[test]
+fn test_float_format() {
+    let format = FloatFormat::new().with_precision(2).with_binary(true);
+    assert_eq!(format.width(), 10);
+    let cases = [
+        (1e-6, "    1.00 µ"),
+        (1.5, "    1.5000"),
+        (1000.0, " 1000.0000"),
+        (4096.0, "   4.00 Ki"),
+        (3.0 * (1u64 << 30) as f64, "   3.00 Gi"),
+        (-1e-3, "   -1.00 m"),
+    ];
+    for (x, expected) in cases {
+        assert_eq!(format.display(x).to_string(), expected);
+    }
+    let format: FloatFormat = "width=9,precision=2".parse().unwrap();
+    assert_eq!(format.display(2.5e9).to_string(), "   2.50 G");
+}
@@ -0,0 +1,55 @@
+//! A lazily constructed instance per thread, for measurements deep inside an application.
+//!
+//! Passing a `&mut QuickPerfEvent` to every code path of interest is impractical in large code bases.
+//! Instead, the [`qpe!`](crate::qpe) macro and [`with`] use an instance created by [`from_env`](crate::from_env) on first use.
+//! ```
+//! let sum = quick_perf_event::qpe!("sum", 1000, {
+//!     (0..1000).map(std::hint::black_box).sum::<u64>()
+//! });
+//! quick_perf_event::global::finish().unwrap();
+//! ```
+//! Each thread uses its own instance.
+//! Since the instance of the main thread is not dropped when the program exits, formats that write their output at the end, like markdown, require calling [`finish`].
+
+use crate::{FinishError, QuickPerfEvent};
+use std::cell::RefCell;
+
+thread_local! {
+    static GLOBAL: RefCell<Option<QuickPerfEvent<str>>> = const { RefCell::new(None) };
+}
+
+/// Calls `f` with the instance of the current thread, creating it if necessary.
+///
+/// # Panics
+/// Panics if called from within `f`, e.g. by nesting [`qpe!`](crate::qpe) invocations.
+pub fn with<R>(f: impl FnOnce(&mut QuickPerfEvent<str>) -> R) -> R {
+    GLOBAL.with(|global| {
+        let mut global = global
+            .try_borrow_mut()
+            .expect("global QuickPerfEvent measurements cannot be nested");
+        f(global.get_or_insert_with(crate::from_env))
+    })
+}
+
+/// Finishes the report of the instance of the current thread, see [`QuickPerfEvent::finish`].
+///
+/// A new instance is created if it is used again afterwards.
+pub fn finish() -> Result<(), FinishError> {
+    let global = GLOBAL.with(|global| global.borrow_mut().take());
+    global.map_or(Ok(()), QuickPerfEvent::finish)
+}
+
+/// Measures a block using the [`global`](crate::global) instance of the current thread and records it with a label and scale.
+///
+/// Evaluates to the value of the block.
+/// The block is executed inside a closure, so `return` and `?` refer to the closure.
+/// ```
+/// # fn build_index() {}
+/// let index = quick_perf_event::qpe!("build", 1, { build_index() });
+/// ```
+#[macro_export]
+macro_rules! qpe {
+    ($labels:expr, $scale:expr, $body:block) => {
+        $crate::global::with(|perf| perf.run(|| $body).record($scale, $labels))
+    };
+}
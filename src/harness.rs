@@ -0,0 +1,108 @@
+//! Running benchmarks with `cargo bench`.
+//!
+//! Benchmarks are functions taking a [`QuickPerfEvent`] and recording any number of readings.
+//! The [`main!`](crate::main) macro registers them and generates a `main` function running them under a shared [`QuickPerfEvent`] created by [`from_env`](crate::from_env).
+//! To use it, put your benchmarks in a file under `benches/` and disable the default harness in `Cargo.toml`:
+//! ```toml
+//! [[bench]]
+//! name = "my_bench"
+//! harness = false
+//! ```
+//! ```no_run
+//! use quick_perf_event::QuickPerfEvent;
+//!
+//! fn sum(perf: &mut QuickPerfEvent<str>) {
+//!     perf.run(|| (0..1_000_000).map(std::hint::black_box).sum::<u64>())
+//!         .record(1_000_000, "sum");
+//! }
+//!
+//! quick_perf_event::main!(sum);
+//! ```
+//! Benchmarks can then be run using `cargo bench`.
+//! Arguments after `--` select benchmarks whose name contains any of them, e.g. `cargo bench -- sum`.
+//! Pass `--exact` to require an exact match instead, or `--list` to list benchmarks without running them.
+//! Other flags passed by cargo are ignored.
+
+use crate::QuickPerfEvent;
+
+/// A benchmark function registered with [`main!`](crate::main).
+pub type Benchmark = fn(&mut QuickPerfEvent<str>);
+
+/// Selects and runs benchmarks according to command line arguments.
+pub struct Harness {
+    filters: Vec<String>,
+    exact: bool,
+    list: bool,
+}
+
+impl Harness {
+    /// Creates a harness from the arguments of the current process.
+    pub fn from_args() -> Self {
+        Self::from_arg_list(std::env::args().skip(1))
+    }
+
+    fn from_arg_list(args: impl IntoIterator<Item = String>) -> Self {
+        let mut harness = Harness {
+            filters: Vec::new(),
+            exact: false,
+            list: false,
+        };
+        for arg in args {
+            match arg.as_str() {
+                "--exact" => harness.exact = true,
+                "--list" => harness.list = true,
+                _ if arg.starts_with('-') => {}
+                _ => harness.filters.push(arg),
+            }
+        }
+        harness
+    }
+
+    fn selected(&self, name: &str) -> bool {
+        self.filters.is_empty()
+            || self.filters.iter().any(|x| {
+                if self.exact {
+                    name == x
+                } else {
+                    name.contains(x.as_str())
+                }
+            })
+    }
+
+    /// Runs all selected benchmarks in order under a shared [`QuickPerfEvent`] configured from environment variables.
+    pub fn run(&self, benchmarks: &[(&str, Benchmark)]) {
+        let selected = benchmarks.iter().filter(|x| self.selected(x.0));
+        if self.list {
+            for (name, _) in selected {
+                println!("{name}: benchmark");
+            }
+            return;
+        }
+        let mut perf = crate::from_env();
+        for (_, benchmark) in selected {
+            benchmark(&mut perf);
+        }
+    }
+}
+
+/// Generates a `main` function running the listed benchmark functions, see [`harness`](crate::harness).
+#[macro_export]
+macro_rules! main {
+    ($($benchmark:path),+ $(,)?) => {
+        fn main() {
+            $crate::harness::Harness::from_args().run(&[$((
+                stringify!($benchmark),
+                $benchmark as $crate::harness::Benchmark,
+            )),+]);
+        }
+    };
+}
+
+#[test]
+fn test_selected() {
+    let harness = |args: &[&str]| Harness::from_arg_list(args.iter().map(|x| x.to_string()));
+    assert!(harness(&["--bench"]).selected("sum"));
+    assert!(harness(&["--bench", "su"]).selected("sum"));
+    assert!(!harness(&["--bench", "su", "--exact"]).selected("sum"));
+    assert!(harness(&["min", "sum"]).selected("sum"));
+}
@@ -0,0 +1,114 @@
+/// Bits of precision below the leading one bit of a value.
+const SUB_BITS: u32 = 5;
+const SUB: u64 = 1 << SUB_BITS;
+
+/// Counts of non-negative integer values in logarithmic buckets.
+///
+/// Values below 64 are counted exactly.
+/// Larger values are grouped into 32 buckets per power of two, giving a relative error below 3%.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Histogram {
+    counts: Vec<u64>,
+    len: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+fn bucket(value: u64) -> usize {
+    if value < 2 * SUB {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let mantissa = value >> (exponent - SUB_BITS);
+    (2 * SUB + (exponent - SUB_BITS - 1) as u64 * SUB + mantissa - SUB) as usize
+}
+
+/// Returns the smallest value and the number of values in a bucket.
+fn bucket_range(index: usize) -> (u64, u64) {
+    let index = index as u64;
+    if index < 2 * SUB {
+        return (index, 1);
+    }
+    let shift = (index - 2 * SUB) / SUB + 1;
+    let mantissa = (index - 2 * SUB) % SUB + SUB;
+    (mantissa << shift, 1 << shift)
+}
+
+impl Histogram {
+    #[inline]
+    pub fn record(&mut self, value: u64) {
+        let i = bucket(value);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        if self.len == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.len += 1;
+        self.sum += value as u128;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.len as f64
+    }
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns the approximate value below which `p` percent of values fall.
+    ///
+    /// Values are reported as the middle of their bucket, clamped to the observed range.
+    /// The 100th percentile is the exact maximum.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.len == 0 {
+            return f64::NAN;
+        }
+        let rank = ((p / 100.0).clamp(0.0, 1.0) * (self.len - 1) as f64).round() as u64;
+        if rank == self.len - 1 {
+            return self.max as f64;
+        }
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                let (low, width) = bucket_range(i);
+                let mid = low as f64 + (width - 1) as f64 / 2.0;
+                return mid.clamp(self.min as f64, self.max as f64);
+            }
+        }
+        self.max as f64
+    }
+}
+
+#[test]
+fn test_histogram() {
+    for value in [0, 63, 64, 65, 1000, 123456789, u64::MAX] {
+        let (low, width) = bucket_range(bucket(value));
+        assert!(low <= value && value - low < width, "{value}");
+    }
+    let mut histogram = Histogram::default();
+    for value in 1..=100 {
+        histogram.record(value * 10);
+    }
+    assert_eq!(histogram.len(), 100);
+    assert_eq!(histogram.mean(), 505.0);
+    assert_eq!(histogram.percentile(0.0), 10.0);
+    assert_eq!(histogram.percentile(100.0), 1000.0);
+    let median = histogram.percentile(50.0);
+    assert!((median - 505.0).abs() / 505.0 < 0.03, "{median}");
+}
@@ -1,8 +1,15 @@
+use std::{borrow::Cow, fmt::Display, sync::OnceLock};
+
 /// Declares a struct type implementing the [`Labels`] trait.
 ///
 /// This macro simplifies creating label structs for use with [`QuickPerfEvent`](crate::QuickPerfEvent).
 /// It generates both the struct definition and its corresponding [`Labels`]
-/// implementation. The fields must implement [`AsRef<str>`](std::convert::AsRef).
+/// implementation. The fields may be of any type implementing [`Display`](std::fmt::Display),
+/// such as `String`, `&str`, `usize`, or `f64`.
+/// Wrap numbers in [`TabledFloat`](crate::formats::TabledFloat) to format them compactly, e.g. `4.1 k`.
+///
+/// Fields may be preceded by `#[width = N]` and `#[overflow = Variant]`, in this order,
+/// to set the [width](LabelMeta::with_width) and [overflow](LabelMeta::with_overflow) of the column.
 ///
 /// ```
 #[doc = include_str!("../examples/struct_labels.rs")]
@@ -10,7 +17,11 @@
 #[macro_export]
 macro_rules! struct_labels {
     ($vis:vis struct $Name:ident{
-        $($fv:vis $f:ident:$F:ty,)* $(,)?
+        $(
+            $(#[width = $width:expr])?
+            $(#[overflow = $overflow:ident])?
+            $fv:vis $f:ident:$F:ty,
+        )* $(,)?
     }) => {
         $vis struct $Name{
             $($fv $f:$F,)*
@@ -19,13 +30,17 @@ macro_rules! struct_labels {
         impl $crate::Labels for $Name{
             fn meta()->&'static [$crate::LabelMeta]{
                 &const{[
-                    $($crate::LabelMeta::new(stringify!($f)),)*
+                    $(
+                        $crate::LabelMeta::new(stringify!($f))
+                            $(.with_width($width))?
+                            $(.with_overflow($crate::Overflow::$overflow))?,
+                    )*
                 ]}
             }
 
 
             fn values(&self,f:&mut dyn FnMut(&str)){
-                $(f(std::convert::AsRef::as_ref(&self.$f));)*
+                $(f(&std::string::ToString::to_string(&self.$f));)*
             }
         }
     };
@@ -33,21 +48,37 @@ macro_rules! struct_labels {
 
 /// A trait for sets of labels attached to a performance measurement.
 ///
-/// Implementors describe both the schema (via [`names`](Self::names))
+/// Implementors describe both the schema (via [`meta`](Self::meta))
 /// and values (via [`values`](Self::values)) of a label set.
 /// You can define a label struct conveniently using the
 /// [`struct_labels!`](crate::struct_labels) macro.
 pub trait Labels {
     /// Returns the static list of label names in order.
     fn meta() -> &'static [LabelMeta];
-    /// Calls `f` for each label value, in the same order as [`names`](Self::names).
+    /// Calls `f` for each label value, in the same order as [`meta`](Self::meta).
     fn values(&self, f: &mut dyn FnMut(&str));
 }
 
+/// How label values exceeding the [width](LabelMeta::width) of their column are displayed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wrap values over multiple lines in the live table, formats that buffer all records show them in full.
+    #[default]
+    Wrap,
+    /// Cut off the end of values, marking it with an ellipsis.
+    TruncateEnd,
+    /// Cut off the start of values, marking it with an ellipsis.
+    ///
+    /// This keeps the file name of long paths visible.
+    TruncateStart,
+}
+
 /// Metadata about a label
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct LabelMeta {
     name: &'static str,
     width: usize,
+    overflow: Overflow,
 }
 
 impl LabelMeta {
@@ -55,18 +86,57 @@ impl LabelMeta {
         self.name
     }
 
+    /// The width of the label column.
+    ///
+    /// The live table uses exactly this width, handling longer values according to [`overflow`](Self::overflow).
+    /// Formats that buffer all records, like markdown, size the column to the longest value instead,
+    /// only using the width to truncate values if the overflow is not [`Wrap`](Overflow::Wrap).
     pub fn width(&self) -> usize {
         self.width
     }
 
+    /// How values exceeding the width of the column are displayed.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
     pub const fn new(name: &'static str) -> Self {
-        LabelMeta { name, width: 9 }
+        LabelMeta {
+            name,
+            width: 9,
+            overflow: Overflow::Wrap,
+        }
     }
 
     pub const fn with_width(mut self, width: usize) -> Self {
         self.width = width;
         self
     }
+
+    pub const fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Truncates a value to the width of the column if required by [`overflow`](Self::overflow).
+    #[cfg_attr(not(any(feature = "live", feature = "markdown")), allow(dead_code))]
+    pub(crate) fn fit<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let len = value.chars().count();
+        if len <= self.width || self.width == 0 {
+            return Cow::Borrowed(value);
+        }
+        let keep = self.width - 1;
+        match self.overflow {
+            Overflow::Wrap => Cow::Borrowed(value),
+            Overflow::TruncateEnd => Cow::Owned(value.chars().take(keep).chain(['…']).collect()),
+            Overflow::TruncateStart => Cow::Owned(
+                ['…']
+                    .into_iter()
+                    .chain(value.chars().skip(len - keep))
+                    .collect(),
+            ),
+        }
+    }
 }
 
 /// No labels.
@@ -81,13 +151,139 @@ impl Labels for () {
 /// Treats the string as a single label with name `"label"`.
 impl Labels for str {
     fn meta() -> &'static [LabelMeta] {
-        &[LabelMeta {
-            name: "label",
-            width: 30,
-        }]
+        &const { [LabelMeta::new("label").with_width(30)] }
     }
 
     fn values(&self, f: &mut dyn FnMut(&str)) {
         f(self)
     }
 }
+
+static DYN_META: OnceLock<&'static [LabelMeta]> = OnceLock::new();
+
+/// Labels whose names are chosen at runtime, e.g. from the parameters of a benchmark driver.
+///
+/// As [`Labels::meta`] returns the same names for all values of a type, the names are shared by the whole process.
+/// Consequently, there is one schema per process: all instances using `DynLabels` report the same label columns, and the names cannot be changed once set.
+/// They must be set using [`set_names`](Self::set_names) or [`set_meta`](Self::set_meta) before the first measurement is recorded,
+/// recording labels without names panics.
+/// Each value must have one entry per name, in the same order.
+/// ```
+/// use quick_perf_event::{DynLabels, from_env};
+///
+/// DynLabels::set_names(["dataset", "threads"]);
+/// let mut perf = from_env::<DynLabels>();
+/// perf.run(|| {
+///     // benchmarked code
+/// })
+/// .record(1, DynLabels::new(["uniform", "4"]));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DynLabels {
+    values: Vec<String>,
+}
+
+impl DynLabels {
+    pub fn new(values: impl IntoIterator<Item = impl Display>) -> Self {
+        DynLabels {
+            values: values.into_iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    /// Set the label names, using the default [`LabelMeta`] for each.
+    ///
+    /// The names are leaked to obtain a `'static` lifetime.
+    ///
+    /// # Panics
+    /// Panics if different names have already been set.
+    pub fn set_names(names: impl IntoIterator<Item = impl Display>) {
+        Self::set_meta(
+            names
+                .into_iter()
+                .map(|x| LabelMeta::new(x.to_string().leak()))
+                .collect(),
+        );
+    }
+
+    /// Set the label metadata.
+    ///
+    /// # Panics
+    /// Panics if different metadata has already been set, including different widths or overflow.
+    pub fn set_meta(meta: Vec<LabelMeta>) {
+        let current = DYN_META.get_or_init(|| meta.clone().leak());
+        assert!(
+            *current == meta,
+            "DynLabels metadata is already set to {current:?}"
+        );
+    }
+}
+
+impl Labels for DynLabels {
+    /// Returns the metadata set using [`set_meta`](Self::set_meta), or none if it has not been set.
+    fn meta() -> &'static [LabelMeta] {
+        DYN_META.get().copied().unwrap_or(&[])
+    }
+
+    fn values(&self, f: &mut dyn FnMut(&str)) {
+        for value in &self.values {
+            f(value);
+        }
+    }
+}
+
+#[test]
+fn test_fit() {
+    let meta = LabelMeta::new("path").with_width(6);
+    assert_eq!(meta.fit("src/lib.rs"), "src/lib.rs");
+    let meta = meta.with_overflow(Overflow::TruncateEnd);
+    assert_eq!(meta.fit("src/lib.rs"), "src/l…");
+    assert_eq!(meta.fit("lib.rs"), "lib.rs");
+    let meta = meta.with_overflow(Overflow::TruncateStart);
+    assert_eq!(meta.fit("src/lib.rs"), "…ib.rs");
+}
+
+#[test]
+fn test_struct_labels() {
+    crate::struct_labels! {
+        struct Numeric {
+            #[width = 20]
+            #[overflow = TruncateEnd]
+            name: &'static str,
+            threads: usize,
+            ratio: f64,
+            size: crate::formats::TabledFloat,
+        }
+    }
+    let labels = Numeric {
+        name: "sort",
+        threads: 4,
+        ratio: 0.5,
+        size: crate::formats::TabledFloat(4096.0),
+    };
+    let mut values = Vec::new();
+    labels.values(&mut |x| values.push(x.to_string()));
+    assert_eq!(values, ["sort", "4", "0.5", "  4.1 k"]);
+    assert_eq!(Numeric::meta()[0].width(), 20);
+    assert_eq!(Numeric::meta()[0].overflow(), Overflow::TruncateEnd);
+    assert_eq!(Numeric::meta()[1].width(), 9);
+}
+
+#[test]
+fn test_dyn_labels() {
+    use crate::{QuickPerfEvent, counters::TimeBackend, report::Report};
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+    DynLabels::set_names(["dataset", "threads"]);
+    DynLabels::set_names(["dataset", "threads"]);
+    let wider = vec![
+        LabelMeta::new("dataset").with_width(20),
+        LabelMeta::new("threads"),
+    ];
+    assert!(catch_unwind(|| DynLabels::set_meta(wider)).is_err());
+    let mut perf = QuickPerfEvent::<DynLabels, _, _>::new(TimeBackend::new(), Report::default());
+    perf.run(|| ()).record(1, DynLabels::new(["uniform", "4"]));
+    let missing = catch_unwind(AssertUnwindSafe(|| {
+        perf.run(|| ()).record(1, DynLabels::new(["uniform"]));
+    }));
+    assert!(missing.is_err());
+    assert_eq!(perf.format_mut().rows.len(), 1);
+}
@@ -6,10 +6,16 @@
 //!   debugging. Each result is printed as soon as it is available, with compact,
 //!   fixed-width, line-wrapped cells to fit many columns in narrow terminals.
 //!   If the requested columns still do not fit, table rows are line wrapped as well.
+//!   If stdout is not a terminal, e.g. when piped to a file, CSV is used instead unless
+//!   `QPE_FORMAT=live` is set explicitly.
 //! - **`QPE_FORMAT=md`** - Generates a **Markdown table** after all runs have completed,
 //!   choosing column widths automatically for clean, publication-ready output.
 //! - **`QPE_FORMAT=csv`** - Streams results as **CSV** records to stdout, suitable for
 //!   further processing.
+//! - **`QPE_FORMAT=all`** - Shows the default output and additionally writes the session as CSV,
+//!   JSON, Markdown, and HTML files to the directory `QPE_EXPORT_DIR` (default `qpe-export`).
+//!
+//! Applications may add their own formats using [`formats::register`].
 //!
 //! # Example
 //! This benchmark measures computing the sum of an iterator.
@@ -34,19 +40,20 @@
 //! You can change this value until the next reboot using `sudo sysctl -w kernel.perf_event_paranoid=0` or permanently by adding `kernel.perf_event_paranoid = 0` to `/etc/sysctl.conf`.
 //! Lower values mean more permissive handling.
 //! See [`man 2 perf_event_open`](https://www.man7.org/linux/man-pages/man2/perf_event_open.2.html) for what the different restriction levels mean.
+//! Perf counters are only available on Linux. On macOS, `t-clock`, `pg-fault`, and `ctx-sw` are recorded using `getrusage` instead. On other platforms, only the remaining backends are used. A notice lists the unsupported counters, see [`Degradation`](counters::Degradation).
 //!
 //! # Usage
 //! To start benchmarking, you first need a [`QuickPerfEvent`] object.
 //! [`QuickPerfEvent`] manages both recording and reporting of benchmarks.
-//! You may configure the set of performance counters using either the environment variable `QPE_EVENTS` or [`with_counters`](QuickPerfEvent::with_counters).
+//! You may configure the set of performance counters using either the environment variable `QPE_EVENTS` or [`QuickPerfEvent::new`].
 //! For basic usage, you should prefer `QPE_EVENTS`.
 //! For example, to count CPU cycles and branch misses, set it to `cycles,br-miss`.
-//! For an up-to-date list of supported values see the implementation of [`with_counter_names`](PerfCounters::with_counter_names).
+//! For an up-to-date list of supported values see the implementation of [`with_counter_names`](counters::PerfBackend::with_counter_names).
 //! If your program is multi-threaded, construct [`QuickPerfEvent`] **before spawning threads** to ensure counts include other threads.
 //!
 //! Now that you have a [`QuickPerfEvent`] object, you may start taking measurements using its [`run`](QuickPerfEvent::run) method.
-//! After each run, you **must** call [`record`](PerfReading::record) on the returned value to log the measurement.
-//! The [`record`](PerfReading::record) method takes two parameters:
+//! After each run, you **must** call [`record`](Reading::record) on the returned value to log the measurement.
+//! The [`record`](Reading::record) method takes two parameters:
 //!
 //! - **`scale`** - a normalization factor (e.g. number of iterations).  
 //!   All performance counters are divided by this value, producing results
@@ -60,32 +67,159 @@
 //!   This can be:
 //!   - the unit type `()` (no labels),
 //!   - a string `&str` (single label),
+//!   - [`DynLabels`] (label names chosen at runtime),
 //!   - or a user-defined struct implementing [`Labels`].
 //!
+//! Suites with families of benchmarks can label records with a group using [`QuickPerfEvent::group`].
+//!
+//! To instrument code paths deep inside an application without passing a [`QuickPerfEvent`] around, use the [`qpe!`] macro, see [`global`].
+//! To keep instrumentation in production builds, an [`embedded`] instance accumulates readings into bounded aggregates instead of writing output.
+//! To measure from many threads concurrently, with per-thread counters and a single output, use a [`SharedQuickPerfEvent`](shared::SharedQuickPerfEvent).
+//! To measure regions of only a few nanoseconds inside a loop, use the [`measure_short!`] macro, see [`short`].
+//! To run benchmarks under `benches/` using `cargo bench` instead of writing your own `main` function, see [`harness`].
+//! To process results programmatically instead of printing them, collect them in a [`Report`].
+//! Rows can be filtered, extended with computed columns, and aggregated before formatting using [`transform`] pipelines.
+//!
+//! # Features
+//! The following features are enabled by default:
+//! - **`live`** - the live table output format.
+//! - **`markdown`** - the markdown table output format.
+//! - **`csv`** - the CSV output format and reading baselines for [`RegressionGate`].
+//! - **`perf`** - hardware and software counters using the `perf-event2` crate, see [`PerfBackend`](counters::PerfBackend). Without it, only the `time` counter is recorded by default.
+//!
+//! Disabling them gives a minimal build for embedding, where counters are only reported by custom [`Format`] implementations.
+//!
+//! Optional features:
+//! - **`symbolize`** - resolve source files and line numbers from debug info when reporting code locations, e.g. in [`Attribution`](Attribution) reports, and demangle Rust function names. Without it, only mangled function names are reported.
+//! - **`criterion`** - use any counter as a criterion measurement, see `counters::CriterionMeasurement`.
+//! - **`nvml`** - GPU utilization and energy counters using the NVIDIA Management Library, see `counters::NvmlBackend`. Linux only.
+//!
 //! # Environment Variables
 //! Quick Perf Event can be configured using various environment variables.
+//! The `QPE_` prefix can be replaced using [`QuickPerfEvent::with_env_prefix`].
+//! To configure an instance independently of environment variables, use [`QuickPerfEvent::from_config`].
 //! - **`QPE_FORMAT`** - set the output format, see above.
-//! - **`QPE_EVENTS`** - set the counters recorded by a default [PerfCounters] instance.
+//! - **`QPE_EVENTS`** - set the counters recorded by a default [`PerfBackend`](counters::PerfBackend) instance. Names prefixed with `-` or `+` remove counters from or add counters to the default set, e.g. `-kcycle`. Names may carry `perf`-style modifiers: `:u` and `:k` count only user space or kernel events, e.g. `instr:uk`, and `:p` pins a counter so it is never multiplexed, e.g. `+cycle:p`. See [`with_counter_names`](counters::PerfBackend::with_counter_names).
+//! - **`QPE_SHARE_COUNTERS`** - if set to `1`, instances created on the same thread share their perf counters instead of opening duplicate events, avoiding multiplexing caused by multiple instances. See [`with_shared_counter_names`](counters::PerfBackend::with_shared_counter_names).
+//! - **`QPE_STRICT_MUX`** - if set to `1`, readings with multiplexed counters are reported as errors, and the affected labels are listed when finished. [`finish`](QuickPerfEvent::finish) returns them as an error. See [`with_strict_multiplexing`](QuickPerfEvent::with_strict_multiplexing).
+//! - **`QPE_CONTINUOUS`** - if set to `1`, perf counters are kept enabled and the values of each measurement are computed from the difference of readings, reducing the overhead of recording many tiny measurements. See [`with_continuous`](counters::PerfBackend::with_continuous).
+//! - **`QPE_PER_CPU`** - if set to `1`, perf counters count events of all processes on every CPU, capturing work of threads and processes that were created before the counters. Usually requires elevated permissions. See [`with_per_cpu_counter_names`](counters::PerfBackend::with_per_cpu_counter_names).
+//! - **`QPE_CGROUP`** - count events of all processes in a cgroup instead of the current process, e.g. `system.slice/my-service.scope`. Relative paths are resolved against `/sys/fs/cgroup`. See [`CgroupBackend`](counters::CgroupBackend).
+//! - **`QPE_DERIVED`** - add derived columns computed from other counters, e.g. `ipc` for instructions per cycle. See [`Derived::with_names`](counters::Derived::with_names) for supported values. Some columns, e.g. `fe-bound` for the fraction of cycles stalled in the frontend or `freq` for the average clock frequency in GHz, are added automatically when their counters are recorded.
+//! - **`QPE_EXPR`** - add columns computed from arithmetic expressions over other counters, e.g. `bw=bytes / time,cpi=cycle / instr`. Expressions followed by `%` are shown as percentages, e.g. `miss=llc-miss / l1-miss %`. See [`Derived::with_expr`](counters::Derived::with_expr) for the syntax.
+//! - **`QPE_COALESCE`** - if set to `1`, the live table merges consecutive records with identical labels into a single row showing their mean values and the spread across records.
+//! - **`QPE_NUMBER_FORMAT`** - how the markdown table formats counter values: `si` (default) scales them with SI prefixes like the live table, e.g. `3.7 G`, `fixed` prints three decimal places, and `raw` prints full precision. See [`NumberFormat`](formats::NumberFormat).
+//! - **`QPE_FLOAT_FORMAT`** - how the live and markdown tables format values, a comma separated list of `width=N`, `precision=N` (decimal places of values with a prefix, default 1), and `binary` (prefixes `Ki`, `Mi`, `Gi` for large values), e.g. `precision=2,binary`. See [`FloatFormat`](formats::FloatFormat).
+//! - **`QPE_AGGREGATE`** - if set to `mean` or `sum`, the markdown table combines rows with identical labels into a single row. See [`Tabled::with_aggregate`](formats::Tabled::with_aggregate).
+//! - **`QPE_SORT`** - sort the rows of the markdown table by a label or counter, optionally followed by `:asc` or `:desc`, e.g. `cycle:desc`. See [`Tabled::with_sort`](formats::Tabled::with_sort).
+//! - **`QPE_SUMMARY_ROW`** - if set to `sum`, `mean`, or `geomean`, the markdown table ends with a row summarizing each counter across all rows. See [`Tabled::with_summary_row`](formats::Tabled::with_summary_row).
+//! - **`QPE_REFERENCE`** - a label and value identifying reference rows, e.g. `impl=baseline`. The markdown table then shows the ratio of each counter to the reference row with otherwise identical labels. See [`Tabled::with_reference`](formats::Tabled::with_reference).
+//! - **`QPE_HIGHLIGHT`** - if set to `emoji` or `html`, the markdown table marks cells that regressed or improved by more than `QPE_HIGHLIGHT_THRESHOLD` percent (default 5) compared to the CSV file `QPE_BASELINE`. See [`Tabled::with_highlight`](formats::Tabled::with_highlight).
+//! - **`QPE_LABELS`** - comma separated `name=value` pairs added as label columns to every row, e.g. `machine=$HOSTNAME,branch=main`. This makes it easy to merge results from multiple machines. See [`ExtraLabels`](transform::ExtraLabels).
+//! - **`QPE_TIMESTAMP`** - if set to `relative` or `wall`, a label column `start` shows the time each row was recorded, relative to the creation of the `QuickPerfEvent` or as UTC wall clock time. See [`Timestamp`](transform::Timestamp).
+//! - **`QPE_REP_INDEX`** - if set to `1`, a column `rep` numbers the readings of each label, starting at 0. This helps distinguishing warm-up effects in the first execution from steady state. See [`RepIndex`](transform::RepIndex).
+//! - **`QPE_THROUGHPUT`** - if set to `1`, a column shows the scale processed per second, e.g. `ops/s`, or `GiB/s` if the scale unit is `byte`. See [`Throughput`](transform::Throughput).
+//! - **`QPE_CHECK_SCALE`** - if set to `1`, warn if the instructions per scale of a reading differ drastically from earlier readings with the same labels, which usually indicates a wrong `scale`. See [`ScaleCheck`](transform::ScaleCheck).
+//! - **`QPE_SCALE_UNIT`** - the unit of the scale passed to `record`, e.g. `byte`. It is shown in the header of the scale column and normalized counters are named per unit, e.g. `cycle/byte`. See [`with_scale_unit`](QuickPerfEvent::with_scale_unit).
+//! - **`QPE_NORMALIZE`** - comma separated `counter:unit` pairs selecting counters that are normalized per unit of a base attached using [`with_base`](Reading::with_base) instead of per scale, e.g. `time:byte`. See [`with_normalization`](QuickPerfEvent::with_normalization).
+//! - **`QPE_REPEAT`** - override the number of executions of measurements taken with [`run_repeated`](QuickPerfEvent::run_repeated).
+//! - **`QPE_PERCENTILES`** - comma separated percentiles reported for measurements taken with [`run_repeated`](QuickPerfEvent::run_repeated). Defaults to `50,95,99`.
+//! - **`QPE_EXCLUDE_OUTLIERS`** - if set to `1`, outliers are excluded from the statistics reported for measurements taken with [`run_repeated`](QuickPerfEvent::run_repeated).
+//! - **`QPE_BUDGET`** - abort the process if a measurement exceeds a limit, e.g. `instr<1e12,time<60`. See [`Budget`](counters::Budget).
+//! - **`QPE_UPROBES`** - comma separated list of functions of the current executable whose calls are counted, e.g. `my_crate::hot_path`. Linux only, see [`UprobeBackend`](counters::UprobeBackend).
+//! - **`QPE_CPU`** - pin measurements to a CPU, given by its number or `auto-isolated` to select a CPU isolated using the `isolcpus` or `nohz_full` kernel parameters. The CPU and whether it is isolated are recorded as columns. Linux only, see [`CpuPinning`](counters::CpuPinning).
+//! - **`QPE_IO`** - if set to `1`, record the bytes read from and written to storage and the number of read and write system calls from `/proc/self/io`. Linux only, see [`IoBackend`](counters::IoBackend).
+//! - **`QPE_RUSAGE`** - if set to `1`, record the growth of the peak resident set size, page faults, and context switches reported by `getrusage`. Unix only, see [`RusageBackend`](counters::RusageBackend).
+//! - **`QPE_NVML`** - record the utilization and energy consumption of the GPU with the given index, e.g. `0`. Requires the `nvml` feature, see `counters::NvmlBackend`.
+//! - **`QPE_CONTEXT`** - if set to `1`, record the CPU quota, number of usable CPUs, and whether the process runs in a container as detail columns. Linux only, see [`CgroupContext`](counters::CgroupContext).
+//! - **`QPE_ATTRIBUTION`** - write a report of the source locations causing the most events of a kind for each label, e.g. `br-miss`. The report is written to `QPE_ATTRIBUTION_FILE` (default `qpe-attribution.md`). Linux only, see [`Attribution`].
+//! - **`QPE_PERF_DATA`** - sample an event, e.g. `cycle`, during each measurement and write a `perf.data` file per label for use with `perf report`. Files are written to `QPE_PERF_DATA_DIR` (default `qpe-perf-data`), the sampling rate is set using `QPE_PERF_DATA_PERIOD` or `QPE_PERF_DATA_FREQ`. Linux only, see [`PerfData`].
+//! - **`QPE_FLAMEGRAPH_DIR`** - sample call stacks during each measurement and write them as folded stacks per label to the given directory, for rendering using e.g. `inferno-flamegraph`. The sampled event is set using `QPE_FLAMEGRAPH_EVENT` (default `t-clock`). Build with `-C force-frame-pointers=yes` for complete stacks. Linux only, see [`Flamegraph`].
+//! - **`QPE_TIMELINE`** - sample counters every `QPE_TIMELINE_INTERVAL` milliseconds (default 10) during each measurement and write the samples of each recorded reading to the given CSV file. Linux only, see [`Timeline`].
+//! - **`QPE_FAIL_ON_REGRESSION`** - compare readings against the CSV file `QPE_BASELINE` written by a previous run and report an error from [`finish`](QuickPerfEvent::finish) if counters regress by more than a threshold, e.g. `cycle:5%,instr:2%`. See [`RegressionGate`].
+//! - **`QPE_BASELINE_KEY`**, **`QPE_BASELINE_IGNORE`** - comma separated label names that identify rows when matching against `QPE_BASELINE`, or that are ignored, e.g. `host,date`. By default, all labels are used. See [`RowKey`].
+//! - **`QPE_SUMMARY`** - write the number of recorded, filtered, discarded, timed out, and failed measurements to a CSV file when finished. A one line summary is printed to stderr if any measurement was skipped. See [`Summary`].
+//! - **`QPE_REPRO`** - write a snippet reproducing the session, consisting of the settings as environment variables and a minimal program recording the same labels, to the given file. See [`Repro`](formats::Repro).
+//! - **`QPE_EXPORT_DIR`** - the directory written by `QPE_FORMAT=all`, default `qpe-export`. See [`Export`](formats::Export).
+//! - **`QPE_METADATA`** - if set to `1`, the CSV and markdown formats and `QPE_FORMAT=all` start with a description of the CPU, kernel, hostname, build profile, git commit, and the active settings. See [`Metadata`].
+//! - **`QPE_ENV_CHECK`** - if set to `0`, do not warn about noise sources such as frequency scaling, turbo boost, SMT, and thermal throttling. See [`Environment`].
 //! - **`QPE_LINE_LEN`** - override the line length used for line wrapping live tables. If not set, terminal size is detected automatically.
+//! - **`QPE_HEADER_INTERVAL`** - repeat the header row of live tables after this many rows, so the column names stay visible in long tables.
+//! - **`QPE_ASCII`** - if set to `1`, live tables are drawn using only ASCII characters such as `+`, `-`, and `|`, for CI logs and terminals that do not display box-drawing characters.
 //!
 //! # Acknowledgements
 //! This crate is heavily inspired by [the C++ header only library](https://github.com/viktorleis/perfevent).
 
+#[cfg(target_os = "linux")]
+mod attribution;
+mod config;
 pub mod counters;
+mod diff;
+pub mod embedded;
+mod environment;
+#[cfg(target_os = "linux")]
+mod flamegraph;
 pub mod formats;
+pub mod global;
+pub mod harness;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod histogram;
 mod labels;
+mod metadata;
+#[cfg(target_os = "linux")]
+mod perf_data;
+#[cfg(target_os = "linux")]
+mod perf_sys;
+mod regression;
+mod report;
+pub mod shared;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod short;
+mod summary;
+#[cfg(target_os = "linux")]
+mod symbols;
+#[cfg(all(target_os = "linux", feature = "perf"))]
+mod timeline;
+pub mod transform;
 
-pub use labels::{LabelMeta, Labels};
+#[cfg(target_os = "linux")]
+pub use attribution::Attribution;
+pub use config::Config;
+pub use diff::{DiffReport, DiffRow};
+pub use environment::Environment;
+#[cfg(target_os = "linux")]
+pub use flamegraph::Flamegraph;
+pub use labels::{DynLabels, LabelMeta, Labels, Overflow};
+pub use metadata::Metadata;
+#[cfg(target_os = "linux")]
+pub use perf_data::PerfData;
+pub use regression::{RegressionError, RegressionGate, RowKey};
+pub use report::{Report, Row};
+pub use summary::{FinishError, MultiplexingError, Skip, Summary};
+#[cfg(all(target_os = "linux", feature = "perf"))]
+pub use timeline::Timeline;
 
 use crate::{
-    counters::{Counters, counters_from_env},
-    formats::{Format, format_from_env},
+    counters::{CounterReading, Counters, Replay, counters_from_config},
+    formats::{Format, format_from_config},
 };
-use std::{borrow::Borrow, marker::PhantomData, time::SystemTime};
+use std::{
+    borrow::Borrow,
+    hint::black_box,
+    iter,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A function called with each recorded row and its counter names, see [`QuickPerfEvent::on_record`].
+type RecordHook = dyn FnMut(&Row, &[String]);
 
 /// Main entry point for performance measurement.
 ///
-/// `QuickPerfEvent` encapsulates a collection of hardware performance counters (`PerfCounters`) and configuration for reporting results.
+/// `QuickPerfEvent` encapsulates a collection of hardware performance counters (`Counters`) and configuration for reporting results.
 /// See the crate level documentation for more information.
 ///
 /// The generic parameter `L` must implement [`Labels`], providing a fixed schema
@@ -102,6 +236,38 @@ pub struct QuickPerfEvent<
     counters: C,
     format: F,
     error_printed: bool,
+    repeat: Option<usize>,
+    percentiles: Vec<f64>,
+    exclude_outliers: bool,
+    scale_unit: Option<String>,
+    /// Counters normalized by a base instead of the scale, with the unit of the base.
+    normalization: Vec<(String, String)>,
+    #[cfg(target_os = "linux")]
+    attribution: Option<Attribution>,
+    #[cfg(target_os = "linux")]
+    perf_data: Option<PerfData>,
+    #[cfg(target_os = "linux")]
+    flamegraph: Option<Flamegraph>,
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    timeline: Option<Timeline>,
+    regression_gate: Option<RegressionGate>,
+    strict_multiplexing: bool,
+    /// The labels of readings with multiplexed counters, in strict mode.
+    multiplexed: Vec<String>,
+    environment: Option<Environment>,
+    summary: Summary,
+    summary_file: Option<PathBuf>,
+    hooks: Vec<Box<RecordHook>>,
+    /// Collects the row passed to `hooks`.
+    hook_report: Report,
+    /// True if the last measurement was stopped, but not recorded yet.
+    unrecorded: bool,
+    /// If `true`, no summary is printed when finished, see [`embedded`](QuickPerfEvent::embedded).
+    quiet: bool,
+    /// The names of the active groups, outermost first.
+    groups: Vec<String>,
+    /// The label metadata including the group column, once [`group`](QuickPerfEvent::group) was called.
+    group_meta: Option<&'static [LabelMeta]>,
     _p: PhantomData<L>,
 }
 
@@ -117,8 +283,16 @@ pub struct Reading<
     pe: &'a mut QuickPerfEvent<L, C, F>,
     start_time: SystemTime,
     ret: T,
+    replay: Option<Replay>,
+    /// Multiplied with the scale passed to [`record`](Self::record).
+    scale_factor: usize,
+    /// Overrides the scale unit of the [`QuickPerfEvent`].
+    scale_unit: Option<String>,
+    /// Amounts of work counters may be normalized by instead of the scale, by unit.
+    bases: Vec<(String, f64)>,
 }
 
+/// An ongoing measurement, see [`QuickPerfEvent::start`].
 pub struct Running<
     'a,
     L: ?Sized + Labels,
@@ -127,11 +301,77 @@ pub struct Running<
 > {
     pe: &'a mut QuickPerfEvent<L, C, F>,
     start_time: SystemTime,
+    start_instant: Instant,
+    paused: bool,
+    /// The start time and readings of the current phase, see [`checkpoint`](Self::checkpoint).
+    checkpoint: Option<(SystemTime, Vec<CounterReading>)>,
+}
+
+/// A measurement that is recorded when dropped, see [`QuickPerfEvent::scope`].
+///
+/// This dereferences to the [`Running`] measurement, so it can be paused or checkpointed.
+#[must_use]
+pub struct Scope<
+    'a,
+    L: ?Sized + Labels,
+    B: Borrow<L>,
+    C: Counters = Box<dyn Counters>,
+    F: Format = Box<dyn Format>,
+> {
+    running: Option<Running<'a, L, C, F>>,
+    scale: usize,
+    labels: B,
+}
+
+/// A group of measurements that ends when dropped, see [`QuickPerfEvent::group`].
+///
+/// This dereferences to the [`QuickPerfEvent`], so measurements and nested groups can be started from it.
+#[must_use]
+pub struct Group<
+    'a,
+    L: ?Sized + Labels,
+    C: Counters = Box<dyn Counters>,
+    F: Format = Box<dyn Format>,
+> {
+    pe: &'a mut QuickPerfEvent<L, C, F>,
 }
 
 /// Create a `QuickPerfEvent` configured from environment variables.
 pub fn from_env<L: Labels + ?Sized>() -> QuickPerfEvent<L, Box<dyn Counters>, Box<dyn Format>> {
-    QuickPerfEvent::new(counters_from_env(), format_from_env())
+    QuickPerfEvent::from_config(&Config::from_env())
+}
+
+impl<L: Labels + ?Sized> QuickPerfEvent<L> {
+    /// Create a `QuickPerfEvent` configured from a [`Config`].
+    ///
+    /// This behaves like [`from_env`], but takes settings from the given source.
+    /// Use this to give each instance its own configuration if multiple instances are embedded in one process.
+    pub fn from_config(config: &Config) -> Self {
+        let mut qpe = QuickPerfEvent::new(counters_from_config(config), format_from_config(config))
+            .with_measurement_config(config)
+            .with_regression_gate(RegressionGate::from_config(config))
+            .with_environment_check(config.get("ENV_CHECK").as_deref() != Some("0"));
+        #[cfg(target_os = "linux")]
+        {
+            qpe = qpe
+                .with_attribution(Attribution::from_config(config))
+                .with_perf_data(PerfData::from_config(config))
+                .with_flamegraph(Flamegraph::from_config(config));
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        {
+            qpe = qpe.with_timeline(Timeline::from_config(config));
+        }
+        qpe
+    }
+
+    /// Create a `QuickPerfEvent` configured from environment variables with a custom prefix.
+    ///
+    /// This behaves like [`from_env`], but reads e.g. `MYAPP_PERF_EVENTS` instead of `QPE_EVENTS` for a prefix of `MYAPP_PERF_`.
+    /// This allows applications embedding this crate to expose their own configuration namespace.
+    pub fn with_env_prefix(prefix: &str) -> Self {
+        Self::from_config(&Config::env_with_prefix(prefix))
+    }
 }
 
 impl<L: Labels + ?Sized, C: Counters, F: Format> QuickPerfEvent<L, C, F> {
@@ -144,10 +384,296 @@ impl<L: Labels + ?Sized, C: Counters, F: Format> QuickPerfEvent<L, C, F> {
             counters,
             error_printed: false,
             format,
+            repeat: None,
+            percentiles: vec![50.0, 95.0, 99.0],
+            exclude_outliers: false,
+            scale_unit: None,
+            normalization: Vec::new(),
+            #[cfg(target_os = "linux")]
+            attribution: None,
+            #[cfg(target_os = "linux")]
+            perf_data: None,
+            #[cfg(target_os = "linux")]
+            flamegraph: None,
+            #[cfg(all(target_os = "linux", feature = "perf"))]
+            timeline: None,
+            regression_gate: None,
+            strict_multiplexing: false,
+            multiplexed: Vec::new(),
+            environment: None,
+            summary: Summary::default(),
+            summary_file: None,
+            hooks: Vec::new(),
+            hook_report: Report::default(),
+            unrecorded: false,
+            quiet: false,
+            groups: Vec::new(),
+            group_meta: None,
             _p: PhantomData,
         }
     }
 
+    /// Applies the settings of a [`Config`] affecting how measurements are taken.
+    pub(crate) fn with_measurement_config(mut self, config: &Config) -> Self {
+        self = self
+            .with_repeat(config.parse("REPEAT"))
+            .with_exclude_outliers(config.flag("EXCLUDE_OUTLIERS"))
+            .with_strict_multiplexing(config.flag("STRICT_MUX"))
+            .with_scale_unit(config.get("SCALE_UNIT").as_deref())
+            .with_summary_file(config.get("SUMMARY").as_deref().map(Path::new));
+        if let Some(percentiles) = config.get("PERCENTILES") {
+            let percentiles: Vec<f64> = percentiles
+                .split(",")
+                .filter(|x| !x.is_empty())
+                .filter_map(|x| {
+                    x.parse()
+                        .map_err(|_| eprintln!("failed to parse percentile: {x:?}"))
+                        .ok()
+                })
+                .collect();
+            self = self.with_percentiles(&percentiles);
+        }
+        if let Some(normalization) = config.get("NORMALIZE") {
+            for def in normalization.split(",").filter(|x| !x.is_empty()) {
+                match def.rsplit_once(":") {
+                    Some((counter, unit)) => self = self.with_normalization(counter, unit),
+                    None => eprintln!("invalid normalization {def:?}: expected counter:unit"),
+                }
+            }
+        }
+        self
+    }
+
+    /// Override the number of executions for all measurements taken with [`run_repeated`](Self::run_repeated).
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_REPEAT`.
+    pub fn with_repeat(mut self, repeat: Option<usize>) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Set the percentiles reported for measurements taken with [`run_repeated`](Self::run_repeated).
+    ///
+    /// Percentiles are given in the range 0 to 100.
+    /// The default is `[50.0, 95.0, 99.0]`, i.e. the median and the 95th and 99th percentile.
+    /// For a default instance, this may be set from the environment variable `QPE_PERCENTILES`.
+    pub fn with_percentiles(mut self, percentiles: &[f64]) -> Self {
+        self.percentiles = percentiles.to_vec();
+        self
+    }
+
+    /// Exclude outliers from the statistics reported for measurements taken with [`run_repeated`](Self::run_repeated).
+    ///
+    /// Outliers are always counted in the `outliers` column, this only controls whether they contribute to the other columns.
+    /// For a default instance, this is set from the environment variable `QPE_EXCLUDE_OUTLIERS`.
+    pub fn with_exclude_outliers(mut self, exclude: bool) -> Self {
+        self.exclude_outliers = exclude;
+        self
+    }
+
+    /// Set the unit of the scale passed to [`record`](Reading::record), e.g. `byte` or `request`.
+    ///
+    /// The unit is shown in the header of the scale column, and normalized counters are named per unit, e.g. `cycle/byte`.
+    /// It may be overridden for individual readings using [`Reading::with_scale_unit`].
+    /// For a default instance, this is set from the environment variable `QPE_SCALE_UNIT`.
+    pub fn with_scale_unit(mut self, unit: Option<&str>) -> Self {
+        self.scale_unit = unit.map(str::to_string);
+        self
+    }
+
+    /// Normalize `counter` per `unit` of a base attached using [`Reading::with_base`], instead of per scale.
+    ///
+    /// The column is named `counter/unit`, e.g. `time/byte`.
+    /// Any counter may be selected, including derived columns and counters that are otherwise not divided by the scale, such as `time`.
+    /// For a default instance, this is set from the environment variable `QPE_NORMALIZE`, e.g. `time:byte,l1-miss:byte`.
+    pub fn with_normalization(mut self, counter: &str, unit: &str) -> Self {
+        self.normalization
+            .push((counter.to_string(), unit.to_string()));
+        self
+    }
+
+    /// Write the number of recorded and skipped measurements to a CSV file when finished, see [`Summary::write`].
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_SUMMARY`.
+    pub fn with_summary_file(mut self, path: Option<&Path>) -> Self {
+        self.summary_file = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// Attribute events to source locations, writing a report per label.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_ATTRIBUTION`.
+    #[cfg(target_os = "linux")]
+    pub fn with_attribution(mut self, attribution: Option<Attribution>) -> Self {
+        self.attribution = attribution;
+        self
+    }
+
+    /// Sample an event during each measurement, writing a `perf.data` file per label.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_PERF_DATA`.
+    #[cfg(target_os = "linux")]
+    pub fn with_perf_data(mut self, perf_data: Option<PerfData>) -> Self {
+        self.perf_data = perf_data;
+        self
+    }
+
+    /// Sample call stacks during each measurement, writing folded stacks per label for rendering as a flame graph.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_FLAMEGRAPH_DIR`.
+    #[cfg(target_os = "linux")]
+    pub fn with_flamegraph(mut self, flamegraph: Option<Flamegraph>) -> Self {
+        self.flamegraph = flamegraph;
+        self
+    }
+
+    /// Sample counters periodically during each measurement, writing a timeline per recorded reading.
+    ///
+    /// For a default instance, this is set from the environment variable `QPE_TIMELINE`.
+    #[cfg(all(target_os = "linux", feature = "perf"))]
+    pub fn with_timeline(mut self, timeline: Option<Timeline>) -> Self {
+        self.timeline = timeline;
+        self
+    }
+
+    /// Compare all readings against a baseline, failing if counters regress.
+    ///
+    /// For a default instance, this is set from the environment variables `QPE_BASELINE` and `QPE_FAIL_ON_REGRESSION`.
+    pub fn with_regression_gate(mut self, gate: Option<RegressionGate>) -> Self {
+        self.regression_gate = gate;
+        self
+    }
+
+    /// Treat readings with multiplexed counters as errors.
+    ///
+    /// Multiplexed counters only run for part of the measurement and are extrapolated, which is not acceptable for e.g. published numbers.
+    /// In strict mode, affected readings are still recorded, but an error naming the counters is printed to stderr.
+    /// The affected labels are reported as an error by [`finish`](Self::finish).
+    /// If the `QuickPerfEvent` is dropped instead, they are only printed to stderr.
+    /// For a default instance, this is set from the environment variable `QPE_STRICT_MUX`.
+    pub fn with_strict_multiplexing(mut self, strict: bool) -> Self {
+        self.strict_multiplexing = strict;
+        self
+    }
+
+    /// Detect common sources of noise such as frequency scaling and print a warning listing them, at most once per process.
+    ///
+    /// If enabled, a warning is also printed when the instance is dropped if CPUs were thermally throttled in the meantime.
+    /// For a default instance, this is enabled unless the environment variable `QPE_ENV_CHECK` is set to `0`.
+    pub fn with_environment_check(mut self, enabled: bool) -> Self {
+        self.environment = enabled.then(Environment::detect);
+        if let Some(environment) = &self.environment {
+            environment::warn_environment(environment);
+        }
+        self
+    }
+
+    /// Returns the environment detected at construction, see [`with_environment_check`](Self::with_environment_check).
+    pub fn environment(&self) -> Option<&Environment> {
+        self.environment.as_ref()
+    }
+
+    /// Returns the number of recorded measurements and the measurements skipped so far.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Note that a measurement was not taken or not recorded, to be included in the [`Summary`].
+    ///
+    /// Use this to account for configurations a benchmark skips, e.g. parameter combinations that are filtered out or runs that exceeded a time limit.
+    /// Readings that are dropped without being recorded and errors returned by the output format are included automatically.
+    pub fn skip(&mut self, reason: Skip, labels: impl Borrow<L>) {
+        let description = self.describe_labels(labels.borrow());
+        self.summary.skipped.push((reason, description));
+    }
+
+    /// Start a group of measurements, e.g. a family of benchmarks in a suite.
+    ///
+    /// Until the returned [`Group`] is dropped, records are labeled with the group in an additional `group` column preceding the other labels.
+    /// Groups can be nested, the column then contains the names of all active groups separated by `/`, e.g. `sort/small`.
+    /// Once a group was started, the column is included in all further records, with an empty value outside of groups.
+    /// If results were recorded before the first group was started, the report is finished first, so that the output starts over with the new column.
+    pub fn group(&mut self, name: impl Into<String>) -> Group<'_, L, C, F> {
+        if self.group_meta.is_none() {
+            if self.summary.recorded > 0
+                && let Err(e) = self.format.dump_and_reset(L::meta(), &mut self.counters)
+                && !self.error_printed
+            {
+                eprintln!("error finnishing report: {e}");
+            }
+            let meta: Vec<LabelMeta> = iter::once(LabelMeta::new("group").with_width(12))
+                .chain(L::meta().iter().cloned())
+                .collect();
+            self.group_meta = Some(meta.leak());
+        }
+        self.groups.push(name.into());
+        Group { pe: self }
+    }
+
+    /// Returns the label metadata of records, including the group column if groups are used.
+    fn label_meta(&self) -> &'static [LabelMeta] {
+        self.group_meta.unwrap_or(L::meta())
+    }
+
+    /// Returns the label values of a record, including the group if groups are used.
+    fn label_values(&self, labels: &L) -> Vec<String> {
+        let mut values = Vec::new();
+        if self.group_meta.is_some() {
+            values.push(self.groups.join("/"));
+        }
+        labels.values(&mut |x| values.push(x.to_string()));
+        let names = self.label_meta();
+        assert!(
+            values.len() == names.len(),
+            "got {} label values for {} label names {:?}, the names of DynLabels must be set before recording",
+            values.len(),
+            names.len(),
+            names.iter().map(|x| x.name()).collect::<Vec<_>>()
+        );
+        values
+    }
+
+    /// Formats labels as `name=value` pairs separated by commas.
+    fn describe_labels(&self, labels: &L) -> String {
+        (self.label_meta().iter())
+            .zip(self.label_values(labels))
+            .map(|(meta, value)| format!("{}={value}", meta.name()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Register a function called with each recorded row before it is passed to the format.
+    ///
+    /// The function receives the row and the names of its counters.
+    /// This allows custom logging, assertions, or forwarding readings to application telemetry without implementing a [`Format`].
+    /// Counters are named and normalized as seen by the format, e.g. per [scale unit](Self::with_scale_unit).
+    /// Hooks are called in the order they were registered.
+    pub fn on_record(&mut self, hook: impl FnMut(&Row, &[String]) + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Returns the format, e.g. to retrieve the rows collected by a [`Report`].
+    pub fn format_mut(&mut self) -> &mut F {
+        &mut self.format
+    }
+
+    /// Finish the report.
+    ///
+    /// This is equivalent to dropping the `QuickPerfEvent`, except that failures are returned as an error instead of only being printed.
+    /// Use this to exit with a nonzero status if counters regressed, e.g. in CI.
+    /// Multiplexing in [strict mode](Self::with_strict_multiplexing) takes precedence over regressions detected by the [`RegressionGate`], since it invalidates comparisons.
+    pub fn finish(mut self) -> Result<(), FinishError> {
+        let gate = self.regression_gate.take();
+        let multiplexed = mem::take(&mut self.multiplexed);
+        drop(self);
+        if !multiplexed.is_empty() {
+            return Err(FinishError::Multiplexed(MultiplexingError {
+                labels: multiplexed,
+            }));
+        }
+        gate.map_or(Ok(()), |x| x.result().map_err(FinishError::Regression))
+    }
+
     /// Measure the execution of a function.
     ///
     /// This is a shorthand for wrapping the function in [`start`](Self::start) and [`stop`](Running::stop) calls.
@@ -157,48 +683,386 @@ impl<L: Labels + ?Sized, C: Counters, F: Format> QuickPerfEvent<L, C, F> {
         running.stop().replace_return_value(ret).0
     }
 
+    /// Measure repeated executions of a function.
+    ///
+    /// The function is executed `n` times, each execution being measured separately.
+    /// Instead of a single sample, the resulting [`Reading`] reports the mean, standard deviation, minimum, maximum, and [percentiles](Self::with_percentiles) of each counter.
+    /// For a counter `x`, these are recorded in the columns `x`, `x-sd`, `x-min`, `x-max`, and e.g. `x-p50` for the median.
+    /// Executions where any counter deviates from its median by much more than the median absolute deviation are counted in an `outliers` column.
+    /// They can be excluded from the statistics using [`with_exclude_outliers`](Self::with_exclude_outliers).
+    /// Since this changes the set of columns, repeated and single measurements should not be recorded in the same session.
+    ///
+    /// The number of executions may be overridden using [`with_repeat`](Self::with_repeat).
+    /// The return value of the last execution is associated with the reading.
+    pub fn run_repeated<R>(
+        &mut self,
+        n: usize,
+        mut f: impl FnMut() -> R,
+    ) -> Reading<'_, L, R, C, F> {
+        let n = self.repeat.unwrap_or(n);
+        let start_time = SystemTime::now();
+        let mut samples = Vec::with_capacity(n);
+        let mut ret = None;
+        for _ in 0..n.max(1) {
+            let (r, sample) = self.measure(&mut f);
+            ret = Some(r);
+            samples.push(sample);
+        }
+        let replay = Replay::summary(
+            &self.counters,
+            &samples,
+            &self.percentiles,
+            self.exclude_outliers,
+        );
+        self.unrecorded = true;
+        Reading {
+            pe: self,
+            start_time,
+            ret: ret.unwrap(),
+            replay: Some(replay),
+            scale_factor: 1,
+            scale_unit: None,
+            bases: Vec::new(),
+        }
+    }
+
+    /// Measure a loop executing a function `iterations` times, correcting for loop overhead.
+    ///
+    /// In addition to the benchmark loop, an empty loop with the same number of iterations is measured.
+    /// The readings of the empty loop are subtracted from those of the benchmark loop.
+    /// This is useful for operations taking only a few cycles, where the loop itself makes up a significant fraction of the measurement.
+    /// The uncorrected values are recorded as detail columns `x-raw`, which are only included by machine-readable formats like CSV.
+    ///
+    /// Usually, `iterations` should also be passed as the scale to [`record`](Reading::record).
+    pub fn run_batched<R>(
+        &mut self,
+        iterations: usize,
+        mut f: impl FnMut() -> R,
+    ) -> Reading<'_, L, (), C, F> {
+        let start_time = SystemTime::now();
+        let ((), measured) = self.measure(|| {
+            for _ in 0..iterations {
+                black_box(f());
+            }
+        });
+        let ((), overhead) = self.measure(|| {
+            for _ in 0..iterations {
+                black_box(());
+            }
+        });
+        let replay = Replay::overhead_corrected(&self.counters, &measured, &overhead);
+        self.unrecorded = true;
+        Reading {
+            pe: self,
+            start_time,
+            ret: (),
+            replay: Some(replay),
+            scale_factor: 1,
+            scale_unit: None,
+            bases: Vec::new(),
+        }
+    }
+
+    /// Measure repeated executions of a function until a wall-clock time budget is exhausted.
+    ///
+    /// The function is executed at least once.
+    /// Executions are measured together, checking the elapsed time after batches of growing size to keep timing overhead low.
+    /// The scale passed to [`record`](Reading::record) is multiplied by the number of executions, so passing `1` reports counters per execution.
+    /// The return value of the last execution is associated with the reading.
+    pub fn run_for<R>(
+        &mut self,
+        duration: Duration,
+        mut f: impl FnMut() -> R,
+    ) -> Reading<'_, L, R, C, F> {
+        let running = self.start();
+        let mut ret = f();
+        let mut invocations = 1;
+        let mut batch = 1;
+        loop {
+            let elapsed = running.elapsed();
+            if elapsed >= duration {
+                break;
+            }
+            let per_call = elapsed.as_nanos() / invocations as u128;
+            let remaining = (duration - elapsed).as_nanos() / per_call.max(1);
+            batch = (batch * 2)
+                .min(remaining.try_into().unwrap_or(usize::MAX))
+                .max(1);
+            for _ in 1..batch {
+                black_box(f());
+            }
+            ret = f();
+            invocations += batch;
+        }
+        let (mut reading, ()) = running.stop().replace_return_value(());
+        reading.scale_factor = invocations;
+        reading.replace_return_value(ret).0
+    }
+
+    /// Measure a function calling [`tick`](short::Ticker::tick) once per iteration, additionally reporting the distribution of per-iteration time.
+    ///
+    /// Iterations are timed using the time stamp counter, which is cheap enough to read once per iteration.
+    /// Counters are measured over the whole run as with [`run`](Self::run), so the scale passed to [`record`](Reading::record) should usually be the number of iterations.
+    /// In addition, the columns `tick-max` and e.g. `tick-p99` for each of the [percentiles](Self::with_percentiles) report time stamp counter ticks per iteration, which are not normalized.
+    /// The first iteration is timed from the start of the run.
+    /// Linux on x86-64 only.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn run_ticked<R>(
+        &mut self,
+        f: impl FnOnce(&mut short::Ticker) -> R,
+    ) -> Reading<'_, L, R, C, F> {
+        let running = self.start();
+        let mut ticker = short::Ticker::new();
+        let ret = f(&mut ticker);
+        let (mut reading, ()) = running.stop().replace_return_value(());
+        let counters = &mut reading.pe.counters;
+        let mut names = Vec::new();
+        counters.names(&mut |x| names.push(x.to_string()));
+        let mut meta = Vec::new();
+        counters.meta(&mut |x| meta.push(x));
+        let mut readings = Vec::new();
+        counters.read(&mut readings);
+        ticker.summary(
+            &reading.pe.percentiles,
+            &mut names,
+            &mut meta,
+            &mut readings,
+        );
+        reading.replay = Some(Replay::new(names, meta, readings));
+        reading.replace_return_value(ret).0
+    }
+
+    /// Measures a function, returning its result and the counter readings.
+    fn measure<R>(&mut self, f: impl FnOnce() -> R) -> (R, Vec<CounterReading>) {
+        let running = self.start();
+        let ret = f();
+        let (reading, ()) = running.stop().replace_return_value(());
+        reading.pe.unrecorded = false;
+        let mut readings = Vec::new();
+        reading.pe.counters.read(&mut readings);
+        (ret, readings)
+    }
+
+    /// Start a measurement that is recorded when the returned guard is dropped.
+    ///
+    /// This allows instrumenting a block of code without moving it into a closure.
+    /// The arguments are the same as for [`record`](Reading::record).
+    /// If the guard is dropped during a panic, the measurement is discarded.
+    /// Note that binding the guard to `_` drops it immediately, use a named binding like `_scope` instead.
+    pub fn scope<B: Borrow<L>>(&mut self, scale: usize, labels: B) -> Scope<'_, L, B, C, F> {
+        Scope {
+            running: Some(self.start()),
+            scale,
+            labels,
+        }
+    }
+
     /// Start a measurement.
     ///
     /// After running your benchmark, call [`stop`](Running::stop) on the returned value to obtain a [`Reading`]
     pub fn start(&mut self) -> Running<'_, L, C, F> {
         let start_time = SystemTime::now();
+        if mem::take(&mut self.unrecorded) {
+            self.summary.skipped.push((Skip::Discarded, String::new()));
+        }
         if self.running {
             self.counters.disable();
         }
         self.running = true;
         self.counters.reset();
         self.counters.enable();
+        #[cfg(target_os = "linux")]
+        if let Some(attribution) = &mut self.attribution {
+            attribution.enable();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(perf_data) = &mut self.perf_data {
+            perf_data.enable();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(flamegraph) = &mut self.flamegraph {
+            flamegraph.enable();
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        if let Some(timeline) = &mut self.timeline {
+            timeline.begin();
+        }
         Running {
             pe: self,
             start_time,
+            start_instant: Instant::now(),
+            paused: false,
+            checkpoint: None,
         }
     }
 }
 
 impl<'a, L: Labels + ?Sized, T, C: Counters, F: Format> Reading<'a, L, T, C, F> {
+    /// Like [`record`](Self::record), but with a fractional scale, e.g. the bytes processed computed as `n * avg_record_size`.
+    ///
+    /// Formats only support integer scales, so the scale is rounded and counter values are adjusted such that their normalized values are divided by the exact scale.
+    /// The scale column shows the rounded scale, which is at least 1.
+    /// If `scale` is not a positive finite number, a warning is printed to stderr and the readings are recorded as not [`valid`](counters::CounterReading::valid).
+    pub fn record_f64(mut self, scale: f64, labels: impl Borrow<L>) -> T {
+        let valid = scale.is_finite() && scale > 0.0;
+        if !valid {
+            eprintln!("invalid scale {scale}: expected a positive finite number");
+        }
+        // Clamping avoids overflow when multiplying with the number of invocations.
+        let rounded = (scale.round() as usize).clamp(1, usize::MAX / self.scale_factor.max(1));
+        let counters: &dyn Counters = match &self.replay {
+            Some(replay) => replay,
+            None => &self.pe.counters,
+        };
+        let mut names = Vec::new();
+        counters.names(&mut |x| names.push(x.to_string()));
+        let mut meta = Vec::new();
+        counters.meta(&mut |x| meta.push(x));
+        let unit = counters.scale_unit().map(str::to_string);
+        let mut readings = Vec::new();
+        match &mut self.replay {
+            Some(replay) => replay.read(&mut readings),
+            None => self.pe.counters.read(&mut readings),
+        }
+        for reading in &mut readings {
+            if !valid {
+                reading.valid = false;
+            } else if reading.enable_scale {
+                reading.value *= rounded as f64 / scale;
+            }
+        }
+        self.replay = Some(Replay::new(names, meta, readings).with_scale_unit(unit));
+        self.record(rounded, labels)
+    }
+
     /// Records the measured result.
     ///
     /// The `scale` argument normalizes counter values (e.g. per iteration count).
+    /// For fractional scales, use [`record_f64`](Self::record_f64).
+    /// If the scale multiplied by the number of executions of [`run_for`](QuickPerfEvent::run_for) overflows, it saturates with a warning to stderr.
     /// The given `labels` instance supplies the labels for this sample.
-    pub fn record(self, scale: usize, labels: impl Borrow<L>) -> T {
-        if let Err(e) = self.pe.format.push(
+    pub fn record(mut self, scale: usize, labels: impl Borrow<L>) -> T {
+        let scale = scale.checked_mul(self.scale_factor).unwrap_or_else(|| {
+            eprintln!(
+                "scale {scale} times {} executions overflows, recording a scale of {}",
+                self.scale_factor,
+                usize::MAX
+            );
+            usize::MAX
+        });
+        let label_meta = self.pe.label_meta();
+        let label_vec = self.pe.label_values(labels.borrow());
+        let description = self.pe.describe_labels(labels.borrow());
+        let counters: &mut dyn Counters = match &mut self.replay {
+            Some(replay) => replay,
+            None => &mut self.pe.counters,
+        };
+        let mut per_base = (!self.pe.normalization.is_empty())
+            .then(|| Replay::per_base(counters, &self.pe.normalization, &self.bases));
+        let counters: &mut dyn Counters = match &mut per_base {
+            Some(per_base) => per_base,
+            None => counters,
+        };
+        let unit = self.scale_unit.as_ref().or(self.pe.scale_unit.as_ref());
+        let mut per_unit = unit.map(|unit| Replay::per_unit(counters, unit));
+        let formatted: &mut dyn Counters = match &mut per_unit {
+            Some(per_unit) => per_unit,
+            None => counters,
+        };
+        self.pe.unrecorded = false;
+        if !self.pe.hooks.is_empty() {
+            let report = &mut self.pe.hook_report;
+            report.rows.clear();
+            // Pushing to a report never fails.
+            let _ = report.push(
+                scale,
+                self.start_time,
+                formatted,
+                &mut |dst| label_vec.iter().for_each(|x| dst(x)),
+                label_meta,
+            );
+            for hook in &mut self.pe.hooks {
+                hook(&report.rows[0], &report.counter_names);
+            }
+        }
+        let result = self.pe.format.push(
             scale,
             self.start_time,
-            &mut self.pe.counters,
-            &mut |dst| labels.borrow().values(dst),
-            L::meta(),
-        ) {
-            if !self.pe.error_printed {
-                self.pe.error_printed = true;
-                eprintln!("error recording result: {e}");
+            formatted,
+            &mut |dst| label_vec.iter().for_each(|x| dst(x)),
+            label_meta,
+        );
+        match result {
+            Ok(()) => self.pe.summary.recorded += 1,
+            Err(e) => {
+                self.pe
+                    .summary
+                    .skipped
+                    .push((Skip::Failed, description.clone()));
+                if !self.pe.error_printed {
+                    self.pe.error_printed = true;
+                    eprintln!("error recording result: {e}");
+                }
             }
         }
+        if let Some(gate) = &mut self.pe.regression_gate {
+            gate.check(scale, counters, label_meta, label_vec);
+        }
+        if self.pe.strict_multiplexing {
+            let mut readings = Vec::new();
+            counters.read_named(&mut readings);
+            let names: Vec<&str> = (readings.iter())
+                .filter(|x| x.reading.multiplexed)
+                .map(|x| x.name.as_str())
+                .collect();
+            if !names.is_empty() {
+                eprintln!(
+                    "error: counters were multiplexed for {description}: {}",
+                    names.join(", ")
+                );
+                self.pe.multiplexed.push(description.clone());
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(attribution) = &mut self.pe.attribution {
+            attribution.record(description.clone());
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(perf_data) = &mut self.pe.perf_data {
+            perf_data.record(description.clone());
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(flamegraph) = &mut self.pe.flamegraph {
+            flamegraph.record(description.clone());
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        if let Some(timeline) = &mut self.pe.timeline {
+            timeline.record(description.clone());
+        }
         self.ret
     }
 
+    /// Set the unit of the scale passed to [`record`](Self::record), e.g. `byte`.
+    ///
+    /// This overrides the unit set using [`QuickPerfEvent::with_scale_unit`] for this reading.
+    /// Since the unit changes the column names, readings with different units should not be recorded in the same session.
+    pub fn with_scale_unit(mut self, unit: &str) -> Self {
+        self.scale_unit = Some(unit.to_string());
+        self
+    }
+
+    /// Attach an amount of work in another unit than the scale, e.g. the bytes processed when the scale counts operations.
+    ///
+    /// Counters selected using [`QuickPerfEvent::with_normalization`] are divided by this amount instead of the scale.
+    /// This allows reporting e.g. `cycle` per operation and `time/byte` from the same reading.
+    pub fn with_base(mut self, unit: &str, amount: f64) -> Self {
+        self.bases.push((unit.to_string(), amount));
+        self
+    }
+
     /// Replace the associated return value.
     ///
-    /// A [`Reading`] contains an associated return value, which is returned from [`record`].
+    /// A [`Reading`] contains an associated return value, which is returned from [`record`](Self::record).
     /// For a [`Reading`] constructed from [`QuickPerfEvent::run`], this is the return value of the passed in function.
     /// This method can be used to separate this value from the reading, or to combine the reading with a return new value.
     pub fn replace_return_value<U>(self, ret: U) -> (Reading<'a, L, U, C, F>, T) {
@@ -207,6 +1071,10 @@ impl<'a, L: Labels + ?Sized, T, C: Counters, F: Format> Reading<'a, L, T, C, F>
                 pe: self.pe,
                 start_time: self.start_time,
                 ret,
+                replay: self.replay,
+                scale_factor: self.scale_factor,
+                scale_unit: self.scale_unit,
+                bases: self.bases,
             },
             self.ret,
         )
@@ -214,28 +1082,233 @@ impl<'a, L: Labels + ?Sized, T, C: Counters, F: Format> Reading<'a, L, T, C, F>
 }
 
 impl<'a, L: Labels + ?Sized, C: Counters, F: Format> Running<'a, L, C, F> {
-    /// Stop the measurement.
-    pub fn stop(self) -> Reading<'a, L, (), C, F> {
+    /// Returns the wall-clock time elapsed since the measurement was started.
+    ///
+    /// This allows deciding dynamically when to stop a benchmark, e.g. after a target duration.
+    /// Unlike the `time` counter, this includes time spent [paused](Self::pause).
+    pub fn elapsed(&self) -> Duration {
+        self.start_instant.elapsed()
+    }
+
+    /// Reads the current counter values without stopping the measurement.
+    ///
+    /// Readings are appended to `dst` in the same order as the names reported by [`Counters::names`].
+    /// Values are not normalized, as the scale of the measurement is not known yet.
+    pub fn peek(&mut self, dst: &mut Vec<CounterReading>) {
+        self.pe.counters.read(dst);
+    }
+
+    /// Records the counters accumulated since the previous checkpoint as a separate reading, without interrupting the measurement.
+    ///
+    /// This allows reporting phases of a run, e.g. `build`, `query`, and `teardown`, while the reading obtained from [`stop`](Self::stop) still covers the whole run.
+    /// The first checkpoint covers the time since the measurement was started.
+    /// The arguments are the same as for [`record`](Reading::record).
+    ///
+    /// Phase values are the differences of the counter values at the checkpoints.
+    /// Derived columns such as ratios are not subtracted, but [recomputed](Counters::recompute_derived) from the phase values.
+    /// [Gauge](counters::CounterMeta::gauge) counters are reported as read at the checkpoint.
+    pub fn checkpoint(&mut self, scale: usize, labels: impl Borrow<L>) {
+        let now = SystemTime::now();
+        let mut readings = Vec::new();
+        self.pe.counters.read(&mut readings);
+        let (phase_start, previous) = self
+            .checkpoint
+            .replace((now, readings.clone()))
+            .unwrap_or((self.start_time, Vec::new()));
+        let mut meta = Vec::new();
+        self.pe.counters.meta(&mut |x| meta.push(x));
+        for ((reading, previous), meta) in readings.iter_mut().zip(previous).zip(&meta) {
+            if !meta.gauge() {
+                reading.value -= previous.value;
+            }
+        }
+        self.pe.counters.recompute_derived(&mut readings);
+        let mut names = Vec::new();
+        self.pe.counters.names(&mut |x| names.push(x.to_string()));
+        Reading {
+            pe: &mut *self.pe,
+            start_time: phase_start,
+            ret: (),
+            replay: Some(Replay::new(names, meta, readings)),
+            scale_factor: 1,
+            scale_unit: None,
+            bases: Vec::new(),
+        }
+        .record(scale, labels);
+    }
+
+    /// Pause the measurement, excluding subsequent work from all counters until [`resume`](Self::resume) is called.
+    ///
+    /// This is useful for excluding setup work interleaved with measured work, e.g. regenerating inputs between batches.
+    /// Note that pausing and resuming takes some time itself, which is partially included in the counters.
+    /// Has no effect if the measurement is already paused.
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.paused = true;
+        #[cfg(target_os = "linux")]
+        if let Some(attribution) = &mut self.pe.attribution {
+            attribution.disable();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(perf_data) = &mut self.pe.perf_data {
+            perf_data.disable();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(flamegraph) = &mut self.pe.flamegraph {
+            flamegraph.disable();
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        if let Some(timeline) = &mut self.pe.timeline {
+            timeline.disable();
+        }
         self.pe.counters.disable();
         self.pe.running = false;
+    }
+
+    /// Resume a measurement paused using [`pause`](Self::pause).
+    ///
+    /// Has no effect if the measurement is not paused.
+    pub fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        self.paused = false;
+        self.pe.running = true;
+        self.pe.counters.enable();
+        #[cfg(target_os = "linux")]
+        if let Some(attribution) = &mut self.pe.attribution {
+            attribution.enable();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(perf_data) = &mut self.pe.perf_data {
+            perf_data.enable();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(flamegraph) = &mut self.pe.flamegraph {
+            flamegraph.enable();
+        }
+        #[cfg(all(target_os = "linux", feature = "perf"))]
+        if let Some(timeline) = &mut self.pe.timeline {
+            timeline.enable();
+        }
+    }
+
+    /// Stop the measurement.
+    pub fn stop(mut self) -> Reading<'a, L, (), C, F> {
+        self.pause();
+        self.pe.unrecorded = true;
         Reading {
             pe: self.pe,
             start_time: self.start_time,
             ret: (),
+            replay: None,
+            scale_factor: 1,
+            scale_unit: None,
+            bases: Vec::new(),
         }
     }
 }
 
+impl<'a, L: Labels + ?Sized, B: Borrow<L>, C: Counters, F: Format> Deref for Scope<'a, L, B, C, F> {
+    type Target = Running<'a, L, C, F>;
+
+    fn deref(&self) -> &Self::Target {
+        self.running.as_ref().unwrap()
+    }
+}
+
+impl<L: Labels + ?Sized, B: Borrow<L>, C: Counters, F: Format> DerefMut for Scope<'_, L, B, C, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.running.as_mut().unwrap()
+    }
+}
+
+impl<L: Labels + ?Sized, B: Borrow<L>, C: Counters, F: Format> Drop for Scope<'_, L, B, C, F> {
+    fn drop(&mut self) {
+        let reading = self.running.take().unwrap().stop();
+        if !std::thread::panicking() {
+            reading.record(self.scale, self.labels.borrow());
+        } else {
+            reading.pe.unrecorded = false;
+            reading.pe.skip(Skip::Discarded, self.labels.borrow());
+        }
+    }
+}
+
+impl<L: Labels + ?Sized, C: Counters, F: Format> Deref for Group<'_, L, C, F> {
+    type Target = QuickPerfEvent<L, C, F>;
+
+    fn deref(&self) -> &Self::Target {
+        self.pe
+    }
+}
+
+impl<L: Labels + ?Sized, C: Counters, F: Format> DerefMut for Group<'_, L, C, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.pe
+    }
+}
+
+impl<L: Labels + ?Sized, C: Counters, F: Format> Drop for Group<'_, L, C, F> {
+    fn drop(&mut self) {
+        self.pe.groups.pop();
+    }
+}
+
 impl<L: Labels + ?Sized, C: Counters, F: Format> Drop for QuickPerfEvent<L, C, F> {
     fn drop(&mut self) {
-        if let Err(e) = self.format.dump_and_reset(L::meta(), &mut self.counters) {
-            if !self.error_printed {
-                eprintln!("error finnishing report: {e}");
-            }
+        let label_meta = self.label_meta();
+        if let Err(e) = self.format.dump_and_reset(label_meta, &mut self.counters)
+            && !self.error_printed
+        {
+            eprintln!("error finnishing report: {e}");
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(attribution) = &mut self.attribution {
+            attribution.dump_and_reset(label_meta);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(perf_data) = &mut self.perf_data {
+            perf_data.dump_and_reset();
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(flamegraph) = &mut self.flamegraph {
+            flamegraph.dump_and_reset();
+        }
+        if let Some(throttled) = self
+            .environment
+            .as_ref()
+            .and_then(Environment::throttled_since)
+            && throttled > 0
+        {
+            eprintln!("CPUs were thermally throttled {throttled} times while measuring");
+        }
+        if mem::take(&mut self.unrecorded) {
+            self.summary.skipped.push((Skip::Discarded, String::new()));
+        }
+        if !self.summary.skipped.is_empty() && !self.quiet {
+            eprintln!("{}", self.summary);
+        }
+        if let Some(path) = &self.summary_file
+            && let Err(e) = self.summary.write(path)
+        {
+            eprintln!("failed to write {}: {e}", path.display());
+        }
+        if !self.multiplexed.is_empty() {
+            let labels = mem::take(&mut self.multiplexed);
+            eprintln!("{}", MultiplexingError { labels });
+        }
+        if let Some(gate) = &self.regression_gate
+            && let Err(e) = gate.result()
+        {
+            eprintln!("{e}");
         }
     }
 }
 
+#[cfg(any(feature = "live", feature = "csv"))]
 fn visit<T: ?Sized>(counters: &[impl Borrow<T>], dst: &mut dyn FnMut(&T)) {
     for name in counters {
         dst(name.borrow())
@@ -0,0 +1,97 @@
+use crate::config::Config;
+use std::{fs, process::Command, thread};
+
+/// Describes the system, build, and configuration a report was recorded with.
+///
+/// This makes result files self-describing, so they can be interpreted and compared long after they were written.
+/// With `QPE_METADATA` set to `1`, the CSV and markdown formats and the files written by [`Export`](crate::formats::Export) start with this metadata.
+/// Information that cannot be determined is `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The CPU model, e.g. `AMD Ryzen 7 5800X 8-Core Processor`.
+    pub cpu: Option<String>,
+    /// The number of CPUs usable by the process.
+    pub cores: Option<usize>,
+    /// The version of the operating system kernel.
+    pub kernel: Option<String>,
+    pub hostname: Option<String>,
+    /// `debug` if this crate was built with debug assertions, otherwise `release`.
+    pub profile: &'static str,
+    /// The commit checked out in the working directory, with a `-dirty` suffix if there are uncommitted changes.
+    pub git_commit: Option<String>,
+    /// The settings in effect, see [`Config::settings`].
+    pub settings: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Collects metadata, including the settings of the default configuration.
+    pub fn detect() -> Self {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Self {
+        Metadata {
+            cpu: cpu_model(),
+            cores: thread::available_parallelism().ok().map(|x| x.get()),
+            kernel: read("/proc/sys/kernel/osrelease"),
+            hostname: read("/proc/sys/kernel/hostname")
+                .or_else(|| std::env::var("HOSTNAME").ok())
+                .or_else(|| std::env::var("COMPUTERNAME").ok()),
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+            git_commit: git_commit(),
+            settings: (config.settings().into_iter())
+                .map(|(name, value)| (config.name(&name), value))
+                .collect(),
+        }
+    }
+
+    /// Returns the known entries as name-value pairs, e.g. `("cores", "8")`.
+    ///
+    /// Settings are named like the environment variables, e.g. `QPE_EVENTS`.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        let mut push = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                entries.push((name.to_string(), value));
+            }
+        };
+        push("cpu", self.cpu.clone());
+        push("cores", self.cores.map(|x| x.to_string()));
+        push("kernel", self.kernel.clone());
+        push("hostname", self.hostname.clone());
+        push("profile", Some(self.profile.to_string()));
+        push("git_commit", self.git_commit.clone());
+        entries.extend(self.settings.iter().cloned());
+        entries
+    }
+}
+
+fn read(path: &str) -> Option<String> {
+    Some(fs::read_to_string(path).ok()?.trim().to_string())
+}
+
+fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        matches!(key.trim(), "model name" | "Model").then(|| value.trim().to_string())
+    })
+}
+
+fn git_commit() -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        output.status.success().then_some(output.stdout)
+    };
+    let commit = String::from_utf8(git(&["rev-parse", "HEAD"])?).ok()?;
+    let dirty = !git(&["status", "--porcelain", "--untracked-files=no"])?.is_empty();
+    Some(format!(
+        "{}{}",
+        commit.trim(),
+        if dirty { "-dirty" } else { "" }
+    ))
+}
@@ -0,0 +1,299 @@
+use crate::{attribution::sampled_event, config::Config, perf_sys::*};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Records samples of an event into a `perf.data` file per label, for inspection using `perf report`.
+///
+/// While a measurement is running, the instruction pointer is sampled every `period` events, or `frequency` times per second.
+/// Samples are grouped by the labels of the recorded [`Reading`](crate::Reading).
+/// When the [`QuickPerfEvent`](crate::QuickPerfEvent) is dropped, the samples of each label are written to a file in the output directory, e.g. `qpe-perf-data/name=sort.perf.data`.
+/// The files can be inspected using e.g. `perf report -i qpe-perf-data/name=sort.perf.data`.
+///
+/// Unlike [`Attribution`](crate::Attribution), this leaves resolving symbols to `perf`, which needs access to the same binaries and libraries.
+/// Only the thread starting the measurement is sampled, and only user space code is included.
+/// Samples are buffered until the measurement is stopped, if the buffer fills up, further samples are lost and `perf report` includes a warning.
+/// Linux only.
+pub struct PerfData {
+    attr: PerfEventAttr,
+    sampler: Sampler,
+    dir: PathBuf,
+    /// The thread that opened the sampler.
+    tid: u32,
+    /// Records since the last record.
+    pending: Vec<u8>,
+    /// Records by label.
+    labels: Vec<(String, Vec<u8>)>,
+}
+
+impl PerfData {
+    /// Creates a recording from the environment variable `QPE_PERF_DATA`.
+    ///
+    /// The variable contains the event to sample, see [`new`](Self::new).
+    /// Files are written to the directory `QPE_PERF_DATA_DIR`, which defaults to `qpe-perf-data`.
+    /// The sampling rate can be set using `QPE_PERF_DATA_PERIOD` or `QPE_PERF_DATA_FREQ`, see [`with_period`](Self::with_period) and [`with_frequency`](Self::with_frequency).
+    /// Returns `None` if `QPE_PERF_DATA` is not set or the event cannot be sampled.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let event = config.get("PERF_DATA")?;
+        let dir = config.get("PERF_DATA_DIR");
+        let mut perf_data = Self::new(&event, dir.as_deref().unwrap_or("qpe-perf-data"))?;
+        if let Some(period) = config.parse("PERF_DATA_PERIOD") {
+            perf_data = perf_data.with_period(period);
+        }
+        if let Some(frequency) = config.parse("PERF_DATA_FREQ") {
+            perf_data = perf_data.with_frequency(frequency);
+        }
+        Some(perf_data)
+    }
+
+    /// Creates a recording of an event, written to files in `dir`.
+    ///
+    /// Supported events are `br-miss`, `llc-miss`, `l1-miss`, `cycle`, and `t-clock`, as defined for [`PerfBackend`](crate::counters::PerfBackend).
+    /// Returns `None` with a warning message to stderr if the event cannot be sampled.
+    pub fn new(event: &str, dir: impl AsRef<Path>) -> Option<Self> {
+        let Some((kind, config, period)) = sampled_event(event) else {
+            eprintln!("invalid perf.data event: {event:?}");
+            return None;
+        };
+        let attr = PerfEventAttr {
+            kind,
+            config,
+            sample_period: period,
+            sample_type: PERF_SAMPLE_IP | PERF_SAMPLE_TID | PERF_SAMPLE_PERIOD,
+            flags: FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+        let sampler = Sampler::new(attr.clone(), 8)
+            .map_err(|e| eprintln!("failed to sample {event:?}: {e}"))
+            .ok()?;
+        Some(PerfData {
+            attr,
+            sampler,
+            dir: dir.as_ref().to_path_buf(),
+            tid: unsafe { libc::gettid() } as u32,
+            pending: Vec::new(),
+            labels: Vec::new(),
+        })
+    }
+
+    /// Set the number of events per sample.
+    ///
+    /// The default is 10000 for cache and branch misses, 1000000 for cycles, and 1ms for `t-clock`.
+    pub fn with_period(mut self, period: u64) -> Self {
+        self.sampler.set_period(period);
+        self.attr.sample_period = period;
+        self.attr.flags &= !FLAG_FREQ;
+        self
+    }
+
+    /// Take `frequency` samples per second, adjusting the period dynamically.
+    ///
+    /// If the event cannot be sampled by frequency, a warning is printed to stderr and the period is kept.
+    pub fn with_frequency(mut self, frequency: u64) -> Self {
+        let mut attr = self.attr.clone();
+        attr.sample_period = frequency;
+        attr.flags |= FLAG_FREQ;
+        match Sampler::new(attr.clone(), 8) {
+            Ok(sampler) => {
+                self.sampler = sampler;
+                self.attr = attr;
+            }
+            Err(e) => eprintln!("failed to sample at {frequency} Hz: {e}"),
+        }
+        self
+    }
+
+    pub(crate) fn enable(&mut self) {
+        ioctl(self.sampler.file(), PERF_EVENT_IOC_ENABLE);
+    }
+
+    pub(crate) fn disable(&mut self) {
+        ioctl(self.sampler.file(), PERF_EVENT_IOC_DISABLE);
+        self.sampler.drain_records(&mut self.pending);
+    }
+
+    /// Assigns the records since the last record to a label.
+    pub(crate) fn record(&mut self, labels: String) {
+        match self.labels.iter_mut().find(|x| x.0 == labels) {
+            Some((_, records)) => records.append(&mut self.pending),
+            None => self
+                .labels
+                .push((labels, std::mem::take(&mut self.pending))),
+        }
+    }
+
+    /// Writes a file per label and discards all samples.
+    pub(crate) fn dump_and_reset(&mut self) {
+        if self.labels.is_empty() {
+            return;
+        }
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            eprintln!("failed to create {}: {e}", self.dir.display());
+            return;
+        }
+        let header = self.process_records();
+        for (labels, records) in self.labels.drain(..) {
+            let name = if labels.is_empty() {
+                "all".to_string()
+            } else {
+                file_name(&labels)
+            };
+            let path = self.dir.join(format!("{name}.perf.data"));
+            if let Err(e) = write_file(&path, &self.attr, &header, &records) {
+                eprintln!("failed to write {}: {e}", path.display());
+            }
+        }
+        eprintln!("perf.data files written to {}", self.dir.display());
+    }
+
+    /// Returns records describing the command name and the executable mappings of the process.
+    fn process_records(&self) -> Vec<u8> {
+        let pid = std::process::id();
+        let mut out = Vec::new();
+        let comm = fs::read_to_string("/proc/self/comm").unwrap_or_default();
+        let mut tids = vec![pid];
+        if self.tid != pid {
+            tids.push(self.tid);
+        }
+        for tid in tids {
+            let mut body = Vec::new();
+            body.extend_from_slice(&pid.to_ne_bytes());
+            body.extend_from_slice(&tid.to_ne_bytes());
+            push_string(&mut body, comm.trim_end());
+            push_record(&mut out, PERF_RECORD_COMM, 0, &body);
+        }
+        let maps = fs::read_to_string("/proc/self/maps").unwrap_or_default();
+        for line in maps.lines() {
+            let Some((start, end, offset, path)) = parse_mapping(line) else {
+                continue;
+            };
+            let mut body = Vec::new();
+            body.extend_from_slice(&pid.to_ne_bytes());
+            body.extend_from_slice(&pid.to_ne_bytes());
+            body.extend_from_slice(&start.to_ne_bytes());
+            body.extend_from_slice(&(end - start).to_ne_bytes());
+            body.extend_from_slice(&offset.to_ne_bytes());
+            push_string(&mut body, path);
+            push_record(&mut out, PERF_RECORD_MMAP, PERF_RECORD_MISC_USER, &body);
+        }
+        out
+    }
+}
+
+const PERF_RECORD_MISC_USER: u16 = 2;
+
+/// Parses the start, end, offset, and path of an executable file mapping in `/proc/self/maps`.
+fn parse_mapping(line: &str) -> Option<(u64, u64, u64, &str)> {
+    let mut fields = line.split_whitespace();
+    let (start, end) = fields.next()?.split_once('-')?;
+    let permissions = fields.next()?;
+    let offset = fields.next()?;
+    let path = fields.nth(2)?;
+    if !permissions.contains('x') || !path.starts_with('/') {
+        return None;
+    }
+    Some((
+        u64::from_str_radix(start, 16).ok()?,
+        u64::from_str_radix(end, 16).ok()?,
+        u64::from_str_radix(offset, 16).ok()?,
+        path,
+    ))
+}
+
+/// Converts labels to a file name.
+pub(crate) fn file_name(labels: &str) -> String {
+    labels
+        .chars()
+        .filter(|x| *x != ' ')
+        .map(|x| {
+            if x.is_alphanumeric() || "=-_.,".contains(x) {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Appends a null terminated string, padded to a multiple of 8 bytes.
+fn push_string(dst: &mut Vec<u8>, s: &str) {
+    dst.extend_from_slice(s.as_bytes());
+    dst.push(0);
+    while !dst.len().is_multiple_of(8) {
+        dst.push(0);
+    }
+}
+
+fn push_record(dst: &mut Vec<u8>, kind: u32, misc: u16, body: &[u8]) {
+    dst.extend_from_slice(&kind.to_ne_bytes());
+    dst.extend_from_slice(&misc.to_ne_bytes());
+    dst.extend_from_slice(&(8 + body.len() as u16).to_ne_bytes());
+    dst.extend_from_slice(body);
+}
+
+/// Writes a `perf.data` file containing a single event.
+///
+/// See `tools/perf/Documentation/perf.data-file-format.txt` in the Linux source tree.
+fn write_file(path: &Path, attr: &PerfEventAttr, header: &[u8], records: &[u8]) -> io::Result<()> {
+    const HEADER_SIZE: u64 = 104;
+    let attr_size = size_of::<PerfEventAttr>() as u64;
+    // the attribute is followed by the section of its sample ids, which is empty
+    let file_attr_size = attr_size + 16;
+    let data_offset = HEADER_SIZE + file_attr_size;
+    let data_size = (header.len() + records.len()) as u64;
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    out.write_all(b"PERFILE2")?;
+    for x in [
+        HEADER_SIZE,
+        file_attr_size,
+        HEADER_SIZE,
+        file_attr_size,
+        data_offset,
+        data_size,
+        // event types
+        0,
+        0,
+        // feature bitmap
+        0,
+        0,
+        0,
+        0,
+    ] {
+        out.write_all(&x.to_ne_bytes())?;
+    }
+    let mut attr = attr.clone();
+    attr.size = attr_size as u32;
+    let attr_bytes = unsafe {
+        std::slice::from_raw_parts(
+            &attr as *const PerfEventAttr as *const u8,
+            attr_size as usize,
+        )
+    };
+    out.write_all(attr_bytes)?;
+    out.write_all(&[0; 16])?;
+    out.write_all(header)?;
+    out.write_all(records)?;
+    out.flush()
+}
+
+#[test]
+fn test_parse_mapping() {
+    assert_eq!(
+        parse_mapping("7f0000001000-7f0000003000 r-xp 00002000 08:01 1234    /usr/lib/libc.so.6"),
+        Some((0x7f0000001000, 0x7f0000003000, 0x2000, "/usr/lib/libc.so.6"))
+    );
+    assert_eq!(
+        parse_mapping("7f0000001000-7f0000003000 rw-p 00000000 00:00 0"),
+        None
+    );
+    assert_eq!(
+        parse_mapping("7ffd00000000-7ffd00001000 r-xp 00000000 00:00 0    [vdso]"),
+        None
+    );
+}
@@ -0,0 +1,400 @@
+//! Raw `perf_event_open` bindings for event types not covered by the `perf_event` crate.
+
+use std::{
+    fs::File,
+    io,
+    os::fd::{AsRawFd, FromRawFd},
+};
+
+pub(crate) const PERF_TYPE_HARDWARE: u32 = 0;
+pub(crate) const PERF_TYPE_SOFTWARE: u32 = 1;
+pub(crate) const PERF_TYPE_HW_CACHE: u32 = 3;
+
+pub(crate) const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+pub(crate) const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+pub(crate) const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+const PERF_EVENT_IOC_PERIOD: libc::c_ulong = 0x40082404;
+#[cfg(feature = "perf")]
+const PERF_FLAG_PID_CGROUP: libc::c_ulong = 1 << 2;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+pub(crate) const FLAG_DISABLED: u64 = 1 << 0;
+pub(crate) const FLAG_INHERIT: u64 = 1 << 1;
+#[cfg(feature = "perf")]
+pub(crate) const FLAG_PINNED: u64 = 1 << 2;
+#[cfg(feature = "perf")]
+pub(crate) const FLAG_EXCLUDE_USER: u64 = 1 << 4;
+pub(crate) const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+pub(crate) const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+pub(crate) const FLAG_FREQ: u64 = 1 << 10;
+
+pub(crate) const PERF_SAMPLE_IP: u64 = 1 << 0;
+pub(crate) const PERF_SAMPLE_TID: u64 = 1 << 1;
+pub(crate) const PERF_SAMPLE_CALLCHAIN: u64 = 1 << 5;
+pub(crate) const PERF_SAMPLE_PERIOD: u64 = 1 << 8;
+
+#[cfg(feature = "perf")]
+pub(crate) const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+#[cfg(feature = "perf")]
+pub(crate) const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+/// `struct perf_event_attr`, up to `PERF_ATTR_SIZE_VER5`.
+#[repr(C)]
+#[derive(Clone, Default)]
+pub(crate) struct PerfEventAttr {
+    pub kind: u32,
+    pub size: u32,
+    pub config: u64,
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    pub flags: u64,
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+    pub config2: u64,
+    pub branch_sample_type: u64,
+    pub sample_regs_user: u64,
+    pub sample_stack_user: u32,
+    pub clockid: i32,
+    pub sample_regs_intr: u64,
+    pub aux_watermark: u32,
+    pub sample_max_stack: u16,
+    pub reserved: u16,
+}
+
+/// Opens an event measuring the calling process on any CPU.
+pub(crate) fn perf_event_open(attr: PerfEventAttr) -> io::Result<File> {
+    open(attr, 0, -1, 0)
+}
+
+#[cfg(feature = "perf")]
+/// Opens an event measuring all processes in the cgroup of the directory `cgroup` on one CPU.
+pub(crate) fn perf_event_open_cgroup(
+    attr: PerfEventAttr,
+    cgroup: &File,
+    cpu: usize,
+) -> io::Result<File> {
+    open(
+        attr,
+        cgroup.as_raw_fd(),
+        cpu as libc::c_int,
+        PERF_FLAG_PID_CGROUP,
+    )
+}
+
+fn open(
+    mut attr: PerfEventAttr,
+    pid: libc::pid_t,
+    cpu: libc::c_int,
+    flags: libc::c_ulong,
+) -> io::Result<File> {
+    attr.size = size_of::<PerfEventAttr>() as u32;
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            pid,
+            cpu,
+            -1 as libc::c_int,
+            flags | PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+pub(crate) fn ioctl(file: &File, request: libc::c_ulong) {
+    unsafe {
+        libc::ioctl(file.as_raw_fd(), request as _, 0);
+    }
+}
+
+pub(crate) const PERF_RECORD_MMAP: u32 = 1;
+const PERF_RECORD_LOST: u32 = 2;
+pub(crate) const PERF_RECORD_COMM: u32 = 3;
+pub(crate) const PERF_RECORD_SAMPLE: u32 = 9;
+
+/// An event sampling the instruction pointer into a ring buffer.
+pub(crate) struct Sampler {
+    file: File,
+    /// The metadata page followed by the data pages.
+    mmap: *mut u8,
+    mmap_len: usize,
+}
+
+impl Sampler {
+    /// Opens a sampling event.
+    ///
+    /// `PERF_SAMPLE_IP` is added to `attr.sample_type`, so the address is the first field of each sample.
+    /// The ring buffer holds `2^pages_log2` pages of samples.
+    pub fn new(mut attr: PerfEventAttr, pages_log2: u32) -> io::Result<Self> {
+        attr.sample_type |= PERF_SAMPLE_IP;
+        let file = perf_event_open(attr)?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mmap_len = page_size * (1 + (1 << pages_log2));
+        let mmap = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mmap_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if mmap == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Sampler {
+            file,
+            mmap: mmap as *mut u8,
+            mmap_len,
+        })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn set_period(&mut self, period: u64) {
+        unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                PERF_EVENT_IOC_PERIOD as _,
+                &period as *const u64,
+            );
+        }
+    }
+
+    /// Appends the sampled addresses to `dst` and removes them from the ring buffer.
+    ///
+    /// Returns the number of samples lost due to the ring buffer being full.
+    pub fn drain(&mut self, dst: &mut Vec<u64>) -> u64 {
+        let mut lost = 0;
+        self.consume(|kind, record| match kind {
+            PERF_RECORD_SAMPLE => dst.push(u64::from_ne_bytes(record[8..16].try_into().unwrap())),
+            PERF_RECORD_LOST => lost += u64::from_ne_bytes(record[16..24].try_into().unwrap()),
+            _ => {}
+        });
+        lost
+    }
+
+    /// Appends all records to `dst` in the format of the ring buffer and removes them from the ring buffer.
+    ///
+    /// Returns the number of samples lost due to the ring buffer being full.
+    pub fn drain_records(&mut self, dst: &mut Vec<u8>) -> u64 {
+        let mut lost = 0;
+        self.consume(|kind, record| {
+            if kind == PERF_RECORD_LOST {
+                lost += u64::from_ne_bytes(record[16..24].try_into().unwrap());
+            }
+            dst.extend_from_slice(record);
+        });
+        lost
+    }
+
+    /// Calls `f` with the type and bytes of each record and removes them from the ring buffer.
+    fn consume(&mut self, mut f: impl FnMut(u32, &[u8])) {
+        // offsets of data_head, data_tail, data_offset, and data_size in struct perf_event_mmap_page
+        let field = |offset: usize| unsafe { self.mmap.add(offset) as *mut u64 };
+        let head = unsafe { field(1024).read_volatile() };
+        std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire);
+        let mut tail = unsafe { field(1032).read_volatile() };
+        let data = unsafe { self.mmap.add(field(1040).read_volatile() as usize) };
+        let size = unsafe { field(1048).read_volatile() };
+        let read = |pos: u64, dst: &mut [u8]| {
+            for (i, x) in dst.iter_mut().enumerate() {
+                *x = unsafe { data.add(((pos + i as u64) % size) as usize).read_volatile() };
+            }
+        };
+        let mut record = Vec::new();
+        while tail < head {
+            let mut header = [0u8; 8];
+            read(tail, &mut header);
+            let kind = u32::from_ne_bytes(header[0..4].try_into().unwrap());
+            let len = u16::from_ne_bytes(header[6..8].try_into().unwrap()).max(8);
+            record.resize(len as usize, 0);
+            read(tail, &mut record);
+            f(kind, &record);
+            tail += len as u64;
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::Release);
+        unsafe { field(1032).write_volatile(head) };
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.mmap as *mut libc::c_void, self.mmap_len);
+        }
+    }
+}
+
+/// A counting event read from user space using `rdpmc`, without a system call.
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct UserCounter {
+    _file: File,
+    page: UserPage,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl UserCounter {
+    /// Opens an enabled event measuring the calling thread.
+    ///
+    /// Fails if the kernel does not allow reading the counter using `rdpmc`.
+    pub fn new(mut attr: PerfEventAttr) -> io::Result<Self> {
+        attr.flags &= !(FLAG_DISABLED | FLAG_INHERIT);
+        let file = perf_event_open(attr)?;
+        let page = UserPage::new(&file)?;
+        Ok(UserCounter { _file: file, page })
+    }
+
+    /// Returns the current count.
+    #[inline(always)]
+    pub fn read(&self) -> u64 {
+        self.page.count()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+const CAP_USER_RDPMC: u64 = 1 << 2;
+#[cfg(all(target_arch = "x86_64", feature = "perf"))]
+const CAP_USER_TIME: u64 = 1 << 3;
+
+/// The metadata page of an event, allowing it to be read from user space using `rdpmc`.
+///
+/// Reading is only valid on the thread measured by the event, so this is neither `Send` nor `Sync`.
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct UserPage {
+    page: *mut u8,
+    page_len: usize,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl UserPage {
+    /// Maps the metadata page of an event.
+    ///
+    /// Fails if the kernel does not allow reading the counter using `rdpmc`.
+    pub fn new(file: &impl AsRawFd) -> io::Result<Self> {
+        let page_len = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let page = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                page_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if page == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let page = UserPage {
+            page: page as *mut u8,
+            page_len,
+        };
+        if page.capabilities() & CAP_USER_RDPMC == 0 {
+            return Err(io::Error::other("rdpmc is not available"));
+        }
+        Ok(page)
+    }
+
+    #[inline(always)]
+    fn field<T>(&self, offset: usize) -> T {
+        unsafe { (self.page.add(offset) as *const T).read_volatile() }
+    }
+
+    fn capabilities(&self) -> u64 {
+        self.field(40)
+    }
+
+    /// Reads the hardware counter with the given index, adding it to `count`.
+    #[inline(always)]
+    fn add_pmc(&self, index: u32, count: &mut i64) {
+        if index == 0 {
+            return;
+        }
+        let width = self.field::<u16>(48) as u32;
+        let (low, high): (u32, u32);
+        unsafe {
+            std::arch::asm!(
+                "rdpmc",
+                in("ecx") index - 1,
+                out("eax") low,
+                out("edx") high,
+                options(nostack, preserves_flags),
+            );
+        }
+        let shift = 64 - width;
+        *count += (((high as u64) << 32 | low as u64) << shift) as i64 >> shift;
+    }
+
+    /// Returns the current count.
+    #[inline(always)]
+    pub fn count(&self) -> u64 {
+        loop {
+            let seq = self.field::<u32>(8);
+            std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+            let index = self.field::<u32>(12);
+            let mut count = self.field::<i64>(16);
+            self.add_pmc(index, &mut count);
+            std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+            if self.field::<u32>(8) == seq {
+                return count as u64;
+            }
+        }
+    }
+
+    /// Returns the count, time enabled, and time running in nanoseconds of an enabled event.
+    ///
+    /// Returns `None` if the kernel currently does not allow reading the counter or time from user space.
+    #[cfg(feature = "perf")]
+    pub fn snapshot(&self) -> Option<[u64; 3]> {
+        loop {
+            let seq = self.field::<u32>(8);
+            std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+            let capabilities = self.capabilities();
+            if capabilities & (CAP_USER_RDPMC | CAP_USER_TIME) != CAP_USER_RDPMC | CAP_USER_TIME {
+                return None;
+            }
+            let mut enabled = self.field::<u64>(24);
+            let mut running = self.field::<u64>(32);
+            let index = self.field::<u32>(12);
+            let mut count = self.field::<i64>(16);
+            // The times are updated when the event is scheduled, extrapolate them using the time stamp counter.
+            let cycles = unsafe { std::arch::x86_64::_rdtsc() };
+            let shift = self.field::<u16>(50) as u32;
+            let mult = self.field::<u32>(52) as u64;
+            let offset = self.field::<u64>(56);
+            self.add_pmc(index, &mut count);
+            std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+            if self.field::<u32>(8) != seq {
+                continue;
+            }
+            let quot = cycles >> shift;
+            let rem = cycles & ((1 << shift) - 1);
+            let delta = offset
+                .wrapping_add(quot.wrapping_mul(mult))
+                .wrapping_add(rem.wrapping_mul(mult) >> shift);
+            enabled = enabled.wrapping_add(delta);
+            if index != 0 {
+                running = running.wrapping_add(delta);
+            }
+            return Some([count as u64, enabled, running]);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Drop for UserPage {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.page as *mut libc::c_void, self.page_len);
+        }
+    }
+}
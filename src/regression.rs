@@ -0,0 +1,336 @@
+use crate::{
+    Report,
+    config::Config,
+    counters::{CounterMeta, CounterReading, Counters},
+    labels::LabelMeta,
+};
+use std::{collections::HashMap, error::Error, fmt, path::Path};
+
+/// Counter values by counter name, by row key.
+type Baseline = HashMap<Vec<(String, String)>, HashMap<String, f64>>;
+
+/// Selects the labels identifying a row when matching readings against a baseline.
+///
+/// By default, all labels form the key, and rows only match if they have the same label names and values.
+/// Ignoring labels like `date` or `host` allows matching baselines recorded on other machines or with additional metadata labels.
+/// Labels are matched by name, so their order does not matter.
+#[derive(Clone, Debug, Default)]
+pub struct RowKey {
+    fields: Option<Vec<String>>,
+    ignored: Vec<String>,
+}
+
+impl RowKey {
+    /// Creates a key consisting of all labels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a key from the environment variables `QPE_BASELINE_KEY` and `QPE_BASELINE_IGNORE`.
+    ///
+    /// Both contain comma separated lists of label names, see [`with_fields`](Self::with_fields) and [`with_ignored`](Self::with_ignored).
+    pub fn from_env() -> Self {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Self {
+        let mut key = Self::new();
+        if let Some(fields) = config.get("BASELINE_KEY") {
+            key = key.with_fields(fields.split(',').map(str::trim));
+        }
+        if let Some(ignored) = config.get("BASELINE_IGNORE") {
+            key = key.with_ignored(ignored.split(',').map(str::trim));
+        }
+        key
+    }
+
+    /// Only use the given labels as the key.
+    pub fn with_fields<'a>(mut self, fields: impl IntoIterator<Item = &'a str>) -> Self {
+        self.fields = Some(fields.into_iter().map(String::from).collect());
+        self
+    }
+
+    /// Exclude the given labels from the key.
+    pub fn with_ignored<'a>(mut self, ignored: impl IntoIterator<Item = &'a str>) -> Self {
+        self.ignored = ignored.into_iter().map(String::from).collect();
+        self
+    }
+
+    /// Returns the names and values of the labels forming the key, sorted by name.
+    pub(crate) fn key(&self, label_meta: &[LabelMeta], labels: &[String]) -> Vec<(String, String)> {
+        let mut key: Vec<(String, String)> = label_meta
+            .iter()
+            .map(|x| x.name())
+            .zip(labels)
+            .filter(|(name, _)| {
+                self.fields
+                    .as_ref()
+                    .is_none_or(|x| x.iter().any(|x| x == name))
+                    && !self.ignored.iter().any(|x| x == name)
+            })
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        key.sort();
+        key
+    }
+}
+
+/// Compares measurements against a baseline and fails if counters regress beyond a threshold.
+///
+/// The baseline is a CSV file written by a previous run using `QPE_FORMAT=csv`.
+/// Each recorded reading is compared against the baseline row with identical labels, or identical key labels if a [`RowKey`] is [set](Self::with_key).
+/// If the baseline contains multiple such rows, the last one is used.
+/// Readings without a matching baseline row are not checked.
+///
+/// A counter regresses if it changes in the unfavorable [direction](crate::counters::Direction) by more than its threshold, given as a percentage of the baseline value.
+/// Regressions are printed to stderr as they are detected.
+/// They are reported as an error by [`finish`](crate::QuickPerfEvent::finish), leaving the exit status to the caller.
+/// If the [`QuickPerfEvent`](crate::QuickPerfEvent) is dropped instead, the number of regressions is only printed.
+pub struct RegressionGate {
+    report: Report,
+    key: RowKey,
+    baseline: Baseline,
+    thresholds: Vec<(String, f64)>,
+    regressions: usize,
+    reading_buffer: Vec<CounterReading>,
+}
+
+/// The error returned by [`finish`](crate::QuickPerfEvent::finish) if counters regressed.
+#[derive(Debug)]
+pub struct RegressionError {
+    pub regressions: usize,
+}
+
+impl fmt::Display for RegressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} counters regressed", self.regressions)
+    }
+}
+
+impl Error for RegressionError {}
+
+impl RegressionGate {
+    /// Creates a gate from the environment variables `QPE_BASELINE` and `QPE_FAIL_ON_REGRESSION`.
+    ///
+    /// Rows are matched using a [`RowKey`] configured from `QPE_BASELINE_KEY` and `QPE_BASELINE_IGNORE`.
+    ///
+    /// `QPE_BASELINE` contains the path of the baseline CSV file.
+    /// `QPE_FAIL_ON_REGRESSION` contains a comma separated list of thresholds of the form `name:value%`, e.g. `cycle:5%,instr:2%`.
+    /// The name `*` applies a threshold to all counters without a threshold of their own.
+    /// Returns `None` if either variable is not set or the baseline cannot be read.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let thresholds = config.get("FAIL_ON_REGRESSION")?;
+        let Some(baseline) = config.get("BASELINE") else {
+            eprintln!(
+                "{} requires a baseline, set {}",
+                config.name("FAIL_ON_REGRESSION"),
+                config.name("BASELINE")
+            );
+            return None;
+        };
+        let thresholds: Vec<(&str, f64)> = thresholds
+            .split(",")
+            .filter_map(|threshold| {
+                let parsed = parse_threshold(threshold);
+                if parsed.is_none() {
+                    eprintln!("invalid regression threshold {threshold:?}: expected name:value%");
+                }
+                parsed
+            })
+            .collect();
+        Self::new(baseline, thresholds)
+            .map_err(|e| eprintln!("failed to read baseline: {e}"))
+            .ok()
+            .map(|x| x.with_key(RowKey::from_config(config)))
+    }
+
+    /// Creates a gate from a baseline CSV file and a list of counter names and thresholds in percent.
+    ///
+    /// Reading the baseline requires the `csv` feature.
+    pub fn new<'a>(
+        baseline: impl AsRef<Path>,
+        thresholds: impl IntoIterator<Item = (&'a str, f64)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let report = read_baseline(baseline.as_ref())?;
+        let key = RowKey::new();
+        Ok(RegressionGate {
+            baseline: index_baseline(&report, &key),
+            report,
+            key,
+            thresholds: thresholds
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            regressions: 0,
+            reading_buffer: Vec::new(),
+        })
+    }
+
+    /// Set the labels identifying a row, by default all labels are used.
+    pub fn with_key(mut self, key: RowKey) -> Self {
+        self.baseline = index_baseline(&self.report, &key);
+        self.key = key;
+        self
+    }
+
+    fn threshold(&self, name: &str) -> Option<f64> {
+        let find = |name: &str| self.thresholds.iter().find(|x| x.0 == name);
+        find(name).or_else(|| find("*")).map(|x| x.1)
+    }
+
+    /// Checks a reading against the baseline, printing any regressions.
+    pub(crate) fn check(
+        &mut self,
+        scale: usize,
+        counters: &mut dyn Counters,
+        label_meta: &[LabelMeta],
+        labels: Vec<String>,
+    ) {
+        let Some(baseline) = self.baseline.get(&self.key.key(label_meta, &labels)) else {
+            return;
+        };
+        let mut meta = Vec::new();
+        counters.meta(&mut |x| meta.push(x));
+        let mut names = Vec::new();
+        counters.names(&mut |x| names.push(x.to_string()));
+        self.reading_buffer.clear();
+        counters.read(&mut self.reading_buffer);
+        for ((name, meta), reading) in names.iter().zip(meta).zip(&self.reading_buffer) {
+            let (Some(threshold), Some(&old)) = (self.threshold(name), baseline.get(name)) else {
+                continue;
+            };
+            let new = reading.scaled_value(scale);
+            if is_regression(meta, old, new, threshold) {
+                self.regressions += 1;
+                let change = (new - old) / old.abs() * 100.0;
+                eprintln!(
+                    "regression in {labels:?}: {name} changed from {old} to {new} ({change:+.1}%, threshold {threshold}%)"
+                );
+            }
+        }
+    }
+
+    pub(crate) fn result(&self) -> Result<(), RegressionError> {
+        if self.regressions > 0 {
+            Err(RegressionError {
+                regressions: self.regressions,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "csv")]
+fn read_baseline(path: &Path) -> Result<Report, Box<dyn Error>> {
+    Report::from_csv(path)
+}
+
+#[cfg(not(feature = "csv"))]
+fn read_baseline(_path: &Path) -> Result<Report, Box<dyn Error>> {
+    Err("reading a baseline requires the csv feature".into())
+}
+
+/// Collects counter values by counter name, by row key.
+fn index_baseline(report: &Report, key: &RowKey) -> Baseline {
+    let mut baseline = HashMap::new();
+    for row in &report.rows {
+        let values = (report.counter_names.iter())
+            .zip(&row.readings)
+            .filter(|x| x.1.valid)
+            .map(|(name, reading)| (name.clone(), reading.value))
+            .collect();
+        baseline.insert(key.key(report.label_meta, &row.labels), values);
+    }
+    baseline
+}
+
+pub(crate) fn is_regression(meta: CounterMeta, old: f64, new: f64, threshold: f64) -> bool {
+    significant_change(meta, old, new, threshold) == Some(false)
+}
+
+/// Returns whether `new` is an improvement over `old`, or `None` if it differs by at most `threshold` percent.
+pub(crate) fn significant_change(
+    meta: CounterMeta,
+    old: f64,
+    new: f64,
+    threshold: f64,
+) -> Option<bool> {
+    let improvement = meta.direction().is_improvement(old, new)?;
+    ((new - old).abs() > old.abs() * threshold / 100.0).then_some(improvement)
+}
+
+/// Parses `name:value%`, splitting on the last `:` since counter names may contain modifiers, e.g. `cycle:k:5%`.
+fn parse_threshold(threshold: &str) -> Option<(&str, f64)> {
+    let (name, value) = threshold.rsplit_once(":")?;
+    let value = value.trim().strip_suffix("%").unwrap_or(value.trim());
+    Some((name.trim(), value.parse().ok()?))
+}
+
+#[test]
+fn test_parse_threshold() {
+    assert_eq!(parse_threshold("cycle:5%"), Some(("cycle", 5.0)));
+    assert_eq!(parse_threshold(" cycle:k : 2.5"), Some(("cycle:k", 2.5)));
+    assert_eq!(parse_threshold("cycle"), None);
+    assert_eq!(parse_threshold("cycle:k"), None);
+}
+
+#[test]
+fn test_is_regression() {
+    use crate::counters::Direction;
+    let lower = CounterMeta::new();
+    let higher = CounterMeta::new().with_direction(Direction::HigherIsBetter);
+    assert!(is_regression(lower, 100.0, 106.0, 5.0));
+    assert!(!is_regression(lower, 100.0, 104.0, 5.0));
+    assert!(!is_regression(lower, 100.0, 90.0, 5.0));
+    assert!(is_regression(higher, 2.0, 1.8, 5.0));
+    assert!(!is_regression(
+        CounterMeta::new().with_direction(Direction::Neutral),
+        1.0,
+        2.0,
+        5.0
+    ));
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_regression_result() {
+    use crate::{
+        QuickPerfEvent,
+        counters::{CounterReading, Replay},
+        formats::Csv,
+        report::Report,
+    };
+    let path = std::env::temp_dir().join(format!("qpe-baseline-{}.csv", std::process::id()));
+    let counters = |value| {
+        Replay::new(
+            vec!["x".into()],
+            vec![CounterMeta::new()],
+            vec![CounterReading::new(value)],
+        )
+    };
+    let csv = Csv::new().with_writer(std::fs::File::create(&path).unwrap());
+    let mut perf = QuickPerfEvent::<str, _, _>::new(counters(1.0), csv);
+    perf.run(|| ()).record(1, "a");
+    drop(perf);
+    let measure = |value| {
+        let gate = RegressionGate::new(&path, [("x", 5.0)]).unwrap();
+        let mut perf = QuickPerfEvent::<str, _, _>::new(counters(value), Report::default())
+            .with_regression_gate(Some(gate));
+        perf.run(|| ()).record(1, "a");
+        perf
+    };
+    // Dropping only prints the regression, the caller decides whether to fail.
+    drop(measure(2.0));
+    assert!(measure(1.0).finish().is_ok());
+    let result = measure(2.0).finish();
+    std::fs::remove_file(&path).unwrap();
+    let Err(crate::FinishError::Regression(error)) = result else {
+        panic!("expected a regression, got {result:?}");
+    };
+    assert_eq!(error.regressions, 1);
+}
@@ -0,0 +1,379 @@
+use crate::{
+    counters::{CounterMeta, CounterReading, Counters, Replay},
+    formats::Format,
+    labels::LabelMeta,
+};
+use std::{error::Error, time::SystemTime};
+#[cfg(feature = "csv")]
+use std::{path::Path, time::Duration};
+
+/// A single recorded measurement.
+#[derive(Clone, Debug)]
+pub struct Row {
+    /// The label values, in the order of [`Report::label_meta`].
+    pub labels: Vec<String>,
+    /// The scale passed to [`record`](crate::Reading::record).
+    pub scale: usize,
+    /// The time the measurement was started.
+    pub start_time: SystemTime,
+    /// The unnormalized counter readings, in the order of [`Report::counter_names`].
+    pub readings: Vec<CounterReading>,
+}
+
+impl Row {
+    /// Returns the value of the `i`th counter, divided by the scale if the counter is normalized.
+    pub fn value(&self, i: usize) -> f64 {
+        self.readings[i].scaled_value(self.scale)
+    }
+}
+
+/// A collection of measurements sharing the same labels and counters.
+///
+/// A report can be collected by using it as the [`Format`] of a [`QuickPerfEvent`](crate::QuickPerfEvent) and retrieving it using [`format_mut`](crate::QuickPerfEvent::format_mut).
+/// Rows can be constructed, filtered, and transformed programmatically, and then be written to any other format using [`write`](Self::write).
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub label_meta: &'static [LabelMeta],
+    pub counter_names: Vec<String>,
+    pub counter_meta: Vec<CounterMeta>,
+    /// The unit of the scale of all rows, see [`Counters::scale_unit`].
+    pub scale_unit: Option<String>,
+    pub rows: Vec<Row>,
+}
+
+impl Report {
+    /// Creates an empty report for the given labels and counters.
+    pub fn new(label_meta: &'static [LabelMeta], counters: &dyn Counters) -> Self {
+        let mut report = Report {
+            label_meta,
+            ..Default::default()
+        };
+        report.set_counters(counters);
+        report
+    }
+
+    fn set_counters(&mut self, counters: &dyn Counters) {
+        self.counter_names.clear();
+        counters.names(&mut |x| self.counter_names.push(x.to_string()));
+        self.counter_meta.clear();
+        counters.meta(&mut |x| self.counter_meta.push(x));
+        self.scale_unit = counters.scale_unit().map(str::to_string);
+    }
+
+    /// Reads a report from a CSV file written using `QPE_FORMAT=csv`.
+    ///
+    /// CSV files contain normalized values, so readings are not [scaled](CounterReading::enable_scale) again.
+    /// Values that cannot be parsed are read as NaN and marked as not [valid](CounterReading::valid).
+    /// Counter metadata is not stored in CSV files, all counters get the default [`CounterMeta`].
+    /// The label metadata is leaked to obtain a `'static` lifetime.
+    /// Lines starting with `#`, such as [metadata](crate::Metadata), are skipped.
+    /// Requires the `csv` feature.
+    #[cfg(feature = "csv")]
+    pub fn from_csv(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_path(path)?;
+        let header = reader.headers()?.clone();
+        let num_labels = header
+            .iter()
+            .position(|x| x == "start_time")
+            .ok_or("missing start_time column")?;
+        // labels, start_time, scale, counters..., multiplexed, running fraction of each counter...
+        let counters_end = (header.iter().enumerate().skip(num_labels + 2))
+            .find(|x| x.1 == "multiplexed")
+            .map_or(header.len().saturating_sub(1), |x| x.0);
+        let counters = num_labels + 2..counters_end;
+        let label_meta: Vec<LabelMeta> = (header.iter().take(num_labels))
+            .map(|x| LabelMeta::new(String::leak(x.to_string())))
+            .collect();
+        let mut report = Report {
+            label_meta: label_meta.leak(),
+            counter_names: header
+                .iter()
+                .skip(counters.start)
+                .take(counters.len())
+                .map(String::from)
+                .collect(),
+            counter_meta: vec![CounterMeta::new(); counters.len()],
+            scale_unit: (header.get(num_labels + 1))
+                .and_then(|x| x.strip_prefix("scale (")?.strip_suffix(')'))
+                .map(String::from),
+            rows: Vec::new(),
+        };
+        // files written before per-counter running fractions were added lack these columns
+        let running_columns: Vec<Option<usize>> = (report.counter_names.iter())
+            .map(|name| header.iter().position(|x| x == format!("{name}/running")))
+            .collect();
+        for record in reader.records() {
+            let record = record?;
+            let multiplexed = record.get(counters.end) == Some("true");
+            report.rows.push(Row {
+                labels: record.iter().take(num_labels).map(String::from).collect(),
+                scale: record[num_labels + 1].parse()?,
+                start_time: (SystemTime::UNIX_EPOCH)
+                    .checked_add(Duration::try_from_secs_f64(record[num_labels].parse()?)?)
+                    .ok_or("start_time out of range")?,
+                readings: counters
+                    .clone()
+                    .map(|i| {
+                        let value = record[i].parse().ok();
+                        let running = running_columns[i - counters.start]
+                            .and_then(|j| record.get(j)?.parse().ok());
+                        CounterReading {
+                            value: value.unwrap_or(f64::NAN),
+                            multiplexed: running.map_or(multiplexed, |x| x < 1.0),
+                            running: running.unwrap_or(1.0),
+                            enable_scale: false,
+                            valid: value.is_some(),
+                        }
+                    })
+                    .collect(),
+            });
+        }
+        Ok(report)
+    }
+
+    /// Returns the index of the counter named `name`.
+    pub fn counter_index(&self, name: &str) -> Option<usize> {
+        self.counter_names.iter().position(|x| x == name)
+    }
+
+    /// Writes all rows to a format and finishes its report.
+    pub fn write(&self, format: &mut dyn Format) -> Result<(), Box<dyn Error>> {
+        self.push_rows(format)?;
+        format.dump_and_reset(self.label_meta, &mut self.counters(Vec::new()))
+    }
+
+    /// Writes all rows to a format without finishing its report.
+    pub(crate) fn push_rows(&self, format: &mut dyn Format) -> Result<(), Box<dyn Error>> {
+        for row in &self.rows {
+            format.push(
+                row.scale,
+                row.start_time,
+                &mut self.counters(row.readings.clone()),
+                &mut |dst| row.labels.iter().for_each(|x| dst(x)),
+                self.label_meta,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Presents readings as counters of this report.
+    pub(crate) fn counters(&self, readings: Vec<CounterReading>) -> Replay {
+        Replay::new(
+            self.counter_names.clone(),
+            self.counter_meta.clone(),
+            readings,
+        )
+        .with_scale_unit(self.scale_unit.clone())
+    }
+}
+
+/// Collects readings as rows.
+///
+/// The counter names and metadata are taken from the first reading.
+/// Finishing the report has no effect, rows are kept until they are removed.
+impl Format for Report {
+    fn push(
+        &mut self,
+        scale: usize,
+        start_time: SystemTime,
+        counters: &mut dyn Counters,
+        labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        if self.rows.is_empty() {
+            self.label_meta = label_meta;
+            self.set_counters(counters);
+        }
+        let mut row = Row {
+            labels: Vec::new(),
+            scale,
+            start_time,
+            readings: Vec::new(),
+        };
+        labels(&mut |x| row.labels.push(x.to_string()));
+        counters.read(&mut row.readings);
+        self.rows.push(row);
+        Ok(())
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        _label_meta: &'static [LabelMeta],
+        _counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_collect_and_write() {
+    use crate::{QuickPerfEvent, counters::TimeBackend};
+    let mut perf = QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), Report::default());
+    perf.run(|| ()).record(2, "a");
+    perf.run(|| ()).record(1, "b");
+    let mut report = std::mem::take(perf.format_mut());
+    assert_eq!(report.counter_names, ["time"]);
+    assert_eq!(report.rows.len(), 2);
+    report.rows.retain(|x| x.labels == ["b"]);
+    let mut copy = Report::default();
+    report.write(&mut copy).unwrap();
+    assert_eq!(copy.label_meta[0].name(), "label");
+    assert_eq!(copy.rows.len(), 1);
+    assert_eq!(copy.rows[0].scale, 1);
+}
+
+#[test]
+fn test_on_record() {
+    use crate::{QuickPerfEvent, counters::TimeBackend};
+    use std::{cell::RefCell, rc::Rc};
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut perf = QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), Report::default());
+    perf.on_record({
+        let seen = seen.clone();
+        move |row, names| seen.borrow_mut().push((row.labels.clone(), names.to_vec()))
+    });
+    perf.run(|| ()).record(1, "a");
+    assert_eq!(
+        *seen.borrow(),
+        [(vec!["a".to_string()], vec!["time".to_string()])]
+    );
+    assert_eq!(perf.format_mut().rows.len(), 1);
+}
+
+#[test]
+fn test_record_f64() {
+    use crate::QuickPerfEvent;
+    let reading = CounterReading {
+        value: 10.0,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let counters = Replay::new(vec!["x".into()], vec![CounterMeta::new()], vec![reading]);
+    let mut perf = QuickPerfEvent::<str, _, _>::new(counters, Report::default());
+    perf.run(|| ()).record_f64(2.5, "a");
+    perf.run(|| ()).record_f64(1e30, "b");
+    perf.run(|| ()).record_f64(f64::NAN, "c");
+    perf.run(|| ()).record_f64(-2.0, "d");
+    let report = perf.format_mut();
+    assert_eq!(report.rows[0].scale, 3);
+    assert_eq!(report.rows[0].value(0), 4.0);
+    assert_eq!(report.rows[1].scale, usize::MAX);
+    assert!((report.rows[1].value(0) - 1e-29).abs() < 1e-35);
+    for row in &report.rows[2..] {
+        assert_eq!(row.scale, 1);
+        assert!(!row.readings[0].valid);
+    }
+}
+
+#[test]
+fn test_run_for_scale_overflow() {
+    use crate::{QuickPerfEvent, counters::TimeBackend};
+    let mut perf = QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), Report::default());
+    perf.run_for(std::time::Duration::from_millis(1), || ())
+        .record(usize::MAX / 2, "a");
+    assert_eq!(perf.format_mut().rows[0].scale, usize::MAX);
+}
+
+#[test]
+fn test_checkpoint_derived() {
+    use crate::{QuickPerfEvent, counters::Derived};
+    /// Counters reporting the next of a fixed sequence of cumulative values on each read.
+    struct Steps(Vec<[f64; 2]>);
+    impl Counters for Steps {
+        fn enable(&mut self) {}
+        fn disable(&mut self) {}
+        fn reset(&mut self) {}
+        fn read(&mut self, dst: &mut Vec<CounterReading>) {
+            for value in self.0.remove(0) {
+                dst.push(CounterReading {
+                    value,
+                    multiplexed: false,
+                    running: 1.0,
+                    enable_scale: true,
+                    valid: true,
+                });
+            }
+        }
+        fn names(&self, dst: &mut dyn FnMut(&str)) {
+            dst("instr");
+            dst("cycle");
+        }
+    }
+    let counters = Derived::with_names(Steps(vec![[10.0, 10.0], [40.0, 20.0]]), ["ipc"]);
+    let mut perf = QuickPerfEvent::<str, _, _>::new(counters, Report::default());
+    let mut running = perf.start();
+    running.checkpoint(1, "a");
+    running.checkpoint(1, "b");
+    drop(running);
+    let report = perf.format_mut();
+    assert_eq!(report.counter_names, ["instr", "cycle", "ipc"]);
+    assert_eq!(report.rows[0].value(2), 1.0);
+    assert_eq!(report.rows[1].value(1), 10.0);
+    assert_eq!(report.rows[1].value(2), 3.0);
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_running() {
+    use crate::{QuickPerfEvent, formats::Csv};
+    let path = std::env::temp_dir().join(format!("qpe-running-{}.csv", std::process::id()));
+    let reading = |value, running| CounterReading {
+        value,
+        multiplexed: running < 1.0,
+        running,
+        enable_scale: true,
+        valid: true,
+    };
+    let counters = Replay::new(
+        vec!["x".into(), "y".into()],
+        vec![CounterMeta::new(); 2],
+        vec![reading(1.0, 1.0), reading(2.0, 0.5)],
+    );
+    let csv = Csv::new().with_writer(std::fs::File::create(&path).unwrap());
+    let mut perf = QuickPerfEvent::<str, _, _>::new(counters, csv);
+    perf.run(|| ()).record(1, "a");
+    drop(perf);
+    let report = Report::from_csv(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(report.counter_names, ["x", "y"]);
+    let readings = &report.rows[0].readings;
+    assert_eq!(
+        readings.iter().map(|x| x.running).collect::<Vec<_>>(),
+        [1.0, 0.5]
+    );
+    assert!(!readings[0].multiplexed && readings[1].multiplexed);
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_hash_label() {
+    use crate::{QuickPerfEvent, counters::TimeBackend, formats::Csv};
+    let path = std::env::temp_dir().join(format!("qpe-hash-{}.csv", std::process::id()));
+    let csv = Csv::new().with_writer(std::fs::File::create(&path).unwrap());
+    let mut perf = QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), csv);
+    perf.run(|| ()).record(1, "#1");
+    drop(perf);
+    let report = Report::from_csv(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(report.rows.len(), 1);
+    assert_eq!(report.rows[0].labels, ["#1"]);
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_invalid_start_time() {
+    let path = std::env::temp_dir().join(format!("qpe-start-{}.csv", std::process::id()));
+    for start_time in ["-1", "NaN", "inf", "1e19"] {
+        std::fs::write(
+            &path,
+            format!("label,start_time,scale,x\na,{start_time},1,2\n"),
+        )
+        .unwrap();
+        assert!(Report::from_csv(&path).is_err());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,222 @@
+//! Measuring from many threads concurrently.
+//!
+//! A [`SharedQuickPerfEvent`] can be shared between threads, e.g. using [`std::thread::scope`] or an [`Arc`](std::sync::Arc).
+//! Each thread measures using its own set of counters, while all readings are written to a single format.
+//! ```
+//! use quick_perf_event::shared::SharedQuickPerfEvent;
+//!
+//! let perf = SharedQuickPerfEvent::<str>::from_env();
+//! std::thread::scope(|s| {
+//!     for _ in 0..4 {
+//!         s.spawn(|| {
+//!             perf.run(1000, "sum", || (0..1000).map(std::hint::black_box).sum::<u64>());
+//!         });
+//!     }
+//! });
+//! ```
+
+use crate::{
+    Config, Labels, QuickPerfEvent, Report,
+    counters::{Counters, counters_from_config},
+    formats::{Format, format_from_config},
+    labels::LabelMeta,
+};
+use std::{
+    any::Any,
+    borrow::Borrow,
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    marker::PhantomData,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+/// The per-thread instance of a [`SharedQuickPerfEvent`].
+pub type ThreadQuickPerfEvent<L> = QuickPerfEvent<L, Box<dyn Counters>, Forward>;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The per-thread instances of all shared instances used on this thread, by id.
+    static INSTANCES: RefCell<HashMap<u64, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// A [`QuickPerfEvent`] that can be used from many threads concurrently.
+///
+/// Each thread lazily creates its own counters when it first takes a measurement.
+/// Since counters are bound to the thread that created them, they only count events of that thread and the threads it spawns afterwards.
+/// Readings are passed to a dedicated output thread writing them to the format, so rows of different threads never interleave.
+/// The report is finished when the `SharedQuickPerfEvent` is dropped.
+///
+/// Per-thread counters are kept until their thread exits.
+/// Regression gates and attribution are not supported.
+pub struct SharedQuickPerfEvent<L: ?Sized + Labels + 'static> {
+    id: u64,
+    config: Config,
+    /// Sends readings to the output thread, `None` finishes the report.
+    sender: Sender<Option<Report>>,
+    output: Option<JoinHandle<()>>,
+    _p: PhantomData<fn(&L)>,
+}
+
+impl<L: ?Sized + Labels + 'static> SharedQuickPerfEvent<L> {
+    /// Create a `SharedQuickPerfEvent` configured from environment variables, see [`from_env`](crate::from_env).
+    pub fn from_env() -> Self {
+        Self::from_config(&Config::default())
+    }
+
+    /// Create a `SharedQuickPerfEvent` configured from a [`Config`], see [`QuickPerfEvent::from_config`].
+    pub fn from_config(config: &Config) -> Self {
+        let (sender, receiver) = channel::<Option<Report>>();
+        let label_meta = L::meta();
+        let format_config = config.clone();
+        let output = thread::spawn(move || {
+            let mut format = format_from_config(&format_config);
+            let mut error_printed = false;
+            let mut last = Report::default();
+            while let Ok(Some(report)) = receiver.recv() {
+                if let Err(e) = report.push_rows(&mut format)
+                    && !error_printed
+                {
+                    error_printed = true;
+                    eprintln!("error recording result: {e}");
+                }
+                last = report;
+            }
+            if let Err(e) = format.dump_and_reset(label_meta, &mut last.counters(Vec::new()))
+                && !error_printed
+            {
+                eprintln!("error finnishing report: {e}");
+            }
+        });
+        SharedQuickPerfEvent {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            config: config.clone(),
+            sender,
+            output: Some(output),
+            _p: PhantomData,
+        }
+    }
+
+    /// Calls `f` with the instance of the current thread, creating it if necessary.
+    ///
+    /// This gives access to the full measurement API, e.g. [`run_repeated`](QuickPerfEvent::run_repeated).
+    pub fn with<R>(&self, f: impl FnOnce(&mut ThreadQuickPerfEvent<L>) -> R) -> R {
+        let mut instance = INSTANCES
+            .with(|x| x.borrow_mut().remove(&self.id))
+            .unwrap_or_else(|| Box::new(self.thread_instance()));
+        let ret = f(instance.downcast_mut().unwrap());
+        INSTANCES.with(|x| x.borrow_mut().insert(self.id, instance));
+        ret
+    }
+
+    /// Measure the execution of a function and record it with a label and scale.
+    ///
+    /// This is a shorthand for [`run`](QuickPerfEvent::run) and [`record`](crate::Reading::record) on the instance of the current thread.
+    pub fn run<R>(&self, scale: usize, labels: impl Borrow<L>, f: impl FnOnce() -> R) -> R {
+        self.with(|perf| perf.run(f).record(scale, labels))
+    }
+
+    fn thread_instance(&self) -> ThreadQuickPerfEvent<L> {
+        let format = Forward {
+            sender: self.sender.clone(),
+            report: Report::default(),
+        };
+        QuickPerfEvent::new(counters_from_config(&self.config), format)
+            .with_measurement_config(&self.config)
+    }
+}
+
+impl<L: ?Sized + Labels + 'static> Drop for SharedQuickPerfEvent<L> {
+    fn drop(&mut self) {
+        INSTANCES.with(|x| x.borrow_mut().remove(&self.id));
+        let _ = self.sender.send(None);
+        let _ = self.output.take().unwrap().join();
+    }
+}
+
+/// The format of per-thread instances, passing readings on to the output thread of a [`SharedQuickPerfEvent`].
+pub struct Forward {
+    sender: Sender<Option<Report>>,
+    report: Report,
+}
+
+impl Format for Forward {
+    fn push(
+        &mut self,
+        scale: usize,
+        start_time: SystemTime,
+        counters: &mut dyn Counters,
+        labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        self.report
+            .push(scale, start_time, counters, labels, label_meta)?;
+        // The receiver is gone once the shared instance is dropped, later readings are discarded.
+        let _ = self.sender.send(Some(mem::take(&mut self.report)));
+        Ok(())
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        _label_meta: &'static [LabelMeta],
+        _counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_shared() {
+    use crate::counters::CounterReading;
+    use std::sync::atomic::AtomicUsize;
+    static ROWS: AtomicUsize = AtomicUsize::new(0);
+    struct Count;
+    impl Format for Count {
+        fn push(
+            &mut self,
+            _scale: usize,
+            _start_time: SystemTime,
+            counters: &mut dyn Counters,
+            _labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+            _label_meta: &'static [LabelMeta],
+        ) -> Result<(), Box<dyn Error>> {
+            let mut readings: Vec<CounterReading> = Vec::new();
+            counters.read(&mut readings);
+            assert!(!readings.is_empty());
+            ROWS.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn dump_and_reset(
+            &mut self,
+            _label_meta: &'static [LabelMeta],
+            _counters: &mut dyn Counters,
+        ) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+    }
+    crate::formats::register("test-shared", |_| Box::new(Count));
+    let perf = SharedQuickPerfEvent::<str>::from_config(
+        &Config::empty()
+            .with_value("FORMAT", "test-shared")
+            .with_value("EVENTS", "t-clock"),
+    );
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10 {
+                    perf.run(1, "x", || ());
+                }
+            });
+        }
+    });
+    drop(perf);
+    assert_eq!(ROWS.load(Ordering::Relaxed), 40);
+}
@@ -0,0 +1,228 @@
+//! Measuring regions of a few nanoseconds inside a loop.
+//!
+//! Starting and stopping counters for each measurement takes several system calls, far more than the region being measured.
+//! A [`ShortMeasurement`] instead keeps a single counter enabled and reads it from user space using `rdpmc` before and after each region.
+//! The differences are accumulated into a histogram, which is recorded as a single row.
+//! Linux on x86-64 only.
+//!
+//! To report the distribution of per-iteration time alongside the counters of a whole run, use [`run_ticked`](QuickPerfEvent::run_ticked) instead.
+//! ```no_run
+//! use quick_perf_event::{measure_short, short::ShortMeasurement};
+//!
+//! let mut perf = quick_perf_event::from_env::<str>();
+//! let mut short = ShortMeasurement::new("cycle").unwrap();
+//! let mut sum = 0u64;
+//! for i in 0..1_000_000 {
+//!     measure_short!(short, { sum = sum.wrapping_mul(31).wrapping_add(i) });
+//! }
+//! short.record(&mut perf, "hash");
+//! ```
+
+use crate::{
+    Labels, QuickPerfEvent, Reading,
+    counters::{CounterMeta, CounterReading, Counters, Direction, Replay},
+    formats::Format,
+    histogram::Histogram,
+    perf_sys::*,
+};
+use std::{arch::x86_64::_rdtsc, borrow::Borrow, hint::black_box, time::SystemTime};
+
+/// Accumulates counts of short regions into a histogram, see the [module documentation](self).
+///
+/// The counter is read using `rdpmc`, which only counts the thread that created it, so a `ShortMeasurement` cannot be sent to other threads.
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<quick_perf_event::short::ShortMeasurement>();
+/// ```
+pub struct ShortMeasurement {
+    name: String,
+    counter: UserCounter,
+    /// The count of an empty region, subtracted from each measurement.
+    overhead: u64,
+    histogram: Histogram,
+    start_time: SystemTime,
+}
+
+impl ShortMeasurement {
+    /// Opens the counter `name` for the calling thread.
+    ///
+    /// Supported names are `cycle`, `instr`, `llc-miss`, and `br-miss`, see [`PerfBackend`](crate::counters::PerfBackend).
+    /// Only events in user space are counted.
+    /// Returns `None` with a warning message to stderr if the counter cannot be opened or read using `rdpmc`.
+    pub fn new(name: &str) -> Option<Self> {
+        let config = match name {
+            "cycle" => 0,
+            "instr" => 1,
+            "llc-miss" => 3,
+            "br-miss" => 5,
+            _ => {
+                eprintln!("invalid counter name for short measurement: {name:?}");
+                return None;
+            }
+        };
+        let attr = PerfEventAttr {
+            kind: PERF_TYPE_HARDWARE,
+            config,
+            flags: FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+        let counter = UserCounter::new(attr)
+            .map_err(|e| eprintln!("failed to create counter {name:?}: {e}"))
+            .ok()?;
+        let mut this = ShortMeasurement {
+            name: name.to_string(),
+            counter,
+            overhead: 0,
+            histogram: Histogram::default(),
+            start_time: SystemTime::now(),
+        };
+        this.overhead = (0..1000)
+            .map(|_| {
+                let start = this.begin();
+                black_box(());
+                this.counter.read().saturating_sub(start)
+            })
+            .min()
+            .unwrap();
+        Some(this)
+    }
+
+    /// Reads the counter at the start of a region, see [`measure_short!`](crate::measure_short).
+    #[inline(always)]
+    pub fn begin(&self) -> u64 {
+        self.counter.read()
+    }
+
+    /// Reads the counter at the end of a region and adds the difference to `start` to the histogram.
+    #[inline(always)]
+    pub fn end(&mut self, start: u64) {
+        let count = self.counter.read().saturating_sub(start);
+        self.histogram.record(count.saturating_sub(self.overhead));
+    }
+
+    /// Measures a function as a single region.
+    #[inline(always)]
+    pub fn measure<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        let start = self.begin();
+        let ret = f();
+        self.end(start);
+        ret
+    }
+
+    /// Records the accumulated regions as one row and clears the histogram.
+    ///
+    /// For a counter `x`, this reports the mean count per region as `x`, along with `x-min`, `x-max`, and the [percentiles](QuickPerfEvent::with_percentiles) of `perf`, e.g. `x-p50`.
+    /// The number of regions is reported in a column `regions`.
+    /// The overhead of reading the counter, estimated as the minimum count of an empty region, is subtracted from each region.
+    pub fn record<L: Labels + ?Sized, C: Counters, F: Format>(
+        &mut self,
+        perf: &mut QuickPerfEvent<L, C, F>,
+        labels: impl Borrow<L>,
+    ) {
+        let histogram = std::mem::take(&mut self.histogram);
+        let reading = |value| CounterReading {
+            value,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: true,
+            valid: true,
+        };
+        let mut names = vec![
+            self.name.clone(),
+            format!("{}-min", self.name),
+            format!("{}-max", self.name),
+        ];
+        let mut readings = vec![
+            reading(histogram.mean()),
+            reading(histogram.min() as f64),
+            reading(histogram.max() as f64),
+        ];
+        for p in &perf.percentiles {
+            names.push(format!("{}-p{p}", self.name));
+            readings.push(reading(histogram.percentile(*p)));
+        }
+        let mut meta = vec![CounterMeta::new(); names.len()];
+        names.push("regions".into());
+        meta.push(CounterMeta::new().with_direction(Direction::Neutral));
+        readings.push(CounterReading {
+            value: histogram.len() as f64,
+            multiplexed: false,
+            running: 1.0,
+            enable_scale: false,
+            valid: true,
+        });
+        let reading = Reading {
+            pe: perf,
+            start_time: std::mem::replace(&mut self.start_time, SystemTime::now()),
+            ret: (),
+            replay: Some(Replay::new(names, meta, readings)),
+            scale_factor: 1,
+            scale_unit: None,
+            bases: Vec::new(),
+        };
+        reading.record(1, labels);
+    }
+}
+
+/// Collects the time between calls to [`tick`](Self::tick), see [`QuickPerfEvent::run_ticked`].
+pub struct Ticker {
+    last: u64,
+    histogram: Histogram,
+}
+
+impl Ticker {
+    pub(crate) fn new() -> Self {
+        Ticker {
+            last: unsafe { _rdtsc() },
+            histogram: Histogram::default(),
+        }
+    }
+
+    /// Marks the end of an iteration, adding the time stamp counter ticks since the previous call or the start of the run to the histogram.
+    #[inline(always)]
+    pub fn tick(&mut self) {
+        let now = unsafe { _rdtsc() };
+        self.histogram.record(now.saturating_sub(self.last));
+        self.last = now;
+    }
+
+    /// Appends the columns `tick-max` and e.g. `tick-p50` for each percentile.
+    pub(crate) fn summary(
+        &self,
+        percentiles: &[f64],
+        names: &mut Vec<String>,
+        meta: &mut Vec<CounterMeta>,
+        readings: &mut Vec<CounterReading>,
+    ) {
+        let values = percentiles
+            .iter()
+            .map(|p| (format!("tick-p{p}"), self.histogram.percentile(*p)))
+            .chain([("tick-max".to_string(), self.histogram.max() as f64)]);
+        for (name, value) in values {
+            names.push(name);
+            meta.push(CounterMeta::new());
+            readings.push(CounterReading {
+                value,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: true,
+            });
+        }
+    }
+}
+
+/// Measures a block as a region of a [`ShortMeasurement`], evaluating to the value of the block.
+///
+/// Unlike [`ShortMeasurement::measure`], the block is not moved into a closure, so `return` and `?` work as usual.
+/// Regions left early this way are not added to the histogram.
+#[macro_export]
+macro_rules! measure_short {
+    ($measurement:expr, $body:block) => {{
+        let measurement: &mut $crate::short::ShortMeasurement = &mut $measurement;
+        let start = measurement.begin();
+        let ret = $body;
+        measurement.end(start);
+        ret
+    }};
+}
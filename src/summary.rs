@@ -0,0 +1,182 @@
+use crate::RegressionError;
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// The reason a measurement was not recorded, see [`QuickPerfEvent::skip`](crate::QuickPerfEvent::skip).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skip {
+    /// The measurement was excluded by a filter, e.g. a parameter combination not applicable to a benchmark.
+    Filtered,
+    /// The measurement was taken, but the [`Reading`](crate::Reading) was dropped without being recorded, e.g. due to a panic in a [`scope`](crate::QuickPerfEvent::scope).
+    Discarded,
+    /// The measurement was aborted after exceeding a time limit.
+    TimedOut,
+    /// The measurement failed, e.g. because the output format returned an error.
+    Failed,
+}
+
+const SKIPS: [Skip; 4] = [
+    Skip::Filtered,
+    Skip::Discarded,
+    Skip::TimedOut,
+    Skip::Failed,
+];
+
+impl Skip {
+    fn name(self) -> &'static str {
+        match self {
+            Skip::Filtered => "filtered",
+            Skip::Discarded => "discarded",
+            Skip::TimedOut => "timed out",
+            Skip::Failed => "failed",
+        }
+    }
+}
+
+/// The number of recorded measurements of a session and the measurements that were not recorded.
+///
+/// When a [`QuickPerfEvent`](crate::QuickPerfEvent) is dropped, a one line summary is printed to stderr if any measurement was skipped.
+/// This makes it obvious when a parameter sweep silently skipped a large part of its configurations.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub recorded: usize,
+    /// The reason and labels of each measurement that was not recorded.
+    pub skipped: Vec<(Skip, String)>,
+}
+
+impl Summary {
+    /// Returns the number of measurements skipped for a reason.
+    pub fn count(&self, reason: Skip) -> usize {
+        self.skipped.iter().filter(|x| x.0 == reason).count()
+    }
+
+    /// Writes the number of measurements per outcome as CSV with the columns `outcome` and `count`.
+    ///
+    /// Outcomes are `recorded`, `filtered`, `discarded`, `timed-out`, and `failed`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "outcome,count")?;
+        writeln!(out, "recorded,{}", self.recorded)?;
+        for reason in SKIPS {
+            writeln!(
+                out,
+                "{},{}",
+                reason.name().replace(' ', "-"),
+                self.count(reason)
+            )?;
+        }
+        out.flush()
+    }
+}
+
+/// The error returned by [`finish`](crate::QuickPerfEvent::finish) if counters were multiplexed in strict mode.
+///
+/// See [`with_strict_multiplexing`](crate::QuickPerfEvent::with_strict_multiplexing).
+#[derive(Debug)]
+pub struct MultiplexingError {
+    /// The labels of each affected reading.
+    pub labels: Vec<String>,
+}
+
+impl fmt::Display for MultiplexingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} readings were multiplexed:", self.labels.len())?;
+        for labels in &self.labels {
+            write!(f, "\n  {labels}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for MultiplexingError {}
+
+/// The error returned by [`finish`](crate::QuickPerfEvent::finish).
+#[derive(Debug)]
+pub enum FinishError {
+    /// Counters were multiplexed in strict mode.
+    Multiplexed(MultiplexingError),
+    /// The [`RegressionGate`](crate::RegressionGate) detected regressions.
+    Regression(RegressionError),
+}
+
+impl fmt::Display for FinishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinishError::Multiplexed(e) => e.fmt(f),
+            FinishError::Regression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for FinishError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FinishError::Multiplexed(e) => Some(e),
+            FinishError::Regression(e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.recorded + self.skipped.len();
+        write!(f, "recorded {} of {total} measurements", self.recorded)?;
+        let mut separator = ": ";
+        for reason in SKIPS {
+            let count = self.count(reason);
+            if count > 0 {
+                write!(f, "{separator}{count} {}", reason.name())?;
+                separator = ", ";
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_display() {
+    let summary = Summary {
+        recorded: 7,
+        skipped: vec![
+            (Skip::TimedOut, "n=3".into()),
+            (Skip::Filtered, "n=1".into()),
+            (Skip::Filtered, "n=2".into()),
+        ],
+    };
+    assert_eq!(
+        summary.to_string(),
+        "recorded 7 of 10 measurements: 2 filtered, 1 timed out"
+    );
+}
+
+#[test]
+fn test_strict_multiplexing() {
+    use crate::{
+        QuickPerfEvent, Report,
+        counters::{CounterMeta, CounterReading, Replay},
+    };
+    let reading = |multiplexed| CounterReading {
+        value: 1.0,
+        multiplexed,
+        running: if multiplexed { 0.5 } else { 1.0 },
+        enable_scale: true,
+        valid: true,
+    };
+    let counters = Replay::new(
+        vec!["a".into(), "b".into()],
+        vec![CounterMeta::new(); 2],
+        vec![reading(false), reading(true)],
+    );
+    let mut perf = QuickPerfEvent::<str, _, _>::new(counters, Report::default())
+        .with_strict_multiplexing(true);
+    perf.run(|| ()).record(1, "x");
+    let Err(FinishError::Multiplexed(error)) = perf.finish() else {
+        panic!("expected a multiplexing error");
+    };
+    assert_eq!(error.labels, ["label=x"]);
+}
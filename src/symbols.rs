@@ -0,0 +1,262 @@
+//! Symbolization of addresses in the running executable.
+//!
+//! This is shared by all features reporting code locations.
+//! Function names are read from the ELF symbol table.
+//! With the `symbolize` feature, Rust symbol names are demangled, and source files, line numbers, and inlined functions are resolved from DWARF debug info.
+
+use std::{collections::HashMap, error::Error, fs, io, path::Path};
+
+/// A function symbol from an ELF symbol table.
+pub(crate) struct Symbol {
+    /// The name, demangled without the trailing hash of legacy Rust symbols if the `symbolize` feature is enabled.
+    pub name: String,
+    /// The virtual address of the function.
+    pub address: u64,
+    pub size: u64,
+    /// The offset of the function within the file.
+    pub file_offset: u64,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct Elf<'a>(&'a [u8]);
+
+impl Elf<'_> {
+    fn bytes<const N: usize>(&self, offset: u64) -> io::Result<[u8; N]> {
+        let offset = usize::try_from(offset).map_err(|_| invalid("offset out of range"))?;
+        self.0
+            .get(offset..offset + N)
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(|| invalid("unexpected end of file"))
+    }
+
+    fn u16(&self, offset: u64) -> io::Result<u64> {
+        Ok(u16::from_le_bytes(self.bytes(offset)?).into())
+    }
+
+    fn u32(&self, offset: u64) -> io::Result<u64> {
+        Ok(u32::from_le_bytes(self.bytes(offset)?).into())
+    }
+
+    fn u64(&self, offset: u64) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(offset)?))
+    }
+
+    fn str(&self, offset: u64) -> io::Result<&str> {
+        let start = usize::try_from(offset).map_err(|_| invalid("offset out of range"))?;
+        let rest = self.0.get(start..).ok_or_else(|| invalid("bad string"))?;
+        let len = rest
+            .iter()
+            .position(|x| *x == 0)
+            .ok_or_else(|| invalid("bad string"))?;
+        std::str::from_utf8(&rest[..len]).map_err(|_| invalid("bad string"))
+    }
+}
+
+const SHT_SYMTAB: u64 = 2;
+const PT_LOAD: u64 = 1;
+const STT_FUNC: u8 = 2;
+
+/// A loadable segment of an ELF file.
+struct Segment {
+    vaddr: u64,
+    offset: u64,
+    size: u64,
+}
+
+/// Checks the ELF header and returns the loadable segments.
+fn segments(elf: &Elf) -> io::Result<Vec<Segment>> {
+    if elf.bytes::<6>(0)? != *b"\x7fELF\x02\x01" {
+        return Err(invalid("not a 64-bit little-endian ELF file"));
+    }
+    let (phoff, phentsize, phnum) = (elf.u64(0x20)?, elf.u16(0x36)?, elf.u16(0x38)?);
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if elf.u32(ph)? == PT_LOAD {
+            segments.push(Segment {
+                vaddr: elf.u64(ph + 0x10)?,
+                offset: elf.u64(ph + 0x08)?,
+                size: elf.u64(ph + 0x20)?,
+            });
+        }
+    }
+    Ok(segments)
+}
+
+/// Reads all defined function symbols from the symbol table of a 64-bit little-endian ELF file.
+pub(crate) fn read_symbols(path: &Path) -> io::Result<Vec<Symbol>> {
+    let data = fs::read(path)?;
+    let elf = Elf(&data);
+    symbols(&elf, &segments(&elf)?)
+}
+
+fn symbols(elf: &Elf, segments: &[Segment]) -> io::Result<Vec<Symbol>> {
+    let (shoff, shentsize, shnum) = (elf.u64(0x28)?, elf.u16(0x3a)?, elf.u16(0x3c)?);
+    let mut symbols = Vec::new();
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        if elf.u32(sh + 0x04)? != SHT_SYMTAB {
+            continue;
+        }
+        let (offset, size, entsize) = (
+            elf.u64(sh + 0x18)?,
+            elf.u64(sh + 0x20)?,
+            elf.u64(sh + 0x38)?,
+        );
+        let strtab = shoff + elf.u32(sh + 0x28)? * shentsize;
+        let strtab_offset = elf.u64(strtab + 0x18)?;
+        for sym in (offset..offset + size).step_by(entsize.max(1) as usize) {
+            let info = elf.bytes::<1>(sym + 0x04)?[0];
+            let address = elf.u64(sym + 0x08)?;
+            if info & 0xf != STT_FUNC || address == 0 {
+                continue;
+            }
+            let Some(segment) = segments
+                .iter()
+                .find(|x| (x.vaddr..x.vaddr + x.size).contains(&address))
+            else {
+                continue;
+            };
+            let name = elf.str(strtab_offset + elf.u32(sym)?)?;
+            symbols.push(Symbol {
+                name: demangle(name),
+                address,
+                size: elf.u64(sym + 0x10)?,
+                file_offset: address - segment.vaddr + segment.offset,
+            });
+        }
+    }
+    Ok(symbols)
+}
+
+/// Demangles a Rust symbol name, omitting the hash. Other names are returned unchanged.
+#[cfg(feature = "symbolize")]
+fn demangle(name: &str) -> String {
+    format!("{:#}", rustc_demangle::demangle(name))
+}
+
+#[cfg(not(feature = "symbolize"))]
+fn demangle(name: &str) -> String {
+    name.to_string()
+}
+
+/// A source location.
+#[derive(Clone)]
+pub(crate) struct Location {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// Resolves addresses in the running executable to functions and source locations.
+///
+/// Results are cached, so repeatedly resolving the same address is cheap.
+/// Addresses in shared libraries are not resolved.
+pub(crate) struct Symbolizer {
+    #[cfg(feature = "symbolize")]
+    loader: addr2line::Loader,
+    /// Function symbols, sorted by address.
+    symbols: Vec<Symbol>,
+    /// Memory mappings of the executable as `(start, end, file offset)`.
+    mappings: Vec<(u64, u64, u64)>,
+    segments: Vec<Segment>,
+    cache: HashMap<u64, Option<Location>>,
+}
+
+impl Symbolizer {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let exe = std::env::current_exe()?;
+        let data = fs::read(&exe)?;
+        let elf = Elf(&data);
+        let segments = segments(&elf)?;
+        let mut symbols = symbols(&elf, &segments)?;
+        symbols.sort_by_key(|x| x.address);
+        let mut mappings = Vec::new();
+        for line in fs::read_to_string("/proc/self/maps")?.lines() {
+            let mut fields = line.split_ascii_whitespace();
+            let (Some(range), Some(offset), Some(path)) =
+                (fields.next(), fields.nth(1), fields.nth(2))
+            else {
+                continue;
+            };
+            if Path::new(path) != exe {
+                continue;
+            }
+            let parse = |x| u64::from_str_radix(x, 16).map_err(|_| invalid("bad memory map"));
+            let (start, end) = range
+                .split_once('-')
+                .ok_or_else(|| invalid("bad memory map"))?;
+            mappings.push((parse(start)?, parse(end)?, parse(offset)?));
+        }
+        Ok(Symbolizer {
+            #[cfg(feature = "symbolize")]
+            loader: addr2line::Loader::new(&exe)?,
+            symbols,
+            mappings,
+            segments,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Translates a runtime address to the corresponding virtual address in the executable file.
+    fn file_address(&self, address: u64) -> Option<u64> {
+        let &(start, _, offset) = self
+            .mappings
+            .iter()
+            .find(|(start, end, _)| (*start..*end).contains(&address))?;
+        let offset = address - start + offset;
+        let segment = self
+            .segments
+            .iter()
+            .find(|x| (x.offset..x.offset + x.size).contains(&offset))?;
+        Some(offset - segment.offset + segment.vaddr)
+    }
+
+    /// Looks up the innermost function containing an address, accounting for inlining if debug info is available.
+    ///
+    /// Returns `None` if the address is not in the executable.
+    pub fn locate(&mut self, address: u64) -> Option<&Location> {
+        if !self.cache.contains_key(&address) {
+            let location = self.file_address(address).map(|x| self.locate_uncached(x));
+            self.cache.insert(address, location);
+        }
+        self.cache[&address].as_ref()
+    }
+
+    fn locate_uncached(&self, address: u64) -> Location {
+        let mut location = Location {
+            function: None,
+            file: None,
+            line: None,
+        };
+        #[cfg(feature = "symbolize")]
+        if let Some(frame) = self
+            .loader
+            .find_frames(address)
+            .ok()
+            .and_then(|mut frames| frames.next().ok().flatten())
+        {
+            location.function = frame
+                .function
+                .as_ref()
+                .and_then(|x| x.raw_name().ok())
+                .map(|x| demangle(&x));
+            if let Some(x) = frame.location {
+                location.file = x.file.map(str::to_string);
+                location.line = x.line;
+            }
+        }
+        if location.function.is_none() {
+            let i = self.symbols.partition_point(|x| x.address <= address);
+            location.function = i
+                .checked_sub(1)
+                .map(|i| &self.symbols[i])
+                .filter(|x| address < x.address + x.size.max(1))
+                .map(|x| x.name.clone());
+        }
+        location
+    }
+}
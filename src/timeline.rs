@@ -0,0 +1,170 @@
+use crate::{
+    config::Config,
+    counters::{CounterReading, Counters, PerfBackend},
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Samples counters at a fine interval during each measurement, writing a timeline per recorded reading to a CSV file.
+///
+/// This allows inspecting phase behavior within a single measurement, e.g. a warm-up phase with many cache misses.
+/// While a measurement is running, a background thread reads the counters every 10ms by default, and once more when the measurement is paused or stopped.
+/// When the [`Reading`](crate::Reading) is recorded, its samples are written as lines with the columns `row`, `labels`, `time`, and one column per counter.
+/// `row` is the index of the recorded reading, `time` is the time in seconds since the start of the measurement, and counter values are cumulative since the start and not normalized by scale.
+/// If a reading consists of several measurements, e.g. with [`run_repeated`](crate::QuickPerfEvent::run_repeated), the samples of each measurement are included.
+///
+/// Timeline counters are opened separately from the counters being reported.
+/// To avoid multiplexing, create both using [`PerfBackend::with_shared_counter_names`], e.g. by setting `QPE_SHARE_COUNTERS`.
+pub struct Timeline {
+    state: Arc<Mutex<State>>,
+    out: BufWriter<File>,
+    path: PathBuf,
+    row: usize,
+    error_printed: bool,
+}
+
+struct State {
+    perf: PerfBackend,
+    interval: Duration,
+    start: Instant,
+    enabled: bool,
+    /// Time and counter values sampled since the last record.
+    samples: Vec<(f64, Vec<CounterReading>)>,
+}
+
+impl Timeline {
+    /// Creates a timeline from the environment variable `QPE_TIMELINE`.
+    ///
+    /// The variable contains the path of the CSV file.
+    /// The counters are configured like the measured counters, e.g. using `QPE_EVENTS`.
+    /// The sampling interval may be set in milliseconds using `QPE_TIMELINE_INTERVAL`.
+    /// Returns `None` if `QPE_TIMELINE` is not set or the file cannot be created.
+    pub fn from_env() -> Option<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        let path = config.get("TIMELINE")?;
+        let timeline = Self::new(PerfBackend::from_config(config), &path)
+            .map_err(|e| eprintln!("failed to create {path}: {e}"))
+            .ok()?;
+        Some(match config.parse("TIMELINE_INTERVAL") {
+            Some(ms) => timeline.with_interval(Duration::from_millis(ms)),
+            None => timeline,
+        })
+    }
+
+    /// Creates a timeline sampling `perf`, written to `path`.
+    pub fn new(perf: PerfBackend, path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path.as_ref())?);
+        write!(out, "row,labels,time")?;
+        perf.names(&mut |name| {
+            let _ = write!(out, ",{name}");
+        });
+        writeln!(out)?;
+        let state = Arc::new(Mutex::new(State {
+            perf,
+            interval: Duration::from_millis(10),
+            start: Instant::now(),
+            enabled: false,
+            samples: Vec::new(),
+        }));
+        let weak = Arc::downgrade(&state);
+        thread::spawn(move || sample_periodically(weak));
+        Ok(Timeline {
+            state,
+            out,
+            path: path.as_ref().to_path_buf(),
+            row: 0,
+            error_printed: false,
+        })
+    }
+
+    /// Set the time between samples, the default is 10ms.
+    pub fn with_interval(self, interval: Duration) -> Self {
+        self.state.lock().unwrap().interval = interval;
+        self
+    }
+
+    /// Resets the counters at the start of a measurement.
+    pub(crate) fn begin(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.perf.reset();
+        state.start = Instant::now();
+        state.perf.enable();
+        state.enabled = true;
+    }
+
+    pub(crate) fn enable(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.perf.enable();
+        state.enabled = true;
+    }
+
+    pub(crate) fn disable(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.perf.disable();
+        if state.enabled {
+            state.sample();
+        }
+        state.enabled = false;
+    }
+
+    /// Writes the samples taken since the last record with a label.
+    pub(crate) fn record(&mut self, labels: String) {
+        let samples = std::mem::take(&mut self.state.lock().unwrap().samples);
+        let labels = format!("\"{}\"", labels.replace('"', "\"\""));
+        let result = samples.iter().try_for_each(|(time, readings)| {
+            write!(self.out, "{},{labels},{time}", self.row)?;
+            for reading in readings {
+                write!(self.out, ",{}", reading.value)?;
+            }
+            writeln!(self.out)
+        });
+        self.row += 1;
+        if let Err(e) = result
+            && !self.error_printed
+        {
+            self.error_printed = true;
+            eprintln!("failed to write {}: {e}", self.path.display());
+        }
+    }
+}
+
+impl Drop for Timeline {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.flush() {
+            eprintln!("failed to write {}: {e}", self.path.display());
+        }
+    }
+}
+
+impl State {
+    fn sample(&mut self) {
+        let mut readings = Vec::new();
+        self.perf.read(&mut readings);
+        self.samples
+            .push((self.start.elapsed().as_secs_f64(), readings));
+    }
+}
+
+fn sample_periodically(state: Weak<Mutex<State>>) {
+    let mut interval = Duration::ZERO;
+    loop {
+        thread::sleep(interval);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        if state.enabled {
+            state.sample();
+        }
+        interval = state.interval;
+    }
+}
@@ -0,0 +1,792 @@
+//! Processing stages between recording and formatting.
+//!
+//! A [`Transform`] modifies a [`Report`] before it is passed on to a [`Format`].
+//! Transforms are chained using [`then`](Transform::then), e.g. `Filter::new(f).then(Aggregate::Mean).then(Tabled::new())`.
+//! The resulting [`Pipeline`] can be used as the format of a [`QuickPerfEvent`](crate::QuickPerfEvent).
+
+use crate::{
+    Report, Row,
+    counters::{CounterMeta, CounterReading, Counters, Derived, Direction, Replay},
+    formats::Format,
+    labels::LabelMeta,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    mem,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A processing stage modifying the rows and columns of a report.
+pub trait Transform {
+    /// Modify a report.
+    fn apply(&mut self, report: &mut Report);
+
+    /// Returns true if applying the transform to each row separately gives the same result as applying it to all rows at once.
+    ///
+    /// Rows passing through only streaming transforms are forwarded as they are recorded.
+    /// Otherwise, they are buffered until the report is finished.
+    fn streaming(&self) -> bool {
+        true
+    }
+
+    /// Passes the output of this transform on to another transform or a format.
+    fn then<N>(self, next: N) -> Pipeline<Self, N>
+    where
+        Self: Sized,
+    {
+        Pipeline {
+            transform: self,
+            next,
+            buffer: Report::default(),
+        }
+    }
+}
+
+/// A transform followed by another transform or a format, see [`Transform::then`].
+pub struct Pipeline<T, N> {
+    transform: T,
+    next: N,
+    buffer: Report,
+}
+
+impl<T: Transform, N: Transform> Transform for Pipeline<T, N> {
+    fn apply(&mut self, report: &mut Report) {
+        self.transform.apply(report);
+        self.next.apply(report);
+    }
+
+    fn streaming(&self) -> bool {
+        self.transform.streaming() && self.next.streaming()
+    }
+}
+
+impl<T: Transform, N: Format> Format for Pipeline<T, N> {
+    fn push(
+        &mut self,
+        scale: usize,
+        start_time: SystemTime,
+        counters: &mut dyn Counters,
+        labels: &mut dyn FnMut(&mut dyn FnMut(&str)),
+        label_meta: &'static [LabelMeta],
+    ) -> Result<(), Box<dyn Error>> {
+        self.buffer
+            .push(scale, start_time, counters, labels, label_meta)?;
+        if self.transform.streaming() {
+            self.transform.apply(&mut self.buffer);
+            self.buffer.push_rows(&mut self.next)?;
+            self.buffer.rows.clear();
+        }
+        Ok(())
+    }
+
+    fn dump_and_reset(
+        &mut self,
+        label_meta: &'static [LabelMeta],
+        counters: &mut dyn Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.transform.streaming() && !self.buffer.rows.is_empty() {
+            self.transform.apply(&mut self.buffer);
+            self.buffer.push_rows(&mut self.next)?;
+            self.buffer.rows.clear();
+        }
+        if self.buffer.counter_names.is_empty() {
+            self.next.dump_and_reset(label_meta, counters)
+        } else {
+            let mut counters = self.buffer.counters(Vec::new());
+            self.next
+                .dump_and_reset(self.buffer.label_meta, &mut counters)
+        }
+    }
+}
+
+/// Keeps only rows matching a predicate.
+pub struct Filter<P>(P);
+
+impl<P: FnMut(&Row) -> bool> Filter<P> {
+    pub fn new(predicate: P) -> Self {
+        Filter(predicate)
+    }
+}
+
+impl<P: FnMut(&Row) -> bool> Transform for Filter<P> {
+    fn apply(&mut self, report: &mut Report) {
+        report.rows.retain(&mut self.0);
+    }
+}
+
+/// Combines rows with identical labels into a single row.
+///
+/// Rows are ordered by the first occurrence of their labels.
+/// The number of combined rows is reported in an additional `runs` column.
+/// Counter values are combined after normalization, the combined row has the scale of the first row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    /// Report the mean of each counter.
+    Mean,
+    /// Report the sum of each counter.
+    Sum,
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Aggregate::Mean),
+            "sum" => Ok(Aggregate::Sum),
+            _ => Err(format!("invalid aggregate {s:?}: expected mean or sum")),
+        }
+    }
+}
+
+impl Transform for Aggregate {
+    fn apply(&mut self, report: &mut Report) {
+        let mut groups: Vec<(Row, usize)> = Vec::new();
+        for row in mem::take(&mut report.rows) {
+            let Some((group, runs)) = groups.iter_mut().find(|x| x.0.labels == row.labels) else {
+                groups.push((row, 1));
+                continue;
+            };
+            *runs += 1;
+            for (sum, reading) in group.readings.iter_mut().zip(&row.readings) {
+                let scale = if reading.enable_scale {
+                    group.scale as f64 / row.scale as f64
+                } else {
+                    1.0
+                };
+                sum.value += reading.value * scale;
+                sum.multiplexed |= reading.multiplexed;
+                sum.running = sum.running.min(reading.running);
+                sum.valid &= reading.valid;
+            }
+        }
+        for (mut row, runs) in groups {
+            if *self == Aggregate::Mean {
+                for reading in &mut row.readings {
+                    reading.value /= runs as f64;
+                }
+            }
+            row.readings.push(CounterReading {
+                value: runs as f64,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: true,
+            });
+            report.rows.push(row);
+        }
+        report.counter_names.push("runs".to_string());
+        report
+            .counter_meta
+            .push(CounterMeta::new().with_direction(Direction::Neutral));
+    }
+
+    fn streaming(&self) -> bool {
+        false
+    }
+}
+
+/// Appends columns computed from arithmetic expressions over other columns.
+///
+/// This works like [`Derived`], but on recorded rows instead of counters.
+pub struct DeriveColumns {
+    columns: Vec<(String, String, CounterMeta)>,
+    /// The input column names and the counters computing the output, reused while the input columns stay the same.
+    derived: Option<(Vec<String>, Derived<Replay>)>,
+}
+
+impl Default for DeriveColumns {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeriveColumns {
+    pub fn new() -> Self {
+        DeriveColumns {
+            columns: Vec::new(),
+            derived: None,
+        }
+    }
+
+    /// Adds a column named `name` computing an arithmetic expression, see [`Derived::with_expr`].
+    pub fn with_expr(mut self, name: &str, expr: &str, meta: CounterMeta) -> Self {
+        self.columns
+            .push((name.to_string(), expr.to_string(), meta));
+        self.derived = None;
+        self
+    }
+}
+
+impl Transform for DeriveColumns {
+    fn apply(&mut self, report: &mut Report) {
+        if self
+            .derived
+            .as_ref()
+            .is_none_or(|x| x.0 != report.counter_names)
+        {
+            let mut derived = Derived::new(report.counters(Vec::new()));
+            for (name, expr, meta) in &self.columns {
+                derived = derived.with_expr(name, expr, *meta);
+            }
+            self.derived = Some((report.counter_names.clone(), derived));
+        }
+        let derived = &mut self.derived.as_mut().unwrap().1;
+        for row in &mut report.rows {
+            derived
+                .inner_mut()
+                .set_readings(mem::take(&mut row.readings));
+            derived.read(&mut row.readings);
+        }
+        report.counter_names.clear();
+        derived.names(&mut |x| report.counter_names.push(x.to_string()));
+        report.counter_meta.clear();
+        derived.meta(&mut |x| report.counter_meta.push(x));
+    }
+}
+
+/// Appends a column `rep` counting previous rows with identical labels.
+///
+/// The first row of each label is numbered 0.
+/// This allows excluding or studying the first execution of each configuration, which is often affected by warm-up effects such as page faults or branch predictor training.
+#[derive(Default)]
+pub struct RepIndex {
+    counts: HashMap<Vec<String>, usize>,
+}
+
+impl RepIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Transform for RepIndex {
+    fn apply(&mut self, report: &mut Report) {
+        for row in &mut report.rows {
+            let count = self.counts.entry(row.labels.clone()).or_default();
+            row.readings.push(CounterReading {
+                value: *count as f64,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: true,
+            });
+            *count += 1;
+        }
+        report.counter_names.push("rep".to_string());
+        report
+            .counter_meta
+            .push(CounterMeta::new().with_direction(Direction::Neutral));
+    }
+}
+
+/// Appends a column with the scale processed per second.
+///
+/// The column is named after the [scale unit](crate::QuickPerfEvent::with_scale_unit), e.g. `request/s`, or `ops/s` if there is none.
+/// For the units `byte` and `bytes`, the column is `GiB/s` instead.
+/// The duration is taken from the `time` column, or the `t-clock` column if `time` is not recorded, including their per-unit variants such as `time/byte`.
+/// If neither is recorded, the transform has no effect.
+/// For a default instance, this is enabled by setting `QPE_THROUGHPUT` to `1`.
+#[derive(Default)]
+pub struct Throughput;
+
+impl Transform for Throughput {
+    fn apply(&mut self, report: &mut Report) {
+        let unit = report.scale_unit.clone();
+        // counters divided by the scale are renamed when a scale unit is set
+        let per_unit = |name: &str| match &unit {
+            Some(unit) => format!("{name}/{unit}"),
+            None => name.to_string(),
+        };
+        let Some(i) = (report.counter_index("time"))
+            .or_else(|| report.counter_index(&per_unit("time")))
+            .or_else(|| report.counter_index(&per_unit("t-clock")))
+            .or_else(|| report.counter_index("t-clock"))
+        else {
+            return;
+        };
+        let (name, factor) = match unit.as_deref() {
+            Some("byte" | "bytes") => ("GiB/s".to_string(), 1.0 / (1u64 << 30) as f64),
+            Some(unit) => (format!("{unit}/s"), 1.0),
+            None => ("ops/s".to_string(), 1.0),
+        };
+        for row in &mut report.rows {
+            let time = &row.readings[i];
+            row.readings.push(CounterReading {
+                value: row.scale as f64 / time.value * factor,
+                multiplexed: time.multiplexed,
+                running: time.running,
+                enable_scale: false,
+                valid: time.valid,
+            });
+        }
+        report.counter_names.push(name);
+        report
+            .counter_meta
+            .push(CounterMeta::new().with_direction(Direction::HigherIsBetter));
+    }
+}
+
+/// Prepends label columns with the same value in every row, e.g. the machine a benchmark ran on.
+///
+/// This allows merging results from multiple machines or branches without adding fields to the [`Labels`](crate::Labels) type.
+/// For a default instance, labels are added from the environment variable `QPE_LABELS`, e.g. `machine=$HOSTNAME,branch=main`.
+/// The label metadata including the added labels is leaked to obtain a `'static` lifetime, once per distinct input metadata.
+#[derive(Default)]
+pub struct ExtraLabels {
+    labels: Vec<(&'static str, String)>,
+    /// The label metadata of the input and of the output, reused while the input metadata stays the same.
+    meta: Option<(&'static [LabelMeta], &'static [LabelMeta])>,
+}
+
+impl ExtraLabels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a label named `name` with the value `value`.
+    pub fn with_label(mut self, name: &str, value: &str) -> Self {
+        self.labels
+            .push((String::leak(name.to_string()), value.to_string()));
+        self.meta = None;
+        self
+    }
+}
+
+/// Parses a comma separated list of `name=value` pairs.
+///
+/// Values of the form `$NAME` are replaced by the environment variable `NAME`, so they can be used in configuration files.
+impl FromStr for ExtraLabels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels = ExtraLabels::new();
+        for def in s.split(",").filter(|x| !x.is_empty()) {
+            let (name, value) = def
+                .split_once("=")
+                .ok_or_else(|| format!("invalid label {def:?}: expected name=value"))?;
+            let value = match value.strip_prefix("$") {
+                Some(var) => std::env::var(var)
+                    .map_err(|_| format!("environment variable {var} is not set"))?,
+                None => value.to_string(),
+            };
+            labels = labels.with_label(name.trim(), &value);
+        }
+        Ok(labels)
+    }
+}
+
+impl Transform for ExtraLabels {
+    fn apply(&mut self, report: &mut Report) {
+        let input = report.label_meta;
+        if self.meta.is_none_or(|x| !std::ptr::eq(x.0, input)) {
+            let added = self.labels.iter().map(|(name, value)| {
+                LabelMeta::new(name).with_width(name.len().max(value.chars().count()))
+            });
+            let output: Vec<LabelMeta> = added.chain(input.iter().cloned()).collect();
+            self.meta = Some((input, output.leak()));
+        }
+        report.label_meta = self.meta.unwrap().1;
+        for row in &mut report.rows {
+            let values = self.labels.iter().map(|x| x.1.clone());
+            row.labels.splice(0..0, values);
+        }
+    }
+}
+
+/// Appends a label column `start` with the time each row was recorded.
+///
+/// CSV files include the start time of each row, but tables do not.
+/// This allows correlating rows of long-running sweeps with external monitoring.
+/// For a default instance, this is enabled by setting `QPE_TIMESTAMP` to `relative` or `wall`.
+pub struct Timestamp {
+    /// The time relative timestamps are measured from, or `None` for wall clock time.
+    origin: Option<SystemTime>,
+    /// The label metadata of the input and of the output, reused while the input metadata stays the same.
+    meta: Option<(&'static [LabelMeta], &'static [LabelMeta])>,
+}
+
+impl Timestamp {
+    /// Show the time since the transform was created, e.g. `0:01:05.250`.
+    ///
+    /// For a default instance, this is when the [`QuickPerfEvent`](crate::QuickPerfEvent) is created.
+    pub fn relative() -> Self {
+        Timestamp {
+            origin: Some(SystemTime::now()),
+            meta: None,
+        }
+    }
+
+    /// Show the wall clock time in UTC, e.g. `2026-01-31T14:03:07Z`.
+    pub fn wall_clock() -> Self {
+        Timestamp {
+            origin: None,
+            meta: None,
+        }
+    }
+
+    fn format(&self, time: SystemTime) -> String {
+        match self.origin {
+            Some(origin) => {
+                let elapsed = time
+                    .duration_since(origin)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                let minutes = (elapsed / 60.0) as u64;
+                let seconds = elapsed - minutes as f64 * 60.0;
+                format!("{}:{:02}:{seconds:06.3}", minutes / 60, minutes % 60)
+            }
+            None => {
+                let secs = time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let (year, month, day) = civil_from_days(secs / 86400);
+                let time = secs % 86400;
+                format!(
+                    "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+                    time / 3600,
+                    time / 60 % 60,
+                    time % 60
+                )
+            }
+        }
+    }
+}
+
+/// Converts days since the Unix epoch to a year, month, and day of the Gregorian calendar.
+///
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relative" => Ok(Timestamp::relative()),
+            "wall" => Ok(Timestamp::wall_clock()),
+            _ => Err(format!(
+                "invalid timestamp {s:?}: expected relative or wall"
+            )),
+        }
+    }
+}
+
+impl Transform for Timestamp {
+    fn apply(&mut self, report: &mut Report) {
+        let input = report.label_meta;
+        if self.meta.is_none_or(|x| !std::ptr::eq(x.0, input)) {
+            let width = if self.origin.is_some() { 11 } else { 20 };
+            let added = LabelMeta::new("start").with_width(width);
+            let output: Vec<LabelMeta> = input.iter().cloned().chain([added]).collect();
+            self.meta = Some((input, output.leak()));
+        }
+        report.label_meta = self.meta.unwrap().1;
+        for row in &mut report.rows {
+            row.labels.push(self.format(row.start_time));
+        }
+    }
+}
+
+/// Warns about rows whose instructions per scale differ drastically from earlier rows with identical labels.
+///
+/// The number of instructions executed per unit of work is usually stable across runs.
+/// A drastic change therefore often indicates a wrong `scale` argument, e.g. forgetting to multiply by the length of an inner loop.
+/// The first row of each label serves as reference.
+/// Rows are passed on unchanged.
+/// If the `instr` counter is not recorded, nothing is checked.
+pub struct ScaleCheck {
+    factor: f64,
+    /// Instructions per scale of the first row, by labels.
+    reference: HashMap<Vec<String>, f64>,
+}
+
+impl Default for ScaleCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScaleCheck {
+    pub fn new() -> Self {
+        ScaleCheck {
+            factor: 4.0,
+            reference: HashMap::new(),
+        }
+    }
+
+    /// Set the ratio between instructions per scale of a row and the reference that triggers a warning, the default is 4.
+    pub fn with_factor(mut self, factor: f64) -> Self {
+        self.factor = factor;
+        self
+    }
+}
+
+impl Transform for ScaleCheck {
+    fn apply(&mut self, report: &mut Report) {
+        let name = match &report.scale_unit {
+            Some(unit) => format!("instr/{unit}"),
+            None => "instr".to_string(),
+        };
+        let Some(instr) = report.counter_index(&name) else {
+            return;
+        };
+        for row in &report.rows {
+            let value = row.value(instr);
+            if !value.is_finite() || value <= 0.0 {
+                continue;
+            }
+            let reference = *self.reference.entry(row.labels.clone()).or_insert(value);
+            if value.max(reference) / value.min(reference) > self.factor {
+                eprintln!(
+                    "suspicious scale for {:?}: {value} instructions per scale, earlier {reference}. Is the scale of {} correct?",
+                    row.labels, row.scale
+                );
+            }
+        }
+    }
+}
+
+/// Compares counters against expected ranges attached to label values.
+///
+/// This appends a column `off-by`, the factor by which the furthest counter lies outside its expected range, or 1 if all counters of the row are in range.
+/// Rows without expectations get NaN.
+/// If a counter is off by more than the [factor](Self::with_factor), a warning is printed to stderr.
+/// This gives quick feedback on broken setups, e.g. when teaching, where an unexpected result usually means the measurement is wrong rather than the code being slow.
+pub struct Expectations {
+    expected: Vec<Expected>,
+    factor: f64,
+}
+
+struct Expected {
+    label: String,
+    counter: String,
+    range: RangeInclusive<f64>,
+}
+
+impl Default for Expectations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Expectations {
+    pub fn new() -> Self {
+        Expectations {
+            expected: Vec::new(),
+            factor: 10.0,
+        }
+    }
+
+    /// Expect the normalized value of `counter` to lie within `range` for rows with a label value equal to `label`.
+    ///
+    /// The counter is looked up by its name, or its name per [scale unit](Counters::scale_unit), e.g. `cycle` matches `cycle/op`.
+    /// For example, `with_expected("add", "cycle", 0.5..=2.0)` expects about one cycle per operation for rows labeled `add`.
+    pub fn with_expected(mut self, label: &str, counter: &str, range: RangeInclusive<f64>) -> Self {
+        self.expected.push(Expected {
+            label: label.to_string(),
+            counter: counter.to_string(),
+            range,
+        });
+        self
+    }
+
+    /// Set the factor by which a counter must be off to trigger a warning, the default is 10.
+    pub fn with_factor(mut self, factor: f64) -> Self {
+        self.factor = factor;
+        self
+    }
+}
+
+/// Returns the factor by which `value` lies outside `range`.
+fn off_by(value: f64, range: &RangeInclusive<f64>) -> f64 {
+    (value / range.end()).max(range.start() / value).max(1.0)
+}
+
+impl Transform for Expectations {
+    fn apply(&mut self, report: &mut Report) {
+        let indices: Vec<Option<usize>> = (self.expected.iter())
+            .map(|x| {
+                report.counter_index(&x.counter).or_else(|| {
+                    let unit = report.scale_unit.as_ref()?;
+                    report.counter_index(&format!("{}/{unit}", x.counter))
+                })
+            })
+            .collect();
+        for row in &mut report.rows {
+            let mut max = f64::NAN;
+            for (expected, index) in self.expected.iter().zip(&indices) {
+                let Some(index) = *index else {
+                    continue;
+                };
+                if !row.labels.contains(&expected.label) {
+                    continue;
+                }
+                let value = row.value(index);
+                let off = off_by(value, &expected.range);
+                if off > self.factor {
+                    eprintln!(
+                        "{:?} is off by {off:.1}x: {} is {value}, expected {}..={}. Check your setup.",
+                        row.labels,
+                        report.counter_names[index],
+                        expected.range.start(),
+                        expected.range.end(),
+                    );
+                }
+                max = max.max(off);
+            }
+            row.readings.push(CounterReading {
+                value: max,
+                multiplexed: false,
+                running: 1.0,
+                enable_scale: false,
+                valid: true,
+            });
+        }
+        report.counter_names.push("off-by".to_string());
+        report.counter_meta.push(CounterMeta::new());
+    }
+}
+
+#[test]
+fn test_aggregate() {
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let row = |label: &str, scale, value| Row {
+        labels: vec![label.to_string()],
+        scale,
+        start_time: SystemTime::UNIX_EPOCH,
+        readings: vec![reading(value)],
+    };
+    let mut report = Report {
+        label_meta: &[],
+        counter_names: vec!["x".to_string()],
+        counter_meta: vec![CounterMeta::new()],
+        scale_unit: None,
+        rows: vec![row("a", 1, 1.0), row("b", 1, 5.0), row("a", 2, 6.0)],
+    };
+    Aggregate::Mean
+        .then(DeriveColumns::new().with_expr("y", "x * 2", CounterMeta::new()))
+        .apply(&mut report);
+    assert_eq!(report.counter_names, ["x", "runs", "y"]);
+    let values: Vec<Vec<f64>> = report
+        .rows
+        .iter()
+        .map(|row| (0..3).map(|i| row.value(i)).collect())
+        .collect();
+    assert_eq!(values, [[2.0, 2.0, 4.0], [5.0, 1.0, 10.0]]);
+}
+
+#[test]
+fn test_expectations() {
+    let reading = |value| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale: true,
+        valid: true,
+    };
+    let row = |label: &str, value| Row {
+        labels: vec![label.to_string()],
+        scale: 2,
+        start_time: SystemTime::UNIX_EPOCH,
+        readings: vec![reading(value)],
+    };
+    let mut report = Report {
+        label_meta: &[],
+        counter_names: vec!["cycle/op".to_string()],
+        counter_meta: vec![CounterMeta::new()],
+        scale_unit: Some("op".to_string()),
+        rows: vec![row("add", 3.0), row("add", 400.0), row("div", 1.0)],
+    };
+    Expectations::new()
+        .with_expected("add", "cycle", 0.5..=2.0)
+        .apply(&mut report);
+    assert_eq!(report.counter_names, ["cycle/op", "off-by"]);
+    assert_eq!(report.rows[0].value(1), 1.0);
+    assert_eq!(report.rows[1].value(1), 100.0);
+    assert!(report.rows[2].value(1).is_nan());
+}
+
+#[test]
+fn test_extra_labels() {
+    use crate::{QuickPerfEvent, counters::TimeBackend};
+    let labels: ExtraLabels = "machine=a,branch=main".parse().unwrap();
+    let mut perf =
+        QuickPerfEvent::<str, _, _>::new(TimeBackend::new(), labels.then(Report::default()));
+    perf.run(|| ()).record(1, "x");
+    perf.run(|| ()).record(1, "y");
+    let report = &perf.format_mut().next;
+    let names: Vec<&str> = report.label_meta.iter().map(|x| x.name()).collect();
+    assert_eq!(names, ["machine", "branch", "label"]);
+    assert_eq!(report.rows[1].labels, ["a", "main", "y"]);
+}
+
+#[test]
+fn test_timestamp() {
+    use std::time::Duration;
+    let time = UNIX_EPOCH + Duration::from_millis(1_785_333_787_250);
+    assert_eq!(Timestamp::wall_clock().format(time), "2026-07-29T14:03:07Z");
+    let relative = Timestamp {
+        origin: Some(UNIX_EPOCH),
+        meta: None,
+    };
+    assert_eq!(
+        relative.format(UNIX_EPOCH + Duration::from_millis(3_665_250)),
+        "1:01:05.250"
+    );
+}
+
+#[test]
+fn test_throughput() {
+    let reading = |value, enable_scale| CounterReading {
+        value,
+        multiplexed: false,
+        running: 1.0,
+        enable_scale,
+        valid: true,
+    };
+    let mut report = Report {
+        label_meta: &[],
+        counter_names: vec!["cycle/byte".to_string(), "t-clock/byte".to_string()],
+        counter_meta: vec![CounterMeta::new(); 2],
+        scale_unit: Some("byte".to_string()),
+        rows: vec![Row {
+            labels: Vec::new(),
+            scale: 1 << 31,
+            start_time: SystemTime::UNIX_EPOCH,
+            readings: vec![reading(1e9, true), reading(0.5, true)],
+        }],
+    };
+    Throughput.apply(&mut report);
+    assert_eq!(report.counter_names[2], "GiB/s");
+    assert_eq!(report.rows[0].value(2), 4.0);
+    // wall-clock time is preferred over CPU time
+    report.counter_names.truncate(2);
+    report.counter_meta.truncate(2);
+    report.counter_names[0] = "time/byte".to_string();
+    report.rows[0].readings.truncate(2);
+    report.rows[0].readings[0] = reading(0.25, true);
+    Throughput.apply(&mut report);
+    assert_eq!(report.rows[0].value(2), 8.0);
+}